@@ -0,0 +1,67 @@
+//! The cluster access the commands need, so they run against an RPC endpoint as well as
+//! against a `solana-program-test` bank in tests.
+
+use std::error::Error;
+
+use interface::PreflightAccounts;
+use interface_client::{accounts::InterfaceAccounts, preflight::preflight_rpc};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    account::Account,
+    hash::Hash,
+    instruction::AccountMeta,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::Transaction,
+};
+
+pub trait Cluster {
+    /// `None` when the account doesn't exist
+    fn get_account(&self, key: &Pubkey) -> Result<Option<Account>, Box<dyn Error>>;
+
+    /// Simulates `perm_program`'s preflight for `accounts`, with `payer` paying the fee
+    fn preflight<A: InterfaceAccounts>(
+        &self,
+        payer: &Keypair,
+        perm_program: &Pubkey,
+        accounts: &A,
+        extra_accounts: &[AccountMeta],
+    ) -> Result<PreflightAccounts, Box<dyn Error>>;
+
+    fn latest_blockhash(&self) -> Result<Hash, Box<dyn Error>>;
+
+    /// Sends a signed transaction and waits until it's confirmed
+    fn send(&self, tx: &Transaction) -> Result<Signature, Box<dyn Error>>;
+}
+
+impl Cluster for RpcClient {
+    fn get_account(&self, key: &Pubkey) -> Result<Option<Account>, Box<dyn Error>> {
+        Ok(self
+            .get_account_with_commitment(key, self.commitment())?
+            .value)
+    }
+
+    fn preflight<A: InterfaceAccounts>(
+        &self,
+        payer: &Keypair,
+        perm_program: &Pubkey,
+        accounts: &A,
+        extra_accounts: &[AccountMeta],
+    ) -> Result<PreflightAccounts, Box<dyn Error>> {
+        Ok(preflight_rpc(
+            self,
+            &payer.pubkey(),
+            perm_program,
+            accounts,
+            extra_accounts,
+        )?)
+    }
+
+    fn latest_blockhash(&self) -> Result<Hash, Box<dyn Error>> {
+        Ok(self.get_latest_blockhash()?)
+    }
+
+    fn send(&self, tx: &Transaction) -> Result<Signature, Box<dyn Error>> {
+        Ok(self.send_and_confirm_transaction(tx)?)
+    }
+}
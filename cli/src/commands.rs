@@ -0,0 +1,333 @@
+use std::{error::Error, fmt};
+
+use interface_client::{
+    accounts::{InterfaceAccounts, LockAccounts, UnlockAccounts},
+    caller::{self, TransferAccounts},
+    preflight::remaining_accounts,
+    records::{recorded_tokens, RecordProgram, RecordedToken, TokenRecordState},
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction},
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::Transaction,
+};
+use spl_token::instruction::AuthorityType;
+
+use crate::cluster::Cluster;
+
+/// Seed of the PDA permission programs freeze and thaw with
+const PROGRAM_CONTROL_SEED: &[u8] = b"static";
+const TOKEN_RECORD_PREFIX: &[u8] = b"token_record";
+
+pub struct Config<C = RpcClient> {
+    pub cluster: C,
+    pub payer: Keypair,
+}
+
+pub fn lock<C: Cluster>(
+    config: &Config<C>,
+    token: &Pubkey,
+    perm_program: &Pubkey,
+    delegate: Option<&Keypair>,
+    nonce: Option<u64>,
+    expect_frozen: bool,
+    extra_accounts: &[Pubkey],
+) -> Result<Signature, Box<dyn Error>> {
+    let delegate = delegate.unwrap_or(&config.payer);
+    let (token_program, state) = token_account(config, token)?;
+    let accounts = LockAccounts {
+        token: *token,
+        mint: state.mint,
+        delegate: delegate.pubkey(),
+        payer: config.payer.pubkey(),
+        token_program,
+    };
+    let extra_accounts = readonly_metas(extra_accounts);
+    let remaining_accounts = resolve(config, perm_program, &accounts, &extra_accounts)?;
+    let ix = caller::lock(
+        &accounts,
+        perm_program,
+        remaining_accounts,
+        nonce,
+        false,
+        expect_frozen,
+    );
+    send(config, &[ix], &[delegate])
+}
+
+pub fn unlock<C: Cluster>(
+    config: &Config<C>,
+    token: &Pubkey,
+    perm_program: &Pubkey,
+    delegate: Option<&Keypair>,
+    nonce: Option<u64>,
+    extra_accounts: &[Pubkey],
+) -> Result<Signature, Box<dyn Error>> {
+    let delegate = delegate.unwrap_or(&config.payer);
+    let (token_program, state) = token_account(config, token)?;
+    let accounts = UnlockAccounts {
+        token: *token,
+        mint: state.mint,
+        delegate: delegate.pubkey(),
+        token_program,
+    };
+    let extra_accounts = readonly_metas(extra_accounts);
+    let remaining_accounts = resolve(config, perm_program, &accounts, &extra_accounts)?;
+    let ix = caller::unlock(
+        &accounts,
+        perm_program,
+        &config.payer.pubkey(),
+        remaining_accounts,
+        nonce,
+        false,
+    );
+    send(config, &[ix], &[delegate])
+}
+
+pub fn transfer<C: Cluster>(
+    config: &Config<C>,
+    source: &Pubkey,
+    dest: &Pubkey,
+    amount: u64,
+    perm_program: &Pubkey,
+    delegate: Option<&Keypair>,
+    expect_frozen: bool,
+) -> Result<Signature, Box<dyn Error>> {
+    let delegate = delegate.unwrap_or(&config.payer);
+    let (token_program, source_state) = token_account(config, source)?;
+    let (_, dest_state) = token_account(config, dest)?;
+    let accounts = TransferAccounts {
+        source: *source,
+        dest: *dest,
+        mint: source_state.mint,
+        delegate: delegate.pubkey(),
+        payer: config.payer.pubkey(),
+        token_program,
+    };
+
+    let unlock_accounts = resolve(config, perm_program, &accounts.unlock_source(), &[])?;
+    // same choice `caller::tfer` makes between `lock` and `lock_on_receive`
+    let delegate_key = delegate.pubkey();
+    let lock_accounts =
+        if dest_state.owner == delegate_key || dest_state.delegate == COption::Some(delegate_key) {
+            resolve(config, perm_program, &accounts.lock_dest(), &[])?
+        } else {
+            resolve(config, perm_program, &accounts.lock_dest_on_receive(), &[])?
+        };
+    // `caller::tfer` skips the relock once the transfer used up an SPL delegate's allowance
+    let keeps_authority = source_state.owner == delegate_key
+        || (source_state.delegate == COption::Some(delegate_key) && source_state.delegated_amount > amount);
+    let relock_accounts = if amount < source_state.amount && keeps_authority {
+        resolve(config, perm_program, &accounts.relock_source(), &[])?
+    } else {
+        vec![]
+    };
+
+    let ix = caller::tfer(
+        &accounts,
+        perm_program,
+        amount,
+        [unlock_accounts, lock_accounts, relock_accounts],
+        false,
+        expect_frozen,
+    )?;
+    send(config, &[ix], &[delegate])
+}
+
+/// A token account and the record its permission program keeps of it, if any
+pub struct TokenStatus {
+    pub token: Pubkey,
+    pub state: spl_token::state::Account,
+    pub token_record: Pubkey,
+    /// Size of the token record, `None` when the permission program has no record of the token
+    pub token_record_len: Option<usize>,
+    /// The decoded token record, `None` when there is none or its layout isn't known
+    pub record: Option<TokenRecordState>,
+}
+
+impl fmt::Display for TokenStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Token:        {}", self.token)?;
+        writeln!(f, "Mint:         {}", self.state.mint)?;
+        writeln!(f, "Owner:        {}", self.state.owner)?;
+        writeln!(f, "Amount:       {}", self.state.amount)?;
+        writeln!(f, "Frozen:       {}", self.state.is_frozen())?;
+        match self.state.delegate {
+            COption::Some(delegate) => writeln!(
+                f,
+                "Delegate:     {} ({})",
+                delegate, self.state.delegated_amount
+            )?,
+            COption::None => writeln!(f, "Delegate:     none")?,
+        }
+        match self.token_record_len {
+            Some(len) => writeln!(f, "Token record: {} ({} bytes)", self.token_record, len)?,
+            None => writeln!(f, "Token record: {} (not created)", self.token_record)?,
+        }
+        if let Some(record) = &self.record {
+            writeln!(f, "Locked:       {}", record.is_locked())?;
+            let lockers = record.lockers();
+            if lockers.is_empty() {
+                writeln!(f, "Lockers:      none")?;
+            } else {
+                let lockers: Vec<String> = lockers.iter().map(Pubkey::to_string).collect();
+                writeln!(f, "Lockers:      {}", lockers.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn status<C: Cluster>(
+    config: &Config<C>,
+    token: &Pubkey,
+    perm_program: &Pubkey,
+) -> Result<TokenStatus, Box<dyn Error>> {
+    let (_, state) = token_account(config, token)?;
+    let token_record =
+        Pubkey::find_program_address(&[token.as_ref(), TOKEN_RECORD_PREFIX], perm_program).0;
+    let account = config.cluster.get_account(&token_record)?;
+    let token_record_len = account.as_ref().map(|account| account.data.len());
+    // other permission programs' records are shown by size only
+    let record = match (RecordProgram::from_program_id(perm_program), account) {
+        (Some(program), Some(account)) => Some(program.decode(&account.data)?),
+        _ => None,
+    };
+    Ok(TokenStatus {
+        token: *token,
+        state,
+        token_record,
+        token_record_len,
+        record,
+    })
+}
+
+/// Token accounts of `mint` the permission program keeps a record of, only locked ones with
+/// `locked_only`
+///
+/// See [`recorded_tokens`] for what this fetches.
+pub fn records(
+    config: &Config<RpcClient>,
+    mint: &Pubkey,
+    perm_program: &Pubkey,
+    locked_only: bool,
+) -> Result<Vec<RecordedToken>, Box<dyn Error>> {
+    let program = RecordProgram::from_program_id(perm_program).ok_or_else(|| {
+        format!(
+            "{} is neither permissioned_token_wrapper nor twice_permissioned",
+            perm_program
+        )
+    })?;
+    Ok(recorded_tokens(&config.cluster, program, mint, locked_only)?)
+}
+
+/// Sets the mint's freeze authority to the permission program's control PDA.
+/// Returns `None` when the permission program already holds it.
+pub fn onboard_mint<C: Cluster>(
+    config: &Config<C>,
+    mint: &Pubkey,
+    perm_program: &Pubkey,
+    freeze_authority: Option<&Keypair>,
+) -> Result<Option<Signature>, Box<dyn Error>> {
+    let freeze_authority = freeze_authority.unwrap_or(&config.payer);
+    let account = get_account(config, mint)?;
+    if account.owner != spl_token::id() {
+        return Err(format!("{} is not an spl-token mint", mint).into());
+    }
+    let state = spl_token::state::Mint::unpack(&account.data)?;
+    let program_control = Pubkey::find_program_address(&[PROGRAM_CONTROL_SEED], perm_program).0;
+    match state.freeze_authority {
+        COption::Some(current) if current == program_control => return Ok(None),
+        COption::Some(current) if current == freeze_authority.pubkey() => {}
+        COption::Some(current) => {
+            return Err(format!(
+                "freeze authority of {} is {}, not {}",
+                mint,
+                current,
+                freeze_authority.pubkey()
+            )
+            .into())
+        }
+        // a mint without freeze authority can never get one
+        COption::None => return Err(format!("{} has no freeze authority", mint).into()),
+    }
+
+    let ix = spl_token::instruction::set_authority(
+        &spl_token::id(),
+        mint,
+        Some(&program_control),
+        AuthorityType::FreezeAccount,
+        &freeze_authority.pubkey(),
+        &[],
+    )?;
+    send(config, &[ix], &[freeze_authority]).map(Some)
+}
+
+/// Token program owning `token` and its state. Token-2022 accounts are read up to their
+/// base state, extensions are ignored.
+fn token_account<C: Cluster>(
+    config: &Config<C>,
+    token: &Pubkey,
+) -> Result<(Pubkey, spl_token::state::Account), Box<dyn Error>> {
+    let account = get_account(config, token)?;
+    let base = account
+        .data
+        .get(..spl_token::state::Account::LEN)
+        .ok_or_else(|| format!("{} is not a token account", token))?;
+    Ok((account.owner, spl_token::state::Account::unpack(base)?))
+}
+
+fn get_account<C: Cluster>(config: &Config<C>, key: &Pubkey) -> Result<Account, Box<dyn Error>> {
+    config
+        .cluster
+        .get_account(key)?
+        .ok_or_else(|| format!("{} not found", key).into())
+}
+
+fn readonly_metas(keys: &[Pubkey]) -> Vec<AccountMeta> {
+    keys.iter()
+        .map(|key| AccountMeta::new_readonly(*key, false))
+        .collect()
+}
+
+fn resolve<C: Cluster, A: InterfaceAccounts>(
+    config: &Config<C>,
+    perm_program: &Pubkey,
+    accounts: &A,
+    extra_accounts: &[AccountMeta],
+) -> Result<Vec<AccountMeta>, Box<dyn Error>> {
+    let preflight = config
+        .cluster
+        .preflight(&config.payer, perm_program, accounts, extra_accounts)?;
+    Ok(remaining_accounts(extra_accounts, &preflight))
+}
+
+/// Signs with the payer and `signers`, each key once
+fn send<C: Cluster>(
+    config: &Config<C>,
+    ixs: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<Signature, Box<dyn Error>> {
+    let mut all_signers: Vec<&Keypair> = vec![&config.payer];
+    for signer in signers {
+        if all_signers
+            .iter()
+            .all(|known| known.pubkey() != signer.pubkey())
+        {
+            all_signers.push(signer);
+        }
+    }
+    let blockhash = config.cluster.latest_blockhash()?;
+    let tx = Transaction::new_signed_with_payer(
+        ixs,
+        Some(&config.payer.pubkey()),
+        &all_signers,
+        blockhash,
+    );
+    config.cluster.send(&tx)
+}
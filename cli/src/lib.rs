@@ -0,0 +1,4 @@
+//! Commands of `perm-cli`, run against any [`cluster::Cluster`]
+
+pub mod cluster;
+pub mod commands;
@@ -0,0 +1,253 @@
+//! `perm-cli`: locks, unlocks and transfers permissioned tokens through the caller program,
+//! resolving each permission program's extra accounts with its preflight.
+
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    process,
+};
+
+use clap::{Parser, Subcommand};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair},
+};
+
+use perm_cli::commands::{self, Config};
+
+#[derive(Parser)]
+#[clap(name = "perm-cli", version, about)]
+struct Cli {
+    /// RPC URL of the cluster
+    #[clap(
+        long,
+        short = 'u',
+        global = true,
+        default_value = "http://localhost:8899"
+    )]
+    url: String,
+    /// Keypair file of the fee payer, also the default delegate [default: ~/.config/solana/id.json]
+    #[clap(long, short = 'k', global = true)]
+    keypair: Option<PathBuf>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Lock a token account through the caller program
+    Lock {
+        #[clap(long)]
+        token: Pubkey,
+        #[clap(long)]
+        perm_program: Pubkey,
+        /// Keypair file of the delegate locking the token [default: the fee payer]
+        #[clap(long)]
+        delegate: Option<PathBuf>,
+        /// Idempotency nonce, for permission programs that take one
+        #[clap(long)]
+        nonce: Option<u64>,
+        /// Fail unless the permission program freezes the token
+        #[clap(long)]
+        expect_frozen: bool,
+        /// Account passed to preflight after the base accounts, e.g. the program a middleware wraps
+        #[clap(long = "extra-account")]
+        extra_accounts: Vec<Pubkey>,
+    },
+    /// Unlock a token account through the caller program
+    Unlock {
+        #[clap(long)]
+        token: Pubkey,
+        #[clap(long)]
+        perm_program: Pubkey,
+        /// Keypair file of the delegate that locked the token [default: the fee payer]
+        #[clap(long)]
+        delegate: Option<PathBuf>,
+        /// Idempotency nonce, for permission programs that take one
+        #[clap(long)]
+        nonce: Option<u64>,
+        /// Account passed to preflight after the base accounts, e.g. the program a middleware wraps
+        #[clap(long = "extra-account")]
+        extra_accounts: Vec<Pubkey>,
+    },
+    /// Unlock, transfer and relock a locked token through the caller program
+    Transfer {
+        #[clap(long)]
+        source: Pubkey,
+        #[clap(long)]
+        dest: Pubkey,
+        #[clap(long)]
+        amount: u64,
+        #[clap(long)]
+        perm_program: Pubkey,
+        /// Keypair file of the delegate of `source` [default: the fee payer]
+        #[clap(long)]
+        delegate: Option<PathBuf>,
+        /// Fail unless the permission program freezes `dest` and a relocked `source`
+        #[clap(long)]
+        expect_frozen: bool,
+    },
+    /// Show the state of a token account and whether the permission program keeps a record of it
+    Status {
+        #[clap(long)]
+        token: Pubkey,
+        #[clap(long)]
+        perm_program: Pubkey,
+    },
+    /// List the token accounts of a mint the permission program keeps a record of
+    ///
+    /// Fetches every token record of the permission program and every token account of the
+    /// mint, which takes a while on large programs and mints.
+    Records {
+        #[clap(long)]
+        mint: Pubkey,
+        #[clap(long)]
+        perm_program: Pubkey,
+        /// Only list tokens the permission program currently holds locked
+        #[clap(long)]
+        locked_only: bool,
+    },
+    /// Hand a mint's freeze authority to a permission program
+    OnboardMint {
+        #[clap(long)]
+        mint: Pubkey,
+        #[clap(long)]
+        perm_program: Pubkey,
+        /// Keypair file of the current freeze authority [default: the fee payer]
+        #[clap(long)]
+        freeze_authority: Option<PathBuf>,
+    },
+}
+
+fn main() {
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let keypair = match cli.keypair {
+        Some(path) => path,
+        None => default_keypair_path()?,
+    };
+    let config = Config {
+        cluster: RpcClient::new_with_commitment(cli.url, CommitmentConfig::confirmed()),
+        payer: read_keypair(&keypair)?,
+    };
+
+    match cli.command {
+        Command::Lock {
+            token,
+            perm_program,
+            delegate,
+            nonce,
+            expect_frozen,
+            extra_accounts,
+        } => {
+            let delegate = delegate.map(|path| read_keypair(&path)).transpose()?;
+            let signature = commands::lock(
+                &config,
+                &token,
+                &perm_program,
+                delegate.as_ref(),
+                nonce,
+                expect_frozen,
+                &extra_accounts,
+            )?;
+            println!("Locked {}: {}", token, signature);
+        }
+        Command::Unlock {
+            token,
+            perm_program,
+            delegate,
+            nonce,
+            extra_accounts,
+        } => {
+            let delegate = delegate.map(|path| read_keypair(&path)).transpose()?;
+            let signature = commands::unlock(
+                &config,
+                &token,
+                &perm_program,
+                delegate.as_ref(),
+                nonce,
+                &extra_accounts,
+            )?;
+            println!("Unlocked {}: {}", token, signature);
+        }
+        Command::Transfer {
+            source,
+            dest,
+            amount,
+            perm_program,
+            delegate,
+            expect_frozen,
+        } => {
+            let delegate = delegate.map(|path| read_keypair(&path)).transpose()?;
+            let signature = commands::transfer(
+                &config,
+                &source,
+                &dest,
+                amount,
+                &perm_program,
+                delegate.as_ref(),
+                expect_frozen,
+            )?;
+            println!(
+                "Transferred {} from {} to {}: {}",
+                amount, source, dest, signature
+            );
+        }
+        Command::Status {
+            token,
+            perm_program,
+        } => {
+            print!("{}", commands::status(&config, &token, &perm_program)?);
+        }
+        Command::Records {
+            mint,
+            perm_program,
+            locked_only,
+        } => {
+            let records = commands::records(&config, &mint, &perm_program, locked_only)?;
+            for recorded in records.iter() {
+                println!(
+                    "{} owner {} amount {} frozen {} locked {} lockers {}",
+                    recorded.token,
+                    recorded.owner,
+                    recorded.amount,
+                    recorded.is_frozen,
+                    recorded.record.is_locked(),
+                    recorded.record.lockers().len()
+                );
+            }
+            println!("{} token records", records.len());
+        }
+        Command::OnboardMint {
+            mint,
+            perm_program,
+            freeze_authority,
+        } => {
+            let freeze_authority = freeze_authority
+                .map(|path| read_keypair(&path))
+                .transpose()?;
+            match commands::onboard_mint(&config, &mint, &perm_program, freeze_authority.as_ref())?
+            {
+                Some(signature) => println!("Onboarded {}: {}", mint, signature),
+                None => println!("{} is already onboarded", mint),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn default_keypair_path() -> Result<PathBuf, Box<dyn Error>> {
+    let home = std::env::var("HOME").map_err(|_| "HOME is not set, pass --keypair")?;
+    Ok(PathBuf::from(home).join(".config/solana/id.json"))
+}
+
+fn read_keypair(path: &Path) -> Result<Keypair, Box<dyn Error>> {
+    read_keypair_file(path).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
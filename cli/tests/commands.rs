@@ -0,0 +1,196 @@
+use std::error::Error;
+
+use interface::PreflightAccounts;
+use interface_client::{accounts::InterfaceAccounts, preflight::preflight_banks};
+use perm_cli::{
+    cluster::Cluster,
+    commands::{self, Config},
+};
+use solana_program_test::{ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    hash::Hash,
+    instruction::AccountMeta,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::Transaction,
+};
+use test_fixtures::{
+    add_program, clone_keypair, create_mint, create_token_account, program_control, token_account,
+};
+use tokio::runtime::Runtime;
+
+/// A `solana-program-test` bank, driven by the runtime it was started on
+struct Bank {
+    runtime: Runtime,
+    context: ProgramTestContext,
+}
+
+impl Cluster for Bank {
+    fn get_account(&self, key: &Pubkey) -> Result<Option<Account>, Box<dyn Error>> {
+        let mut banks = self.context.banks_client.clone();
+        Ok(self.runtime.block_on(banks.get_account(*key))?)
+    }
+
+    fn preflight<A: InterfaceAccounts>(
+        &self,
+        payer: &Keypair,
+        perm_program: &Pubkey,
+        accounts: &A,
+        extra_accounts: &[AccountMeta],
+    ) -> Result<PreflightAccounts, Box<dyn Error>> {
+        let mut banks = self.context.banks_client.clone();
+        Ok(self.runtime.block_on(preflight_banks(
+            &mut banks,
+            payer,
+            perm_program,
+            accounts,
+            extra_accounts,
+        ))?)
+    }
+
+    fn latest_blockhash(&self) -> Result<Hash, Box<dyn Error>> {
+        let mut banks = self.context.banks_client.clone();
+        Ok(self.runtime.block_on(banks.get_latest_blockhash())?)
+    }
+
+    fn send(&self, tx: &Transaction) -> Result<Signature, Box<dyn Error>> {
+        let mut banks = self.context.banks_client.clone();
+        self.runtime
+            .block_on(banks.process_transaction(tx.clone()))?;
+        Ok(tx.signatures[0])
+    }
+}
+
+/// A token of a mint whose freeze authority is the payer, held by the payer, with the caller
+/// and permissioned_token_wrapper deployed
+struct Fixture {
+    config: Config<Bank>,
+    mint: Pubkey,
+    token: Pubkey,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let runtime = Runtime::new().unwrap();
+        let mut program_test = ProgramTest::default();
+        add_program(&mut program_test, caller::id(), "caller");
+        add_program(
+            &mut program_test,
+            permissioned_token_wrapper::id(),
+            "permissioned_token_wrapper",
+        );
+        let mut context = runtime.block_on(program_test.start_with_context());
+
+        let payer = context.payer.pubkey();
+        let (mint, token) = runtime.block_on(async {
+            let mint = create_mint(&mut context, 0, Some(&payer)).await;
+            let token = create_token_account(&mut context, &mint, &payer, 1).await;
+            (mint, token)
+        });
+        let payer = clone_keypair(&context.payer);
+        Self {
+            config: Config {
+                cluster: Bank { runtime, context },
+                payer,
+            },
+            mint,
+            token,
+        }
+    }
+
+    fn is_frozen(&mut self) -> bool {
+        let Bank { runtime, context } = &mut self.config.cluster;
+        runtime
+            .block_on(token_account(context, self.token))
+            .is_frozen()
+    }
+}
+
+#[test]
+fn onboard_mint_hands_over_the_freeze_authority_once() {
+    let fixture = Fixture::new();
+    let perm_program = permissioned_token_wrapper::id();
+
+    let signature =
+        commands::onboard_mint(&fixture.config, &fixture.mint, &perm_program, None).unwrap();
+    assert!(signature.is_some());
+    let mint = fixture
+        .config
+        .cluster
+        .get_account(&fixture.mint)
+        .unwrap()
+        .unwrap();
+    let mint = spl_token::state::Mint::unpack(&mint.data).unwrap();
+    assert_eq!(
+        mint.freeze_authority,
+        Some(program_control(&perm_program)).into()
+    );
+
+    let signature =
+        commands::onboard_mint(&fixture.config, &fixture.mint, &perm_program, None).unwrap();
+    assert!(signature.is_none());
+}
+
+#[test]
+fn onboard_mint_rejects_another_freeze_authority() {
+    let fixture = Fixture::new();
+    let other = Keypair::new();
+
+    let err = commands::onboard_mint(
+        &fixture.config,
+        &fixture.mint,
+        &permissioned_token_wrapper::id(),
+        Some(&other),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("freeze authority"));
+}
+
+#[test]
+fn lock_and_unlock_show_in_status() {
+    let mut fixture = Fixture::new();
+    let perm_program = permissioned_token_wrapper::id();
+    let payer = fixture.config.payer.pubkey();
+    commands::onboard_mint(&fixture.config, &fixture.mint, &perm_program, None).unwrap();
+
+    let status = commands::status(&fixture.config, &fixture.token, &perm_program).unwrap();
+    assert!(status.token_record_len.is_none());
+    assert!(status.record.is_none());
+
+    commands::lock(
+        &fixture.config,
+        &fixture.token,
+        &perm_program,
+        None,
+        None,
+        true,
+        &[],
+    )
+    .unwrap();
+    assert!(fixture.is_frozen());
+    let status = commands::status(&fixture.config, &fixture.token, &perm_program).unwrap();
+    let record = status.record.as_ref().unwrap();
+    assert!(record.is_locked());
+    assert_eq!(record.lockers(), &[payer]);
+    let shown = status.to_string();
+    assert!(shown.contains("Locked:       true"));
+    assert!(shown.contains(&format!("Lockers:      {}", payer)));
+
+    commands::unlock(
+        &fixture.config,
+        &fixture.token,
+        &perm_program,
+        None,
+        None,
+        &[],
+    )
+    .unwrap();
+    assert!(!fixture.is_frozen());
+    let status = commands::status(&fixture.config, &fixture.token, &perm_program).unwrap();
+    let record = status.record.as_ref().unwrap();
+    assert!(!record.is_locked());
+    assert!(record.lockers().is_empty());
+    assert!(status.to_string().contains("Lockers:      none"));
+}
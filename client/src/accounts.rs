@@ -0,0 +1,94 @@
+//! Base accounts of the interface functions, laid out the way `interface::call` passes them.
+
+use interface::{IAccountSpec, ILock, IUnlock};
+use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey};
+
+/// Base accounts of an interface function, in the order of its `IAccountSpec`
+pub trait InterfaceAccounts {
+    /// Name of the interface function, its preflight is `preflight_{IX_NAME}`
+    const IX_NAME: &'static str;
+    const SPEC: &'static [IAccountSpec];
+
+    fn keys(&self) -> Vec<Pubkey>;
+
+    /// With `preflight` no account is a signer, as preflight functions never receive signers
+    fn metas(&self, preflight: bool) -> Vec<AccountMeta> {
+        Self::SPEC
+            .iter()
+            .zip(self.keys())
+            .map(|(spec, pubkey)| AccountMeta {
+                pubkey,
+                is_signer: spec.signer && !preflight,
+                is_writable: spec.writable,
+            })
+            .collect()
+    }
+}
+
+/// `ILock`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockAccounts {
+    pub token: Pubkey,
+    pub mint: Pubkey,
+    pub delegate: Pubkey,
+    pub payer: Pubkey,
+    pub token_program: Pubkey,
+}
+
+impl InterfaceAccounts for LockAccounts {
+    const IX_NAME: &'static str = "lock";
+    const SPEC: &'static [IAccountSpec] = ILock::ACCOUNTS;
+
+    fn keys(&self) -> Vec<Pubkey> {
+        vec![
+            self.token,
+            self.mint,
+            self.delegate,
+            self.payer,
+            self.token_program,
+        ]
+    }
+}
+
+/// `IUnlock`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnlockAccounts {
+    pub token: Pubkey,
+    pub mint: Pubkey,
+    pub delegate: Pubkey,
+    pub token_program: Pubkey,
+}
+
+impl InterfaceAccounts for UnlockAccounts {
+    const IX_NAME: &'static str = "unlock";
+    const SPEC: &'static [IAccountSpec] = IUnlock::ACCOUNTS;
+
+    fn keys(&self) -> Vec<Pubkey> {
+        vec![self.token, self.mint, self.delegate, self.token_program]
+    }
+}
+
+/// `ILock`, for `lock_on_receive`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockOnReceiveAccounts {
+    pub token: Pubkey,
+    pub mint: Pubkey,
+    pub delegate: Pubkey,
+    pub payer: Pubkey,
+    pub token_program: Pubkey,
+}
+
+impl InterfaceAccounts for LockOnReceiveAccounts {
+    const IX_NAME: &'static str = "lock_on_receive";
+    const SPEC: &'static [IAccountSpec] = ILock::ACCOUNTS;
+
+    fn keys(&self) -> Vec<Pubkey> {
+        vec![
+            self.token,
+            self.mint,
+            self.delegate,
+            self.payer,
+            self.token_program,
+        ]
+    }
+}
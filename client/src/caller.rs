@@ -0,0 +1,180 @@
+//! Builds `caller` instructions from interface base accounts and the remaining accounts
+//! resolved by `preflight`.
+
+use anchor_lang::{InstructionData, ToAccountMetas};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+};
+
+use crate::{
+    accounts::{LockAccounts, LockOnReceiveAccounts, UnlockAccounts},
+    preflight::ClientError,
+};
+
+pub fn lock(
+    accounts: &LockAccounts,
+    perm_program: &Pubkey,
+    remaining_accounts: Vec<AccountMeta>,
+    nonce: Option<u64>,
+    use_authority: bool,
+    expect_frozen: bool,
+) -> Instruction {
+    let mut metas = caller::accounts::TILock {
+        token: accounts.token,
+        mint: accounts.mint,
+        delegate: accounts.delegate,
+        payer: accounts.payer,
+        token_program: accounts.token_program,
+        perm_program: *perm_program,
+    }
+    .to_account_metas(None);
+    delegate_signs(&mut metas, &accounts.delegate, use_authority);
+    metas.extend(remaining_accounts);
+    Instruction {
+        program_id: caller::id(),
+        accounts: metas,
+        data: caller::instruction::Lock {
+            nonce,
+            use_authority,
+            expect_frozen,
+        }
+        .data(),
+    }
+}
+
+/// `operator` signs the unlock, with `use_authority` the delegate is its caller authority PDA
+pub fn unlock(
+    accounts: &UnlockAccounts,
+    perm_program: &Pubkey,
+    operator: &Pubkey,
+    remaining_accounts: Vec<AccountMeta>,
+    nonce: Option<u64>,
+    use_authority: bool,
+) -> Instruction {
+    let mut metas = caller::accounts::TIUnlock {
+        token: accounts.token,
+        mint: accounts.mint,
+        delegate: accounts.delegate,
+        operator: *operator,
+        token_program: accounts.token_program,
+        perm_program: *perm_program,
+    }
+    .to_account_metas(None);
+    delegate_signs(&mut metas, &accounts.delegate, use_authority);
+    metas.extend(remaining_accounts);
+    Instruction {
+        program_id: caller::id(),
+        accounts: metas,
+        data: caller::instruction::Unlock {
+            nonce,
+            use_authority,
+        }
+        .data(),
+    }
+}
+
+/// Base accounts of `caller::tfer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferAccounts {
+    pub source: Pubkey,
+    pub dest: Pubkey,
+    pub mint: Pubkey,
+    pub delegate: Pubkey,
+    pub payer: Pubkey,
+    pub token_program: Pubkey,
+}
+
+impl TransferAccounts {
+    /// Accounts to resolve the first partition with
+    pub fn unlock_source(&self) -> UnlockAccounts {
+        UnlockAccounts {
+            token: self.source,
+            mint: self.mint,
+            delegate: self.delegate,
+            token_program: self.token_program,
+        }
+    }
+
+    /// Accounts to resolve the second partition with when the delegate is the owner
+    /// or SPL delegate of `dest`, `tfer` then locks it with `lock`
+    pub fn lock_dest(&self) -> LockAccounts {
+        LockAccounts {
+            token: self.dest,
+            mint: self.mint,
+            delegate: self.delegate,
+            payer: self.payer,
+            token_program: self.token_program,
+        }
+    }
+
+    /// Accounts to resolve the second partition with otherwise, `tfer` then locks
+    /// `dest` with `lock_on_receive`
+    pub fn lock_dest_on_receive(&self) -> LockOnReceiveAccounts {
+        LockOnReceiveAccounts {
+            token: self.dest,
+            mint: self.mint,
+            delegate: self.delegate,
+            payer: self.payer,
+            token_program: self.token_program,
+        }
+    }
+
+    /// Accounts to resolve the last partition with, only needed for partial transfers
+    pub fn relock_source(&self) -> LockAccounts {
+        LockAccounts {
+            token: self.source,
+            mint: self.mint,
+            delegate: self.delegate,
+            payer: self.payer,
+            token_program: self.token_program,
+        }
+    }
+}
+
+/// `partitions` are the remaining accounts of the unlock, lock and relock calls, see
+/// `TransferAccounts` for how to resolve each
+pub fn tfer(
+    accounts: &TransferAccounts,
+    perm_program: &Pubkey,
+    amount: u64,
+    partitions: [Vec<AccountMeta>; 3],
+    use_authority: bool,
+    expect_frozen: bool,
+) -> Result<Instruction, ClientError> {
+    let (lens, remaining_accounts) = interface::build_partitions(Vec::from(partitions))
+        .map_err(|e| ClientError::Partitions(e.to_string()))?;
+    let mut metas = caller::accounts::Transfer {
+        source: accounts.source,
+        dest: accounts.dest,
+        mint: accounts.mint,
+        delegate: accounts.delegate,
+        payer: accounts.payer,
+        token_program: accounts.token_program,
+        perm_program: *perm_program,
+    }
+    .to_account_metas(None);
+    delegate_signs(&mut metas, &accounts.delegate, use_authority);
+    metas.extend(remaining_accounts);
+    Ok(Instruction {
+        program_id: caller::id(),
+        accounts: metas,
+        data: caller::instruction::Tfer {
+            amount,
+            account_partitions: [lens[0], lens[1], lens[2]],
+            use_authority,
+            expect_frozen,
+        }
+        .data(),
+    })
+}
+
+/// The delegate must sign unless it's the caller's authority PDA, which the caller signs for
+fn delegate_signs(metas: &mut [AccountMeta], delegate: &Pubkey, use_authority: bool) {
+    if use_authority {
+        return;
+    }
+    for meta in metas.iter_mut().filter(|meta| meta.pubkey == *delegate) {
+        meta.is_signer = true;
+    }
+}
@@ -0,0 +1,181 @@
+//! Resolves the extra accounts of an interface call from the permission program's Anchor IDL,
+//! without simulating it: accounts past the interface accounts are derived from the PDA seeds
+//! the IDL declares, or are well-known programs and sysvars.
+
+use std::{collections::HashMap, path::Path};
+
+use interface_conformance::idl::{decode_idl_account, load_idl, to_camel_case};
+use serde_json::Value;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey, system_program, sysvar};
+
+use crate::{accounts::InterfaceAccounts, preflight::ClientError};
+
+/// Seed of the IDL account, created with `Pubkey::create_with_seed` from the program's base PDA
+const IDL_SEED: &str = "anchor:idl";
+
+/// Address of the IDL account `anchor idl init` creates for `program_id`
+pub fn idl_address(program_id: &Pubkey) -> Pubkey {
+    let base = Pubkey::find_program_address(&[], program_id).0;
+    // only fails on seeds longer than 32 bytes or owners ending in the PDA marker
+    Pubkey::create_with_seed(&base, IDL_SEED, program_id).unwrap()
+}
+
+/// Fetches and decodes the IDL `program_id` published on chain
+pub fn fetch_idl(rpc: &RpcClient, program_id: &Pubkey) -> Result<Value, ClientError> {
+    let account = rpc.get_account(&idl_address(program_id))?;
+    decode_idl_account(&account.data).map_err(|e| ClientError::Idl(e.to_string()))
+}
+
+/// Reads an IDL file or an IDL account dump, like `accounts/*-idl.json`
+pub fn read_idl(path: impl AsRef<Path>) -> Result<Value, ClientError> {
+    load_idl(path).map_err(|e| ClientError::Idl(e.to_string()))
+}
+
+/// Remaining accounts of the interface call `accounts` is for, resolved from `idl`.
+///
+/// The IDL instruction must start with the interface accounts under the same names, as
+/// `interface_conformance::idl::check_idl` checks. Every account after them must be a PDA of
+/// constant seeds and accounts declared before it, the system program or a sysvar. Optional
+/// accounts that can't be resolved are passed as `perm_program`, Anchor's `None`.
+pub fn resolve_from_idl<A: InterfaceAccounts>(
+    idl: &Value,
+    perm_program: &Pubkey,
+    accounts: &A,
+) -> Result<Vec<AccountMeta>, ClientError> {
+    let ix_name = to_camel_case(A::IX_NAME);
+    let idl_accounts = idl
+        .get("instructions")
+        .and_then(Value::as_array)
+        .and_then(|ixs| {
+            ixs.iter()
+                .find(|ix| ix.get("name").and_then(Value::as_str) == Some(ix_name.as_str()))
+        })
+        .and_then(|ix| ix.get("accounts"))
+        .and_then(Value::as_array)
+        .ok_or_else(|| ClientError::Idl(format!("{} is not in the idl", ix_name)))?;
+    let mut idl_accounts_flat = vec![];
+    flatten_accounts(idl_accounts, &mut idl_accounts_flat);
+
+    let mut known = HashMap::new();
+    for (position, (spec, pubkey)) in A::SPEC.iter().zip(accounts.keys()).enumerate() {
+        let name = to_camel_case(spec.name);
+        if account_name(idl_accounts_flat.get(position).copied()) != Some(name.as_str()) {
+            return Err(ClientError::Idl(format!(
+                "{} account {} is not {}",
+                ix_name, position, name
+            )));
+        }
+        known.insert(name, pubkey);
+    }
+
+    let mut remaining = vec![];
+    for account in idl_accounts_flat.iter().skip(A::SPEC.len()) {
+        let name = account_name(Some(account))
+            .ok_or_else(|| ClientError::Idl(format!("{} has an unnamed account", ix_name)))?;
+        let flag = |key: &str| account.get(key).and_then(Value::as_bool).unwrap_or(false);
+        if flag("isSigner") {
+            return Err(ClientError::Idl(format!(
+                "{} requires {} to sign, the interface only passes its own signers",
+                ix_name, name
+            )));
+        }
+        let meta = match resolve_account(name, account, perm_program, &known) {
+            Ok(pubkey) => AccountMeta {
+                pubkey,
+                is_signer: false,
+                is_writable: flag("isMut"),
+            },
+            Err(_) if flag("isOptional") => AccountMeta::new_readonly(*perm_program, false),
+            Err(e) => return Err(e),
+        };
+        known.insert(name.to_string(), meta.pubkey);
+        remaining.push(meta);
+    }
+    Ok(remaining)
+}
+
+/// Composite accounts nest their fields under `accounts`, the instruction takes them in order
+fn flatten_accounts<'a>(accounts: &'a [Value], flat: &mut Vec<&'a Value>) {
+    for account in accounts {
+        match account.get("accounts").and_then(Value::as_array) {
+            Some(nested) => flatten_accounts(nested, flat),
+            None => flat.push(account),
+        }
+    }
+}
+
+fn account_name(account: Option<&Value>) -> Option<&str> {
+    account?.get("name").and_then(Value::as_str)
+}
+
+fn resolve_account(
+    name: &str,
+    account: &Value,
+    perm_program: &Pubkey,
+    known: &HashMap<String, Pubkey>,
+) -> Result<Pubkey, ClientError> {
+    if let Some(pda) = account.get("pda") {
+        let seeds = pda
+            .get("seeds")
+            .and_then(Value::as_array)
+            .ok_or_else(|| ClientError::Idl(format!("{} has no pda seeds", name)))?
+            .iter()
+            .map(|seed| seed_bytes(name, seed, known))
+            .collect::<Result<Vec<_>, _>>()?;
+        let program_id = match pda.get("programId") {
+            Some(seed) => {
+                let bytes = seed_bytes(name, seed, known)?;
+                Pubkey::try_from(bytes.as_slice()).map_err(|_| {
+                    ClientError::Idl(format!("{} pda program is not a pubkey", name))
+                })?
+            }
+            None => *perm_program,
+        };
+        let seeds: Vec<&[u8]> = seeds.iter().map(Vec::as_slice).collect();
+        return Ok(Pubkey::find_program_address(&seeds, &program_id).0);
+    }
+    match name {
+        "systemProgram" => Ok(system_program::id()),
+        "rent" => Ok(sysvar::rent::id()),
+        "clock" => Ok(sysvar::clock::id()),
+        "instructions" => Ok(sysvar::instructions::id()),
+        _ => Err(ClientError::Idl(format!(
+            "{} is neither a pda nor a well-known account",
+            name
+        ))),
+    }
+}
+
+/// Bytes of a `const` seed, or the key of an `account` seed declared earlier. Seeds read from
+/// instruction arguments or account data can't be resolved without the call itself.
+fn seed_bytes(
+    name: &str,
+    seed: &Value,
+    known: &HashMap<String, Pubkey>,
+) -> Result<Vec<u8>, ClientError> {
+    let unresolvable = |what: String| ClientError::Idl(format!("{}: {}", name, what));
+    match seed.get("kind").and_then(Value::as_str) {
+        Some("const") => match seed.get("value") {
+            Some(Value::String(value)) => Ok(value.as_bytes().to_vec()),
+            Some(Value::Array(bytes)) => bytes
+                .iter()
+                .map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| unresolvable("const seed is not bytes".to_string())),
+            _ => Err(unresolvable("unsupported const seed".to_string())),
+        },
+        Some("account") => {
+            let path = seed
+                .get("path")
+                .and_then(Value::as_str)
+                .ok_or_else(|| unresolvable("account seed has no path".to_string()))?;
+            known
+                .get(path)
+                .map(|pubkey| pubkey.to_bytes().to_vec())
+                .ok_or_else(|| unresolvable(format!("seed {} is not a known account", path)))
+        }
+        Some(kind) => Err(unresolvable(format!("{} seeds are not supported", kind))),
+        None => Err(unresolvable("seed has no kind".to_string())),
+    }
+}
@@ -0,0 +1,7 @@
+pub mod accounts;
+pub mod caller;
+pub mod idl;
+#[cfg(feature = "offline")]
+pub mod offline;
+pub mod preflight;
+pub mod records;
@@ -0,0 +1,123 @@
+//! Runs `preflight_*` without a validator: the permission program's `.so` and snapshots of
+//! the accounts it reads are loaded into the SBF runtime embedded in `solana-program-test`.
+
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+
+use interface::PreflightAccounts;
+use serde_json::Value;
+use solana_program_test::ProgramTest;
+use solana_sdk::{
+    account::Account, bpf_loader, instruction::AccountMeta, pubkey::Pubkey, rent::Rent,
+};
+
+use crate::{
+    accounts::InterfaceAccounts,
+    preflight::{preflight_banks, ClientError},
+};
+
+/// A permission program and the account state its preflight runs against.
+/// Every run starts a fresh bank from the same snapshots, so results are deterministic.
+pub struct OfflinePreflight {
+    program_id: Pubkey,
+    elf: Vec<u8>,
+    accounts: HashMap<Pubkey, Account>,
+}
+
+impl OfflinePreflight {
+    pub fn new(program_id: Pubkey, elf: Vec<u8>) -> Self {
+        Self {
+            program_id,
+            elf,
+            accounts: HashMap::new(),
+        }
+    }
+
+    pub fn from_file(program_id: Pubkey, so_path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        let elf = fs::read(so_path.as_ref()).map_err(|e| {
+            ClientError::Snapshot(format!("{}: {}", so_path.as_ref().display(), e))
+        })?;
+        Ok(Self::new(program_id, elf))
+    }
+
+    /// Adds or replaces the snapshot of `pubkey`. Accounts without a snapshot don't exist.
+    pub fn add_account(&mut self, pubkey: Pubkey, account: Account) -> &mut Self {
+        self.accounts.insert(pubkey, account);
+        self
+    }
+
+    pub fn account(&self, pubkey: &Pubkey) -> Option<&Account> {
+        self.accounts.get(pubkey)
+    }
+
+    /// Adds an account dump as written by `solana account --output json`
+    pub fn add_account_dump(&mut self, path: impl AsRef<Path>) -> Result<&mut Self, ClientError> {
+        let json = fs::read_to_string(path.as_ref())
+            .map_err(|e| ClientError::Snapshot(format!("{}: {}", path.as_ref().display(), e)))?;
+        let json: Value = serde_json::from_str(&json)
+            .map_err(|e| ClientError::Snapshot(format!("{}: {}", path.as_ref().display(), e)))?;
+        let (pubkey, account) = parse_account_dump(&json)?;
+        Ok(self.add_account(pubkey, account))
+    }
+
+    /// Same as `preflight_banks`, against the snapshots instead of a live bank
+    pub async fn preflight<A: InterfaceAccounts>(
+        &self,
+        accounts: &A,
+        extra_accounts: &[AccountMeta],
+    ) -> Result<PreflightAccounts, ClientError> {
+        let mut program_test = ProgramTest::default();
+        program_test.add_account(
+            self.program_id,
+            Account {
+                lamports: Rent::default().minimum_balance(self.elf.len()),
+                data: self.elf.clone(),
+                owner: bpf_loader::id(),
+                executable: true,
+                rent_epoch: 0,
+            },
+        );
+        for (pubkey, account) in self.accounts.iter() {
+            program_test.add_account(*pubkey, account.clone());
+        }
+        let mut context = program_test.start_with_context().await;
+        preflight_banks(
+            &mut context.banks_client,
+            &context.payer,
+            &self.program_id,
+            accounts,
+            extra_accounts,
+        )
+        .await
+    }
+}
+
+fn parse_account_dump(json: &Value) -> Result<(Pubkey, Account), ClientError> {
+    let field = |pointer: &str| {
+        json.pointer(pointer)
+            .ok_or_else(|| ClientError::Snapshot(format!("account dump is missing {}", pointer)))
+    };
+    let pubkey = |pointer: &str| {
+        field(pointer)?
+            .as_str()
+            .and_then(|key| Pubkey::from_str(key).ok())
+            .ok_or_else(|| ClientError::Snapshot(format!("{} is not a pubkey", pointer)))
+    };
+    let number = |pointer: &str| {
+        field(pointer)?
+            .as_u64()
+            .ok_or_else(|| ClientError::Snapshot(format!("{} is not a number", pointer)))
+    };
+
+    let data = field("/account/data/0")?
+        .as_str()
+        .ok_or_else(|| ClientError::Snapshot("/account/data/0 is not a string".to_string()))?;
+    let account = Account {
+        lamports: number("/account/lamports")?,
+        data: base64::decode(data)
+            .map_err(|e| ClientError::Snapshot(format!("account data is not base64: {}", e)))?,
+        owner: pubkey("/account/owner")?,
+        executable: field("/account/executable")?.as_bool().unwrap_or(false),
+        rent_epoch: number("/account/rentEpoch")?,
+    };
+    Ok((pubkey("/pubkey")?, account))
+}
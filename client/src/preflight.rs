@@ -0,0 +1,210 @@
+//! Resolves the extra accounts of an interface call by simulating the permission program's
+//! `preflight_*` function, either against an RPC endpoint or a `BanksClient`.
+
+use std::fmt;
+
+use anchor_lang::{solana_program::hash, AnchorDeserialize};
+use interface::PreflightAccounts;
+use solana_banks_client::{BanksClient, BanksClientError};
+use solana_client::{rpc_client::RpcClient, rpc_config::RpcSimulateTransactionConfig};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+
+use crate::accounts::InterfaceAccounts;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Rpc(Box<solana_client::client_error::ClientError>),
+    Banks(BanksClientError),
+    /// The preflight simulation failed or returned nothing
+    Simulation(String),
+    /// The preflight return data is not a borsh `Vec<u8>` holding `PreflightAccounts`
+    Decode(String),
+    /// A `caller::tfer` partition holds more accounts than its length can count
+    Partitions(String),
+    /// A program or account snapshot for offline preflight couldn't be loaded
+    Snapshot(String),
+    /// The IDL couldn't be loaded or doesn't say how to resolve an account
+    Idl(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Rpc(e) => write!(f, "rpc error: {}", e),
+            ClientError::Banks(e) => write!(f, "banks error: {}", e),
+            ClientError::Simulation(e) => write!(f, "simulation error: {}", e),
+            ClientError::Decode(e) => write!(f, "decode error: {}", e),
+            ClientError::Partitions(e) => write!(f, "invalid partitions: {}", e),
+            ClientError::Snapshot(e) => write!(f, "snapshot error: {}", e),
+            ClientError::Idl(e) => write!(f, "idl error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<solana_client::client_error::ClientError> for ClientError {
+    fn from(e: solana_client::client_error::ClientError) -> Self {
+        ClientError::Rpc(Box::new(e))
+    }
+}
+
+impl From<BanksClientError> for ClientError {
+    fn from(e: BanksClientError) -> Self {
+        ClientError::Banks(e)
+    }
+}
+
+/// The `preflight_*` instruction for `accounts`. `extra_accounts` follow the base accounts,
+/// like the caller's remaining accounts do in `interface::preflight`, so middleware
+/// permission programs can find the program they wrap.
+pub fn preflight_instruction<A: InterfaceAccounts>(
+    perm_program: &Pubkey,
+    accounts: &A,
+    extra_accounts: &[AccountMeta],
+) -> Instruction {
+    let mut metas = accounts.metas(true);
+    metas.extend(
+        extra_accounts
+            .iter()
+            .map(|account| AccountMeta::new_readonly(account.pubkey, false)),
+    );
+    Instruction {
+        program_id: *perm_program,
+        accounts: metas,
+        data: sighash(&format!("preflight_{}", A::IX_NAME)),
+    }
+}
+
+/// Simulates preflight through `rpc`. The transaction isn't signed, `fee_payer` only has to exist.
+pub fn preflight_rpc<A: InterfaceAccounts>(
+    rpc: &RpcClient,
+    fee_payer: &Pubkey,
+    perm_program: &Pubkey,
+    accounts: &A,
+    extra_accounts: &[AccountMeta],
+) -> Result<PreflightAccounts, ClientError> {
+    let ix = preflight_instruction(perm_program, accounts, extra_accounts);
+    let tx = Transaction::new_unsigned(Message::new(&[ix], Some(fee_payer)));
+    let simulation = rpc
+        .simulate_transaction_with_config(
+            &tx,
+            RpcSimulateTransactionConfig {
+                sig_verify: false,
+                replace_recent_blockhash: true,
+                commitment: Some(rpc.commitment()),
+                ..RpcSimulateTransactionConfig::default()
+            },
+        )?
+        .value;
+    if let Some(e) = simulation.err {
+        return Err(ClientError::Simulation(format!(
+            "preflight_{} failed: {}",
+            A::IX_NAME,
+            e
+        )));
+    }
+    let return_data = simulation.return_data.ok_or_else(|| {
+        ClientError::Simulation(format!("preflight_{} set no return data", A::IX_NAME))
+    })?;
+    if return_data.program_id != perm_program.to_string() {
+        return Err(ClientError::Simulation(format!(
+            "preflight_{} return data was set by {}",
+            A::IX_NAME,
+            return_data.program_id
+        )));
+    }
+    let bytes = base64::decode(&return_data.data.0)
+        .map_err(|e| ClientError::Decode(format!("return data is not base64: {}", e)))?;
+    decode_return_data(A::IX_NAME, &bytes)
+}
+
+/// Simulates preflight through `banks`, signed and paid for by `payer`
+pub async fn preflight_banks<A: InterfaceAccounts>(
+    banks: &mut BanksClient,
+    payer: &Keypair,
+    perm_program: &Pubkey,
+    accounts: &A,
+    extra_accounts: &[AccountMeta],
+) -> Result<PreflightAccounts, ClientError> {
+    let ix = preflight_instruction(perm_program, accounts, extra_accounts);
+    let blockhash = banks.get_latest_blockhash().await?;
+    let tx = Transaction::new_signed_with_payer(&[ix], Some(&payer.pubkey()), &[payer], blockhash);
+    let simulation = banks.simulate_transaction(tx).await?;
+    match simulation.result {
+        Some(Ok(())) => {}
+        Some(Err(e)) => {
+            return Err(ClientError::Simulation(format!(
+                "preflight_{} failed: {}",
+                A::IX_NAME,
+                e
+            )))
+        }
+        None => {
+            return Err(ClientError::Simulation(format!(
+                "preflight_{} was not simulated",
+                A::IX_NAME
+            )))
+        }
+    }
+    let return_data = simulation
+        .simulation_details
+        .and_then(|details| details.return_data)
+        .ok_or_else(|| {
+            ClientError::Simulation(format!("preflight_{} set no return data", A::IX_NAME))
+        })?;
+    if return_data.program_id != *perm_program {
+        return Err(ClientError::Simulation(format!(
+            "preflight_{} return data was set by {}",
+            A::IX_NAME,
+            return_data.program_id
+        )));
+    }
+    decode_return_data(A::IX_NAME, &return_data.data)
+}
+
+/// Preflight returns `PreflightAccounts` serialized into a borsh `Vec<u8>`
+pub fn decode_return_data(ix_name: &str, data: &[u8]) -> Result<PreflightAccounts, ClientError> {
+    let bytes = Vec::<u8>::try_from_slice(data).map_err(|e| {
+        ClientError::Decode(format!("preflight_{} return data is not a Vec<u8>: {}", ix_name, e))
+    })?;
+    PreflightAccounts::try_from_slice(&bytes).map_err(|e| {
+        ClientError::Decode(format!(
+            "preflight_{} return data is not PreflightAccounts: {}",
+            ix_name, e
+        ))
+    })
+}
+
+/// Remaining accounts of an interface call: the extra accounts handed to preflight,
+/// followed by the accounts preflight resolved that aren't among them already.
+/// Accounts in both also get the flags preflight asked for.
+pub fn remaining_accounts(
+    extra_accounts: &[AccountMeta],
+    preflight: &PreflightAccounts,
+) -> Vec<AccountMeta> {
+    let mut accounts = extra_accounts.to_vec();
+    for account in preflight.accounts.iter() {
+        if let Some(meta) = accounts.iter_mut().find(|meta| meta.pubkey == account.pubkey) {
+            meta.is_signer |= account.signer;
+            meta.is_writable |= account.writable;
+            continue;
+        }
+        accounts.push(AccountMeta {
+            pubkey: account.pubkey,
+            is_signer: account.signer,
+            is_writable: account.writable,
+        });
+    }
+    accounts
+}
+
+fn sighash(ix_name: &str) -> Vec<u8> {
+    hash::hash(format!("global:{}", ix_name).as_bytes()).to_bytes()[..8].to_vec()
+}
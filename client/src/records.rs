@@ -0,0 +1,255 @@
+//! Enumerates the `TokenRecord`s kept by `permissioned_token_wrapper` and `twice_permissioned`
+//! and joins them with the state of the token accounts they track.
+//!
+//! Records don't store their token account, only its key as a PDA seed, so they are matched
+//! by deriving the record address of every token account of the mint.
+
+use std::collections::HashMap;
+
+use anchor_lang::{AccountDeserialize, Discriminator};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{account::Account, program_pack::Pack, pubkey::Pubkey};
+
+use crate::preflight::ClientError;
+
+/// `AccountType::Account` of a token-2022 account, stored right after the base state
+const TOKEN_2022_ACCOUNT_TYPE: u8 = 2;
+
+/// Permission programs whose `TokenRecord` layout is known
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordProgram {
+    PermissionedTokenWrapper,
+    TwicePermissioned,
+}
+
+impl RecordProgram {
+    pub fn from_program_id(program_id: &Pubkey) -> Option<Self> {
+        if *program_id == permissioned_token_wrapper::id() {
+            Some(RecordProgram::PermissionedTokenWrapper)
+        } else if *program_id == twice_permissioned::id() {
+            Some(RecordProgram::TwicePermissioned)
+        } else {
+            None
+        }
+    }
+
+    pub fn program_id(&self) -> Pubkey {
+        match self {
+            RecordProgram::PermissionedTokenWrapper => permissioned_token_wrapper::id(),
+            RecordProgram::TwicePermissioned => twice_permissioned::id(),
+        }
+    }
+
+    pub fn token_record_address(&self, token: &Pubkey) -> Pubkey {
+        let prefix = match self {
+            RecordProgram::PermissionedTokenWrapper => {
+                permissioned_token_wrapper::TOKEN_RECORD_PREFIX
+            }
+            RecordProgram::TwicePermissioned => twice_permissioned::TOKEN_RECORD_PREFIX,
+        };
+        Pubkey::find_program_address(&[token.as_ref(), prefix.as_bytes()], &self.program_id()).0
+    }
+
+    /// `getProgramAccounts` filters matching the program's token records, locked or not.
+    /// There is no filter on the `locked` flag: unmigrated v0 records keep it at another
+    /// offset than current ones, so records are only told apart once decoded.
+    pub fn record_filters(&self) -> Vec<RpcFilterType> {
+        let discriminator = match self {
+            RecordProgram::PermissionedTokenWrapper => {
+                permissioned_token_wrapper::TokenRecord::DISCRIMINATOR
+            }
+            RecordProgram::TwicePermissioned => twice_permissioned::TokenRecord::DISCRIMINATOR,
+        };
+        vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+            0,
+            discriminator.to_vec(),
+        ))]
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Result<TokenRecordState, ClientError> {
+        let decode_error = |e: anchor_lang::error::Error| {
+            ClientError::Decode(format!("invalid {:?} token record: {}", self, e))
+        };
+        match self {
+            RecordProgram::PermissionedTokenWrapper => {
+                if data.len() == permissioned_token_wrapper::LEGACY_TOKEN_RECORD_SPACE {
+                    // what `migrate_record` would upgrade it to, minus the unknown locker
+                    return Ok(TokenRecordState::PermissionedTokenWrapper(
+                        permissioned_token_wrapper::TokenRecord {
+                            version: 0,
+                            locked: data[8],
+                            lockers: vec![],
+                        },
+                    ));
+                }
+                permissioned_token_wrapper::TokenRecord::try_deserialize(&mut &data[..])
+                    .map(TokenRecordState::PermissionedTokenWrapper)
+                    .map_err(decode_error)
+            }
+            RecordProgram::TwicePermissioned => {
+                if data.len() == twice_permissioned::LEGACY_TOKEN_RECORD_SPACE {
+                    // v0 only counted locks, so the lockers are unknown until `migrate_record`
+                    return Ok(TokenRecordState::TwicePermissioned(
+                        twice_permissioned::TokenRecord {
+                            version: 0,
+                            locked: data[8],
+                            lockers: vec![],
+                            recent_nonces: vec![],
+                        },
+                    ));
+                }
+                twice_permissioned::TokenRecord::try_deserialize(&mut &data[..])
+                    .map(TokenRecordState::TwicePermissioned)
+                    .map_err(decode_error)
+            }
+        }
+    }
+}
+
+/// A decoded `TokenRecord`
+#[derive(Clone)]
+pub enum TokenRecordState {
+    PermissionedTokenWrapper(permissioned_token_wrapper::TokenRecord),
+    TwicePermissioned(twice_permissioned::TokenRecord),
+}
+
+impl TokenRecordState {
+    /// Whether the permission program has frozen the token account
+    pub fn is_locked(&self) -> bool {
+        match self {
+            TokenRecordState::PermissionedTokenWrapper(record) => record.locked != 0,
+            TokenRecordState::TwicePermissioned(record) => {
+                record.locked & twice_permissioned::LOCKED_FROZEN != 0
+            }
+        }
+    }
+
+    pub fn lockers(&self) -> &[Pubkey] {
+        match self {
+            TokenRecordState::PermissionedTokenWrapper(record) => &record.lockers,
+            TokenRecordState::TwicePermissioned(record) => &record.lockers,
+        }
+    }
+}
+
+/// A token account, the record its permission program keeps of it and its token state
+#[derive(Clone)]
+pub struct RecordedToken {
+    pub token: Pubkey,
+    pub token_record: Pubkey,
+    pub record: TokenRecordState,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub is_frozen: bool,
+}
+
+/// `getProgramAccounts` filters matching the token accounts of `mint` owned by `token_program`
+pub fn token_account_filters(token_program: &Pubkey, mint: &Pubkey) -> Vec<RpcFilterType> {
+    let mut filters = vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+        0,
+        mint.to_bytes().to_vec(),
+    ))];
+    if *token_program == spl_token::id() {
+        filters.push(RpcFilterType::DataSize(
+            spl_token::state::Account::LEN as u64,
+        ));
+    } else {
+        // token-2022 accounts grow with their extensions, their type tells them from mints
+        filters.push(RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+            spl_token::state::Account::LEN,
+            vec![TOKEN_2022_ACCOUNT_TYPE],
+        )));
+    }
+    filters
+}
+
+/// Joins `records` with the `token_accounts` they track. Records of other token accounts are
+/// left out, token accounts without a record too, and with `locked_only` unlocked records.
+pub fn join_records(
+    program: RecordProgram,
+    records: &[(Pubkey, Account)],
+    token_accounts: &[(Pubkey, Account)],
+    locked_only: bool,
+) -> Result<Vec<RecordedToken>, ClientError> {
+    let records: HashMap<Pubkey, &Account> = records
+        .iter()
+        .map(|(address, account)| (*address, account))
+        .collect();
+    let mut recorded = vec![];
+    for (token, account) in token_accounts.iter() {
+        let token_record = program.token_record_address(token);
+        let record = match records.get(&token_record) {
+            Some(record) => program.decode(&record.data)?,
+            None => continue,
+        };
+        if locked_only && !record.is_locked() {
+            continue;
+        }
+        let state = account
+            .data
+            .get(..spl_token::state::Account::LEN)
+            .and_then(|base| spl_token::state::Account::unpack(base).ok())
+            .ok_or_else(|| ClientError::Decode(format!("{} is not a token account", token)))?;
+        recorded.push(RecordedToken {
+            token: *token,
+            token_record,
+            record,
+            owner: state.owner,
+            amount: state.amount,
+            is_frozen: state.is_frozen(),
+        });
+    }
+    Ok(recorded)
+}
+
+/// Token accounts of `mint` that `program` keeps a record of, only locked ones with
+/// `locked_only`. `mint` must exist, its owner is the token program searched.
+///
+/// This fetches every token record of `program`, of all mints and locked or not, and every
+/// token account of `mint`, in two `getProgramAccounts` calls. `locked_only` doesn't make
+/// them any smaller. Expect it to be slow, and to need an RPC node that serves
+/// `getProgramAccounts`, for programs with many records or mints with many holders.
+pub fn recorded_tokens(
+    rpc: &RpcClient,
+    program: RecordProgram,
+    mint: &Pubkey,
+    locked_only: bool,
+) -> Result<Vec<RecordedToken>, ClientError> {
+    let token_program = rpc.get_account(mint)?.owner;
+    let records = program_accounts(
+        rpc,
+        &program.program_id(),
+        program.record_filters(),
+    )?;
+    let token_accounts = program_accounts(
+        rpc,
+        &token_program,
+        token_account_filters(&token_program, mint),
+    )?;
+    join_records(program, &records, &token_accounts, locked_only)
+}
+
+fn program_accounts(
+    rpc: &RpcClient,
+    program_id: &Pubkey,
+    filters: Vec<RpcFilterType>,
+) -> Result<Vec<(Pubkey, Account)>, ClientError> {
+    Ok(rpc.get_program_accounts_with_config(
+        program_id,
+        RpcProgramAccountsConfig {
+            filters: Some(filters),
+            account_config: RpcAccountInfoConfig {
+                // records outgrow the 128 bytes base58 can carry
+                encoding: Some(UiAccountEncoding::Base64),
+                commitment: Some(rpc.commitment()),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        },
+    )?)
+}
@@ -0,0 +1,228 @@
+use std::{path::PathBuf, str::FromStr};
+
+use interface_client::{
+    accounts::{LockAccounts, UnlockAccounts},
+    idl::{idl_address, read_idl, resolve_from_idl},
+    preflight::ClientError,
+};
+use serde_json::json;
+use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey, system_program};
+
+fn idl_dump(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../accounts")
+        .join(format!("{}-idl.json", name))
+}
+
+fn lock_accounts() -> LockAccounts {
+    let owner = Pubkey::new_unique();
+    LockAccounts {
+        token: Pubkey::new_unique(),
+        mint: Pubkey::new_unique(),
+        delegate: owner,
+        payer: owner,
+        token_program: spl_token::id(),
+    }
+}
+
+/// `programControl`, `tokenRecord` and `systemProgram`, which both shipped IDLs start with,
+/// followed by the per-mint `extra` PDAs
+fn expected_accounts(
+    perm_program: &Pubkey,
+    token: &Pubkey,
+    mint: &Pubkey,
+    extra: &[&[u8]],
+) -> Vec<AccountMeta> {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(
+            Pubkey::find_program_address(&[b"static"], perm_program).0,
+            false,
+        ),
+        AccountMeta::new(
+            Pubkey::find_program_address(&[token.as_ref(), b"token_record"], perm_program).0,
+            false,
+        ),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    for prefix in extra {
+        accounts.push(AccountMeta::new_readonly(
+            Pubkey::find_program_address(&[mint.as_ref(), prefix], perm_program).0,
+            false,
+        ));
+    }
+    accounts
+}
+
+#[test]
+fn resolves_pda_seeds_of_the_shipped_idls() {
+    let lockers: &[&[u8]] = &[b"locker_list"];
+    let lock_config: &[&[u8]] = &[b"lock_config"];
+    for (name, perm_program, lock_extra, unlock_extra) in [
+        (
+            "permissioned_token_wrapper",
+            permissioned_token_wrapper::id(),
+            lockers,
+            &[][..],
+        ),
+        (
+            "twice_permissioned",
+            twice_permissioned::id(),
+            lock_config,
+            lock_config,
+        ),
+    ] {
+        let idl = read_idl(idl_dump(name)).unwrap();
+        let lock = lock_accounts();
+        assert_eq!(
+            resolve_from_idl(&idl, &perm_program, &lock).unwrap(),
+            expected_accounts(&perm_program, &lock.token, &lock.mint, lock_extra),
+            "{} lock",
+            name
+        );
+        let unlock = UnlockAccounts {
+            token: lock.token,
+            mint: lock.mint,
+            delegate: lock.delegate,
+            token_program: lock.token_program,
+        };
+        assert_eq!(
+            resolve_from_idl(&idl, &perm_program, &unlock).unwrap(),
+            expected_accounts(&perm_program, &lock.token, &lock.mint, unlock_extra),
+            "{} unlock",
+            name
+        );
+    }
+}
+
+#[test]
+fn idl_address_matches_the_published_account() {
+    assert_eq!(
+        idl_address(&permissioned_token_wrapper::id()),
+        Pubkey::from_str("44jUpTaeKj6R9kGrrgiSkE6g416Nr36sraf2vCYBKWEd").unwrap()
+    );
+}
+
+#[test]
+fn resolves_accounts_of_an_unknown_program() {
+    let perm_program = Pubkey::new_unique();
+    let other_program = Pubkey::new_unique();
+    let idl = json!({
+        "instructions": [
+            {
+                "name": "lock",
+                "accounts": [
+                    { "name": "token", "isMut": true, "isSigner": false },
+                    { "name": "mint", "isMut": false, "isSigner": false },
+                    { "name": "delegate", "isMut": false, "isSigner": true },
+                    { "name": "payer", "isMut": true, "isSigner": true },
+                    { "name": "tokenProgram", "isMut": false, "isSigner": false },
+                    {
+                        "name": "config",
+                        "accounts": [
+                            {
+                                "name": "mintConfig",
+                                "isMut": false,
+                                "isSigner": false,
+                                "pda": {
+                                    "seeds": [
+                                        { "kind": "account", "type": "publicKey", "path": "mint" },
+                                        { "kind": "const", "type": { "array": ["u8", 2] }, "value": [1, 2] }
+                                    ],
+                                    "programId": {
+                                        "kind": "const",
+                                        "type": "publicKey",
+                                        "value": other_program.to_bytes().to_vec()
+                                    }
+                                }
+                            }
+                        ]
+                    },
+                    {
+                        "name": "lockRecord",
+                        "isMut": true,
+                        "isSigner": false,
+                        "pda": {
+                            "seeds": [
+                                { "kind": "account", "type": "publicKey", "path": "mintConfig" },
+                                { "kind": "account", "type": "publicKey", "path": "delegate" }
+                            ]
+                        }
+                    },
+                    { "name": "feeVault", "isMut": true, "isSigner": false, "isOptional": true },
+                    { "name": "rent", "isMut": false, "isSigner": false }
+                ],
+                "args": []
+            }
+        ]
+    });
+    let lock = lock_accounts();
+    let mint_config =
+        Pubkey::find_program_address(&[lock.mint.as_ref(), &[1, 2]], &other_program).0;
+    let lock_record = Pubkey::find_program_address(
+        &[mint_config.as_ref(), lock.delegate.as_ref()],
+        &perm_program,
+    )
+    .0;
+    assert_eq!(
+        resolve_from_idl(&idl, &perm_program, &lock).unwrap(),
+        vec![
+            AccountMeta::new_readonly(mint_config, false),
+            AccountMeta::new(lock_record, false),
+            AccountMeta::new_readonly(perm_program, false),
+            AccountMeta::new_readonly(solana_sdk::sysvar::rent::id(), false),
+        ]
+    );
+}
+
+#[test]
+fn rejects_accounts_it_cannot_resolve() {
+    let perm_program = Pubkey::new_unique();
+    let lock = lock_accounts();
+    let idl_with = |extra: serde_json::Value| {
+        json!({
+            "instructions": [
+                {
+                    "name": "lock",
+                    "accounts": [
+                        { "name": "token", "isMut": true, "isSigner": false },
+                        { "name": "mint", "isMut": false, "isSigner": false },
+                        { "name": "delegate", "isMut": false, "isSigner": true },
+                        { "name": "payer", "isMut": true, "isSigner": true },
+                        { "name": "tokenProgram", "isMut": false, "isSigner": false },
+                        extra
+                    ],
+                    "args": []
+                }
+            ]
+        })
+    };
+
+    for extra in [
+        json!({ "name": "oracle", "isMut": false, "isSigner": false }),
+        json!({ "name": "authority", "isMut": false, "isSigner": true }),
+        json!({
+            "name": "nonceRecord",
+            "isMut": true,
+            "isSigner": false,
+            "pda": { "seeds": [{ "kind": "arg", "type": "u64", "path": "nonce" }] }
+        }),
+    ] {
+        let idl = idl_with(extra.clone());
+        assert!(
+            matches!(
+                resolve_from_idl(&idl, &perm_program, &lock),
+                Err(ClientError::Idl(_))
+            ),
+            "{}",
+            extra
+        );
+    }
+
+    // interface accounts renamed
+    let idl = json!({
+        "instructions": [
+            { "name": "lock", "accounts": [{ "name": "account", "isMut": true, "isSigner": false }] }
+        ]
+    });
+    assert!(resolve_from_idl(&idl, &perm_program, &lock).is_err());
+}
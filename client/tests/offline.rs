@@ -0,0 +1,108 @@
+use std::{path::PathBuf, str::FromStr};
+
+use interface_client::{
+    accounts::{LockAccounts, UnlockAccounts},
+    offline::OfflinePreflight,
+};
+use solana_sdk::{
+    account::Account, program_option::COption, program_pack::Pack, pubkey::Pubkey, rent::Rent,
+};
+use test_fixtures::{deployed_program_path, program_control};
+
+fn token_program_account<T: Pack>(state: T) -> Account {
+    let mut data = vec![0; T::LEN];
+    state.pack_into_slice(&mut data);
+    Account {
+        lamports: Rent::default().minimum_balance(T::LEN),
+        data,
+        owner: spl_token::id(),
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+/// Snapshots of a permissioned mint and a token account holding one token of it
+fn snapshots(preflight: &mut OfflinePreflight, mint: Pubkey, token: Pubkey, owner: Pubkey) {
+    let program_control = program_control(&permissioned_token_wrapper::id());
+    preflight.add_account(
+        mint,
+        token_program_account(spl_token::state::Mint {
+            mint_authority: COption::Some(owner),
+            supply: 1,
+            decimals: 0,
+            is_initialized: true,
+            freeze_authority: COption::Some(program_control),
+        }),
+    );
+    preflight.add_account(
+        token,
+        token_program_account(spl_token::state::Account {
+            mint,
+            owner,
+            amount: 1,
+            state: spl_token::state::AccountState::Initialized,
+            ..spl_token::state::Account::default()
+        }),
+    );
+}
+
+#[tokio::test]
+async fn preflight_runs_against_snapshots() {
+    let so_path = deployed_program_path("permissioned_token_wrapper");
+    let program_id = permissioned_token_wrapper::id();
+    let mut preflight = OfflinePreflight::from_file(program_id, so_path).unwrap();
+    let (mint, token, owner) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    snapshots(&mut preflight, mint, token, owner);
+
+    let token_record = Pubkey::find_program_address(
+        &[token.as_ref(), permissioned_token_wrapper::TOKEN_RECORD_PREFIX.as_bytes()],
+        &program_id,
+    )
+    .0;
+    let lock = LockAccounts {
+        token,
+        mint,
+        delegate: owner,
+        payer: owner,
+        token_program: spl_token::id(),
+    };
+    let accounts = preflight.preflight(&lock, &[]).await.unwrap();
+    let record = accounts
+        .accounts
+        .iter()
+        .find(|account| account.pubkey == token_record)
+        .expect("token record not resolved");
+    assert!(record.writable);
+    assert!(accounts.accounts.iter().all(|account| !account.signer));
+
+    // every run starts from the same snapshots
+    let again = preflight.preflight(&lock, &[]).await.unwrap();
+    let keys = |accounts: &interface::PreflightAccounts| -> Vec<Pubkey> {
+        accounts.accounts.iter().map(|account| account.pubkey).collect()
+    };
+    assert_eq!(keys(&accounts), keys(&again));
+
+    let unlock = UnlockAccounts {
+        token,
+        mint,
+        delegate: owner,
+        token_program: spl_token::id(),
+    };
+    let accounts = preflight.preflight(&unlock, &[]).await.unwrap();
+    assert!(accounts.accounts.iter().any(|account| account.pubkey == token_record));
+}
+
+#[test]
+fn loads_account_dumps() {
+    let mut preflight = OfflinePreflight::new(permissioned_token_wrapper::id(), vec![]);
+    let dump = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../accounts/permissioned_token_wrapper-idl.json");
+    preflight.add_account_dump(dump).unwrap();
+    let idl_address = Pubkey::from_str("44jUpTaeKj6R9kGrrgiSkE6g416Nr36sraf2vCYBKWEd").unwrap();
+    let idl_account = preflight.account(&idl_address).unwrap();
+    assert_eq!(idl_account.owner, permissioned_token_wrapper::id());
+    assert!(!idl_account.data.is_empty());
+
+    let missing = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../accounts/missing.json");
+    assert!(preflight.add_account_dump(missing).is_err());
+}
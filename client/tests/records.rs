@@ -0,0 +1,219 @@
+use anchor_lang::{AccountSerialize, Discriminator};
+use interface_client::records::{join_records, token_account_filters, RecordProgram};
+use solana_client::rpc_filter::RpcFilterType;
+use solana_sdk::{account::Account, program_pack::Pack, pubkey::Pubkey, rent::Rent};
+
+fn program_account(owner: Pubkey, data: Vec<u8>) -> Account {
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+fn token_account(mint: Pubkey, owner: Pubkey, amount: u64, frozen: bool) -> Account {
+    let state = spl_token::state::Account {
+        mint,
+        owner,
+        amount,
+        state: if frozen {
+            spl_token::state::AccountState::Frozen
+        } else {
+            spl_token::state::AccountState::Initialized
+        },
+        ..spl_token::state::Account::default()
+    };
+    let mut data = vec![0; spl_token::state::Account::LEN];
+    state.pack_into_slice(&mut data);
+    program_account(spl_token::id(), data)
+}
+
+fn record_account<T: AccountSerialize>(program: RecordProgram, record: T) -> Account {
+    let mut data = vec![];
+    record.try_serialize(&mut data).unwrap();
+    program_account(program.program_id(), data)
+}
+
+#[test]
+fn joins_wrapper_records_with_token_state() {
+    let program = RecordProgram::PermissionedTokenWrapper;
+    let mint = Pubkey::new_unique();
+    let (locked, unlocked, unrecorded) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let (owner, locker) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let token_accounts = vec![
+        (locked, token_account(mint, owner, 5, true)),
+        (unlocked, token_account(mint, owner, 1, false)),
+        (unrecorded, token_account(mint, owner, 1, false)),
+    ];
+    let mut legacy_data = permissioned_token_wrapper::TokenRecord::DISCRIMINATOR.to_vec();
+    legacy_data.push(0);
+    let records = vec![
+        (
+            program.token_record_address(&locked),
+            record_account(
+                program,
+                permissioned_token_wrapper::TokenRecord {
+                    version: permissioned_token_wrapper::TOKEN_RECORD_VERSION,
+                    locked: 1,
+                    lockers: vec![locker],
+                },
+            ),
+        ),
+        // an unmigrated v0 record
+        (
+            program.token_record_address(&unlocked),
+            program_account(program.program_id(), legacy_data),
+        ),
+        // a record of a token account of another mint
+        (
+            program.token_record_address(&Pubkey::new_unique()),
+            record_account(
+                program,
+                permissioned_token_wrapper::TokenRecord {
+                    version: permissioned_token_wrapper::TOKEN_RECORD_VERSION,
+                    locked: 0,
+                    lockers: vec![],
+                },
+            ),
+        ),
+    ];
+
+    let recorded = join_records(program, &records, &token_accounts, false).unwrap();
+    assert_eq!(recorded.len(), 2);
+    let first = &recorded[0];
+    assert_eq!(first.token, locked);
+    assert_eq!(first.token_record, program.token_record_address(&locked));
+    assert!(first.record.is_locked());
+    assert_eq!(first.record.lockers(), &[locker]);
+    assert_eq!(
+        (first.owner, first.amount, first.is_frozen),
+        (owner, 5, true)
+    );
+    let second = &recorded[1];
+    assert_eq!(second.token, unlocked);
+    assert!(!second.record.is_locked());
+    assert!(second.record.lockers().is_empty());
+    assert!(!second.is_frozen);
+}
+
+#[test]
+fn locked_only_keeps_locked_legacy_records() {
+    let program = RecordProgram::PermissionedTokenWrapper;
+    let mint = Pubkey::new_unique();
+    let (legacy, locked, unlocked) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let owner = Pubkey::new_unique();
+    let token_accounts = vec![
+        (legacy, token_account(mint, owner, 1, true)),
+        (locked, token_account(mint, owner, 1, true)),
+        (unlocked, token_account(mint, owner, 1, false)),
+    ];
+    let record = |locked: u8| {
+        record_account(
+            program,
+            permissioned_token_wrapper::TokenRecord {
+                version: permissioned_token_wrapper::TOKEN_RECORD_VERSION,
+                locked,
+                lockers: vec![owner; locked as usize],
+            },
+        )
+    };
+    // a locked v0 record keeps its flag where a current record keeps `version`
+    let mut legacy_data = permissioned_token_wrapper::TokenRecord::DISCRIMINATOR.to_vec();
+    legacy_data.push(1);
+    let records = vec![
+        (
+            program.token_record_address(&legacy),
+            program_account(program.program_id(), legacy_data),
+        ),
+        (program.token_record_address(&locked), record(1)),
+        (program.token_record_address(&unlocked), record(0)),
+    ];
+
+    let recorded = join_records(program, &records, &token_accounts, true).unwrap();
+    let tokens: Vec<Pubkey> = recorded.iter().map(|recorded| recorded.token).collect();
+    assert_eq!(tokens, vec![legacy, locked]);
+    let recorded = join_records(program, &records, &token_accounts, false).unwrap();
+    assert_eq!(recorded.len(), 3);
+}
+
+#[test]
+fn decodes_twice_permissioned_records() {
+    let program = RecordProgram::TwicePermissioned;
+    assert_eq!(
+        RecordProgram::from_program_id(&twice_permissioned::id()),
+        Some(program)
+    );
+    assert_eq!(RecordProgram::from_program_id(&caller::id()), None);
+
+    let (mint, token, owner) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let lockers = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+    let records = vec![(
+        program.token_record_address(&token),
+        record_account(
+            program,
+            twice_permissioned::TokenRecord {
+                version: twice_permissioned::TOKEN_RECORD_VERSION,
+                locked: 1,
+                lockers: lockers.clone(),
+                recent_nonces: vec![],
+            },
+        ),
+    )];
+    let token_accounts = vec![(token, token_account(mint, owner, 1, true))];
+    let recorded = join_records(program, &records, &token_accounts, false).unwrap();
+    assert_eq!(recorded.len(), 1);
+    assert!(recorded[0].record.is_locked());
+    assert_eq!(recorded[0].record.lockers(), &lockers[..]);
+
+    // an unmigrated v0 record: locked, with a lock count of 2
+    let mut legacy_data = twice_permissioned::TokenRecord::DISCRIMINATOR.to_vec();
+    legacy_data.extend([1, 2]);
+    let records = vec![(
+        program.token_record_address(&token),
+        program_account(program.program_id(), legacy_data),
+    )];
+    let recorded = join_records(program, &records, &token_accounts, false).unwrap();
+    assert!(recorded[0].record.is_locked());
+    assert!(recorded[0].record.lockers().is_empty());
+
+    // a record that isn't one fails to decode instead of being skipped
+    let records = vec![(
+        program.token_record_address(&token),
+        program_account(program.program_id(), vec![0; 8]),
+    )];
+    assert!(join_records(program, &records, &token_accounts, false).is_err());
+}
+
+#[test]
+fn filters_match_the_record_and_token_layouts() {
+    let wrapper = RecordProgram::PermissionedTokenWrapper;
+    assert_eq!(wrapper.record_filters().len(), 1);
+
+    let mint = Pubkey::new_unique();
+    let filters = token_account_filters(&spl_token::id(), &mint);
+    assert!(filters.contains(&RpcFilterType::DataSize(
+        spl_token::state::Account::LEN as u64
+    )));
+    let filters = token_account_filters(&Pubkey::new_unique(), &mint);
+    assert!(!filters
+        .iter()
+        .any(|filter| matches!(filter, RpcFilterType::DataSize(_))));
+
+    // the mint sits where the filters look for it
+    let data = token_account(mint, Pubkey::new_unique(), 0, false).data;
+    assert_eq!(&data[..32], mint.as_ref());
+}
@@ -0,0 +1,124 @@
+use interface_client::{
+    accounts::{LockAccounts, UnlockAccounts},
+    caller,
+    preflight::{preflight_banks, remaining_accounts, ClientError},
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::{instruction::Instruction, signature::Signer};
+use test_fixtures::{clone_keypair, process, token_account, TokenFixture};
+
+/// A permissioned token held by `base.owner`, with the caller and the wrapper deployed
+struct Fixture {
+    base: TokenFixture,
+}
+
+impl Fixture {
+    async fn new() -> Self {
+        let programs = [
+            (::caller::id(), "caller"),
+            (
+                permissioned_token_wrapper::id(),
+                "permissioned_token_wrapper",
+            ),
+        ];
+        Self {
+            base: TokenFixture::new(&programs, &permissioned_token_wrapper::id(), 1).await,
+        }
+    }
+
+    fn lock_accounts(&self) -> LockAccounts {
+        LockAccounts {
+            token: self.base.token,
+            mint: self.base.mint,
+            delegate: self.base.owner.pubkey(),
+            payer: self.base.owner.pubkey(),
+            token_program: spl_token::id(),
+        }
+    }
+
+    fn unlock_accounts(&self) -> UnlockAccounts {
+        UnlockAccounts {
+            token: self.base.token,
+            mint: self.base.mint,
+            delegate: self.base.owner.pubkey(),
+            token_program: spl_token::id(),
+        }
+    }
+
+    async fn is_frozen(&mut self) -> bool {
+        token_account(&mut self.base.context, self.base.token)
+            .await
+            .is_frozen()
+    }
+
+    async fn process(&mut self, ix: Instruction) -> Result<(), BanksClientError> {
+        let owner = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&owner]).await
+    }
+}
+
+#[tokio::test]
+async fn resolved_lock_and_unlock_through_caller() {
+    let mut fixture = Fixture::new().await;
+    let perm_program = permissioned_token_wrapper::id();
+    let payer = clone_keypair(&fixture.base.context.payer);
+
+    let lock_accounts = fixture.lock_accounts();
+    let preflight = preflight_banks(
+        &mut fixture.base.context.banks_client,
+        &payer,
+        &perm_program,
+        &lock_accounts,
+        &[],
+    )
+    .await
+    .unwrap();
+    assert!(!preflight.accounts.is_empty());
+    let ix = caller::lock(
+        &lock_accounts,
+        &perm_program,
+        remaining_accounts(&[], &preflight),
+        None,
+        false,
+        true,
+    );
+    fixture.process(ix).await.unwrap();
+    assert!(fixture.is_frozen().await);
+
+    let unlock_accounts = fixture.unlock_accounts();
+    let preflight = preflight_banks(
+        &mut fixture.base.context.banks_client,
+        &payer,
+        &perm_program,
+        &unlock_accounts,
+        &[],
+    )
+    .await
+    .unwrap();
+    let ix = caller::unlock(
+        &unlock_accounts,
+        &perm_program,
+        &payer.pubkey(),
+        remaining_accounts(&[], &preflight),
+        None,
+        false,
+    );
+    fixture.process(ix).await.unwrap();
+    assert!(!fixture.is_frozen().await);
+}
+
+#[tokio::test]
+async fn preflight_of_a_program_without_the_interface_fails() {
+    let mut fixture = Fixture::new().await;
+    let payer = clone_keypair(&fixture.base.context.payer);
+    let lock_accounts = fixture.lock_accounts();
+    let result = preflight_banks(
+        &mut fixture.base.context.banks_client,
+        &payer,
+        &::caller::id(),
+        &lock_accounts,
+        &[],
+    )
+    .await;
+    assert!(matches!(result, Err(ClientError::Simulation(_))));
+}
@@ -0,0 +1,38 @@
+//! Rewrites the IDL account dumps in `accounts/` from the IDLs in `target/idl/`.
+//!
+//! Run `anchor build` first, then `cargo run -p interface-conformance --bin update_idl_dumps`.
+
+use std::{path::PathBuf, process::exit};
+
+use interface_conformance::idl::{load_idl, update_idl_dump};
+
+/// Each program's IDL and the dumps the validator loads it from
+const DUMPS: &[(&str, &[&str])] = &[
+    (
+        "permissioned_token_wrapper",
+        &["permissioned_token_wrapper-idl.json", "permission_token_wrapper-idl.json"],
+    ),
+    ("twice_permissioned", &["twice_permissioned-idl.json"]),
+];
+
+fn main() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..");
+    for (program, dumps) in DUMPS {
+        let idl_path = root.join("target/idl").join(format!("{}.json", program));
+        let idl = match load_idl(&idl_path) {
+            Ok(idl) => idl,
+            Err(e) => {
+                eprintln!("{}: {} (run `anchor build` first)", idl_path.display(), e);
+                exit(1);
+            }
+        };
+        for dump in dumps.iter() {
+            let dump_path = root.join("accounts").join(dump);
+            if let Err(e) = update_idl_dump(&dump_path, &idl) {
+                eprintln!("{}: {}", dump_path.display(), e);
+                exit(1);
+            }
+            println!("updated {}", dump_path.display());
+        }
+    }
+}
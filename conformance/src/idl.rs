@@ -0,0 +1,234 @@
+//! Checks a permission program's Anchor IDL against the account layout `interface::call` uses.
+
+use std::{
+    fmt, fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use interface::{IAccountSpec, ILock, IUnlock};
+use serde_json::{json, Value};
+use solana_sdk::rent::Rent;
+
+/// On-chain IDL accounts start with the discriminator, the authority and the compressed data length
+const IDL_ACCOUNT_HEADER_LEN: usize = 8 + 32 + 4;
+
+#[derive(Debug)]
+pub enum IdlError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Base64(base64::DecodeError),
+    Format(String),
+}
+
+impl fmt::Display for IdlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdlError::Io(e) => write!(f, "io error: {}", e),
+            IdlError::Json(e) => write!(f, "json error: {}", e),
+            IdlError::Base64(e) => write!(f, "base64 error: {}", e),
+            IdlError::Format(e) => write!(f, "malformed idl: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for IdlError {}
+
+impl From<std::io::Error> for IdlError {
+    fn from(e: std::io::Error) -> Self {
+        IdlError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for IdlError {
+    fn from(e: serde_json::Error) -> Self {
+        IdlError::Json(e)
+    }
+}
+
+impl From<base64::DecodeError> for IdlError {
+    fn from(e: base64::DecodeError) -> Self {
+        IdlError::Base64(e)
+    }
+}
+
+/// Loads an IDL from either a plain `anchor build` IDL file or an account dump
+/// as written by `solana account --output json` (like `accounts/*-idl.json`)
+pub fn load_idl(path: impl AsRef<Path>) -> Result<Value, IdlError> {
+    let json: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    if json.get("instructions").is_some() {
+        return Ok(json);
+    }
+    let data = json
+        .pointer("/account/data/0")
+        .and_then(Value::as_str)
+        .ok_or_else(|| IdlError::Format("expected an idl or an account dump".to_string()))?;
+    decode_idl_account(&base64::decode(data)?)
+}
+
+/// Decodes the zlib-compressed IDL stored in an Anchor IDL account
+pub fn decode_idl_account(data: &[u8]) -> Result<Value, IdlError> {
+    if data.len() < IDL_ACCOUNT_HEADER_LEN {
+        return Err(IdlError::Format("idl account too short".to_string()));
+    }
+    let mut len_bytes = [0u8; 4];
+    len_bytes.copy_from_slice(&data[IDL_ACCOUNT_HEADER_LEN - 4..IDL_ACCOUNT_HEADER_LEN]);
+    let len = u32::from_le_bytes(len_bytes) as usize;
+    let compressed = data
+        .get(IDL_ACCOUNT_HEADER_LEN..IDL_ACCOUNT_HEADER_LEN + len)
+        .ok_or_else(|| IdlError::Format("idl data length exceeds account".to_string()))?;
+
+    let mut json = String::new();
+    ZlibDecoder::new(compressed).read_to_string(&mut json)?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// Re-encodes `data`, an Anchor IDL account, to hold `idl`, keeping its discriminator and authority.
+///
+/// The account keeps its size when the compressed IDL still fits, and grows to fit it otherwise.
+pub fn encode_idl_account(data: &[u8], idl: &Value) -> Result<Vec<u8>, IdlError> {
+    if data.len() < IDL_ACCOUNT_HEADER_LEN {
+        return Err(IdlError::Format("idl account too short".to_string()));
+    }
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder.write_all(&serde_json::to_vec(idl)?)?;
+    let compressed = encoder.finish()?;
+
+    let mut encoded = data[..IDL_ACCOUNT_HEADER_LEN - 4].to_vec();
+    encoded.extend((compressed.len() as u32).to_le_bytes());
+    encoded.extend(compressed);
+    encoded.resize(encoded.len().max(data.len()), 0);
+    Ok(encoded)
+}
+
+/// Rewrites the account dump at `dump_path` to hold `idl`, as `anchor idl upgrade` would on chain
+pub fn update_idl_dump(dump_path: impl AsRef<Path>, idl: &Value) -> Result<(), IdlError> {
+    let dump_path = dump_path.as_ref();
+    let mut dump: Value = serde_json::from_str(&fs::read_to_string(dump_path)?)?;
+    let data = dump
+        .pointer("/account/data/0")
+        .and_then(Value::as_str)
+        .ok_or_else(|| IdlError::Format("expected an account dump".to_string()))?;
+    let data = encode_idl_account(&base64::decode(data)?, idl)?;
+
+    dump["account"]["lamports"] = json!(Rent::default().minimum_balance(data.len()));
+    dump["account"]["data"] = json!([base64::encode(&data), "base64"]);
+    fs::write(dump_path, serde_json::to_string_pretty(&dump)? + "\n")?;
+    Ok(())
+}
+
+/// A mismatch between a permission program's instruction and the interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub instruction: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.instruction, self.message)
+    }
+}
+
+/// Checks `lock`, `unlock` and their preflights against `ILock::ACCOUNTS`/`IUnlock::ACCOUNTS`.
+///
+/// Each instruction must start with the interface accounts, with the same names and order.
+/// It may treat an account as read-only or non-signer when the interface passes more,
+/// but must not require writability or a signature the interface doesn't provide.
+/// Preflight functions never receive signers.
+pub fn check_idl(idl: &Value) -> Vec<Violation> {
+    let mut violations = vec![];
+    check_instruction(idl, "lock", ILock::ACCOUNTS, false, &mut violations);
+    check_instruction(idl, "preflight_lock", ILock::ACCOUNTS, true, &mut violations);
+    check_instruction(idl, "unlock", IUnlock::ACCOUNTS, false, &mut violations);
+    check_instruction(idl, "preflight_unlock", IUnlock::ACCOUNTS, true, &mut violations);
+    violations
+}
+
+fn check_instruction(
+    idl: &Value,
+    ix_name: &str,
+    spec: &[IAccountSpec],
+    preflight: bool,
+    violations: &mut Vec<Violation>,
+) {
+    let mut violation = |message: String| {
+        violations.push(Violation {
+            instruction: ix_name.to_string(),
+            message,
+        })
+    };
+
+    let idl_name = to_camel_case(ix_name);
+    let accounts = idl
+        .get("instructions")
+        .and_then(Value::as_array)
+        .and_then(|ixs| {
+            ixs.iter()
+                .find(|ix| ix.get("name").and_then(Value::as_str) == Some(idl_name.as_str()))
+        })
+        .and_then(|ix| ix.get("accounts"))
+        .and_then(Value::as_array);
+    let accounts = match accounts {
+        Some(accounts) => accounts,
+        None => return violation("instruction is missing".to_string()),
+    };
+
+    if accounts.len() < spec.len() {
+        violation(format!(
+            "expected at least {} accounts, found {}",
+            spec.len(),
+            accounts.len()
+        ));
+    }
+
+    for (position, (expected, account)) in spec.iter().zip(accounts.iter()).enumerate() {
+        let expected_name = to_camel_case(expected.name);
+        let name = account.get("name").and_then(Value::as_str).unwrap_or("");
+        if name != expected_name {
+            violation(format!(
+                "account {} is `{}`, expected `{}`",
+                position, name, expected_name
+            ));
+            continue;
+        }
+        if account.get("accounts").is_some() {
+            violation(format!("account `{}` must not be a composite", name));
+            continue;
+        }
+
+        let is_mut = account.get("isMut").and_then(Value::as_bool).unwrap_or(false);
+        let is_signer = account.get("isSigner").and_then(Value::as_bool).unwrap_or(false);
+        let signer = expected.signer && !preflight;
+        if is_mut && !expected.writable {
+            violation(format!(
+                "account `{}` must not be writable, the interface passes it read-only",
+                name
+            ));
+        }
+        if is_signer && !signer {
+            violation(format!(
+                "account `{}` must not be a signer, the interface does not sign for it",
+                name
+            ));
+        }
+    }
+}
+
+/// IDL names of instructions and accounts are the camelCase of their Rust names
+pub fn to_camel_case(name: &str) -> String {
+    let mut camel = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
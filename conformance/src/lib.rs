@@ -0,0 +1,3 @@
+pub mod idl;
+#[cfg(feature = "scenarios")]
+pub mod scenarios;
@@ -0,0 +1,374 @@
+//! Standard behavioural scenarios any permission program must pass to be callable
+//! through `interface::call`, run against the program's `.so` in `solana-program-test`.
+
+use std::{fmt, fs, io, path::Path};
+
+use anchor_lang::AnchorDeserialize;
+use interface::{IAccountSpec, ILock, IUnlock, PreflightAccounts};
+use solana_program_test::{BanksClientError, ProgramTest};
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use test_fixtures::{clone_keypair, create_token_account, program_account, sighash, TokenFixture};
+
+/// A permission program under test
+pub struct PermissionProgram {
+    pub program_id: Pubkey,
+    pub elf: Vec<u8>,
+    /// Freeze authority the program expects on the mints it locks
+    pub freeze_authority: Pubkey,
+    /// Number of distinct lockers needed before the token is restricted
+    pub lockers_required: usize,
+}
+
+impl PermissionProgram {
+    pub fn from_file(
+        program_id: Pubkey,
+        so_path: impl AsRef<Path>,
+        freeze_authority: Pubkey,
+        lockers_required: usize,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            program_id,
+            elf: fs::read(so_path)?,
+            freeze_authority,
+            lockers_required,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ScenarioResult {
+    pub name: &'static str,
+    pub outcome: Result<(), String>,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub results: Vec<ScenarioResult>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.outcome.is_ok())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in self.results.iter() {
+            match &result.outcome {
+                Ok(()) => writeln!(f, "PASS {}", result.name)?,
+                Err(e) => writeln!(f, "FAIL {}: {}", result.name, e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs every scenario, each against a freshly started bank
+pub async fn run(program: &PermissionProgram) -> Report {
+    let mut results = vec![];
+    for name in SCENARIOS {
+        let outcome = Fixture::new(program).await.run_scenario(name).await;
+        results.push(ScenarioResult { name, outcome });
+    }
+    Report { results }
+}
+
+const SCENARIOS: [&str; 5] = [
+    "preflight_returns_resolvable_accounts",
+    "preflight_is_read_only",
+    "lock_restricts_transfers",
+    "unlock_restores_transfers",
+    "unauthorized_delegate_rejected",
+];
+
+struct Fixture {
+    base: TokenFixture,
+    program_id: Pubkey,
+    lockers_required: usize,
+    /// Second token account of the owner, for transfers
+    dest: Pubkey,
+}
+
+impl Fixture {
+    async fn new(program: &PermissionProgram) -> Self {
+        let mut program_test = ProgramTest::default();
+        program_test.add_account(program.program_id, program_account(program.elf.clone()));
+        let mut base = TokenFixture::start(program_test, &program.freeze_authority, 1).await;
+        let owner = base.owner.pubkey();
+        let dest = create_token_account(&mut base.context, &base.mint, &owner, 0).await;
+        Self {
+            base,
+            program_id: program.program_id,
+            lockers_required: program.lockers_required,
+            dest,
+        }
+    }
+
+    async fn run_scenario(&mut self, name: &str) -> Result<(), String> {
+        match name {
+            "preflight_returns_resolvable_accounts" => {
+                self.preflight_returns_resolvable_accounts().await
+            }
+            "preflight_is_read_only" => self.preflight_is_read_only().await,
+            "lock_restricts_transfers" => self.lock_restricts_transfers().await,
+            "unlock_restores_transfers" => self.unlock_restores_transfers().await,
+            "unauthorized_delegate_rejected" => self.unauthorized_delegate_rejected().await,
+            _ => Err(format!("unknown scenario {}", name)),
+        }
+    }
+
+    async fn preflight_returns_resolvable_accounts(&mut self) -> Result<(), String> {
+        let owner = clone_keypair(&self.base.owner);
+        for (ix_name, spec) in [("lock", ILock::ACCOUNTS), ("unlock", IUnlock::ACCOUNTS)] {
+            let accounts = self.preflight(ix_name, spec, &owner.pubkey()).await?;
+            if let Some(account) = accounts.accounts.iter().find(|account| account.signer) {
+                return Err(format!(
+                    "preflight_{} requires signer {}, which callers cannot provide",
+                    ix_name, account.pubkey
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    async fn preflight_is_read_only(&mut self) -> Result<(), String> {
+        let owner = clone_keypair(&self.base.owner);
+        let accounts = self.preflight("lock", ILock::ACCOUNTS, &owner.pubkey()).await?;
+        let mut keys: Vec<Pubkey> = self
+            .base_metas(ILock::ACCOUNTS, &owner.pubkey(), false)
+            .iter()
+            .map(|meta| meta.pubkey)
+            .collect();
+        keys.extend(accounts.accounts.iter().map(|account| account.pubkey));
+
+        let before = self.snapshot(&keys).await?;
+        let ix = Instruction {
+            program_id: self.program_id,
+            accounts: self.base_metas(ILock::ACCOUNTS, &owner.pubkey(), false),
+            data: sighash("preflight_lock"),
+        };
+        self.process(&[ix], &[]).await.map_err(|e| e.to_string())?;
+        let after = self.snapshot(&keys).await?;
+
+        let payer = self.base.context.payer.pubkey();
+        for ((key, before), after) in keys.iter().zip(before.iter()).zip(after.iter()) {
+            let changed = match (before, after) {
+                (Some(before), Some(after)) => {
+                    before.data != after.data
+                        || before.owner != after.owner
+                        || (*key != payer && before.lamports != after.lamports)
+                }
+                (None, None) => false,
+                _ => true,
+            };
+            if changed {
+                return Err(format!("preflight_lock modified account {}", key));
+            }
+        }
+        Ok(())
+    }
+
+    async fn lock_restricts_transfers(&mut self) -> Result<(), String> {
+        self.lock_with_all_lockers().await?;
+        if self.transfer().await.is_ok() {
+            return Err("token could still be transferred after lock".to_string());
+        }
+        Ok(())
+    }
+
+    async fn unlock_restores_transfers(&mut self) -> Result<(), String> {
+        let lockers = self.lock_with_all_lockers().await?;
+        for locker in lockers.iter() {
+            self.call("unlock", IUnlock::ACCOUNTS, locker)
+                .await
+                .map_err(|e| format!("unlock failed: {}", e))?;
+        }
+        self.transfer()
+            .await
+            .map_err(|e| format!("token could not be transferred after unlock: {}", e))
+    }
+
+    async fn unauthorized_delegate_rejected(&mut self) -> Result<(), String> {
+        let stranger = Keypair::new();
+        if self.call("lock", ILock::ACCOUNTS, &stranger).await.is_ok() {
+            return Err("lock succeeded for a delegate with no authority over the token".to_string());
+        }
+        Ok(())
+    }
+
+    /// Locks with freshly approved SPL delegates, then with the owner, until
+    /// `lockers_required` distinct lockers hold the token.
+    ///
+    /// Each delegate locks right after its approval, since the next approval replaces it.
+    /// The owner needs no approval and locks last, as approving fails once the token is frozen.
+    async fn lock_with_all_lockers(&mut self) -> Result<Vec<Keypair>, String> {
+        let mut lockers = vec![];
+        while lockers.len() + 1 < self.lockers_required {
+            let delegate = Keypair::new();
+            let approve = spl_token::instruction::approve(
+                &spl_token::id(),
+                &self.base.token,
+                &delegate.pubkey(),
+                &self.base.owner.pubkey(),
+                &[],
+                1,
+            )
+            .map_err(|e| e.to_string())?;
+            let owner = clone_keypair(&self.base.owner);
+            self.process(&[approve], &[&owner])
+                .await
+                .map_err(|e| format!("approve failed: {}", e))?;
+            self.call("lock", ILock::ACCOUNTS, &delegate)
+                .await
+                .map_err(|e| format!("lock failed: {}", e))?;
+            lockers.push(delegate);
+        }
+        let owner = clone_keypair(&self.base.owner);
+        self.call("lock", ILock::ACCOUNTS, &owner)
+            .await
+            .map_err(|e| format!("lock failed: {}", e))?;
+        lockers.push(owner);
+        Ok(lockers)
+    }
+
+    /// Mirrors `interface::call`: preflight, then execute with the returned accounts
+    async fn call(
+        &mut self,
+        ix_name: &str,
+        spec: &[IAccountSpec],
+        delegate: &Keypair,
+    ) -> Result<(), String> {
+        let extra = self.preflight(ix_name, spec, &delegate.pubkey()).await?;
+        let mut accounts = self.base_metas(spec, &delegate.pubkey(), true);
+        accounts.extend(extra.accounts.iter().map(|account| {
+            if account.writable {
+                AccountMeta::new(account.pubkey, account.signer)
+            } else {
+                AccountMeta::new_readonly(account.pubkey, account.signer)
+            }
+        }));
+        let mut data = sighash(ix_name);
+        // `interface::call` always appends a `None` nonce
+        data.push(0);
+        let ix = Instruction {
+            program_id: self.program_id,
+            accounts,
+            data,
+        };
+        self.process(&[ix], &[delegate])
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn preflight(
+        &mut self,
+        ix_name: &str,
+        spec: &[IAccountSpec],
+        delegate: &Pubkey,
+    ) -> Result<PreflightAccounts, String> {
+        let ix = Instruction {
+            program_id: self.program_id,
+            accounts: self.base_metas(spec, delegate, false),
+            data: sighash(&format!("preflight_{}", ix_name)),
+        };
+        let tx = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&self.base.context.payer.pubkey()),
+            &[&self.base.context.payer],
+            self.base.context.last_blockhash,
+        );
+        let simulation = self
+            .context
+            .banks_client
+            .simulate_transaction(tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        match simulation.result {
+            Some(Ok(())) => {}
+            Some(Err(e)) => return Err(format!("preflight_{} failed: {}", ix_name, e)),
+            None => return Err(format!("preflight_{} was not simulated", ix_name)),
+        }
+        let return_data = simulation
+            .simulation_details
+            .and_then(|details| details.return_data)
+            .ok_or_else(|| format!("preflight_{} set no return data", ix_name))?;
+        if return_data.program_id != self.program_id {
+            return Err(format!(
+                "preflight_{} return data was set by {}",
+                ix_name, return_data.program_id
+            ));
+        }
+        let bytes = Vec::<u8>::try_from_slice(&return_data.data)
+            .map_err(|e| format!("preflight_{} return data is not a Vec<u8>: {}", ix_name, e))?;
+        PreflightAccounts::try_from_slice(&bytes).map_err(|e| {
+            format!(
+                "preflight_{} return data is not PreflightAccounts: {}",
+                ix_name, e
+            )
+        })
+    }
+
+    /// Interface accounts in order, signers only when executing like `interface::call` does
+    fn base_metas(&self, spec: &[IAccountSpec], delegate: &Pubkey, execute: bool) -> Vec<AccountMeta> {
+        spec.iter()
+            .map(|account| {
+                let pubkey = match account.name {
+                    "token" => self.base.token,
+                    "mint" => self.base.mint,
+                    "delegate" => *delegate,
+                    "payer" => self.base.context.payer.pubkey(),
+                    "token_program" => spl_token::id(),
+                    name => panic!("unknown interface account {}", name),
+                };
+                AccountMeta {
+                    pubkey,
+                    is_signer: execute && account.signer,
+                    is_writable: account.writable,
+                }
+            })
+            .collect()
+    }
+
+    async fn transfer(&mut self) -> Result<(), BanksClientError> {
+        let ix = spl_token::instruction::transfer(
+            &spl_token::id(),
+            &self.base.token,
+            &self.dest,
+            &self.base.owner.pubkey(),
+            &[],
+            1,
+        )
+        .unwrap();
+        let owner = clone_keypair(&self.base.owner);
+        self.process(&[ix], &[&owner]).await
+    }
+
+    async fn snapshot(&mut self, keys: &[Pubkey]) -> Result<Vec<Option<Account>>, String> {
+        let mut accounts = vec![];
+        for key in keys {
+            accounts.push(
+                self.base.context
+                    .banks_client
+                    .get_account(*key)
+                    .await
+                    .map_err(|e| e.to_string())?,
+            );
+        }
+        Ok(accounts)
+    }
+
+    async fn process(
+        &mut self,
+        ixs: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), BanksClientError> {
+        test_fixtures::process(&mut self.base.context, ixs, signers).await
+    }
+}
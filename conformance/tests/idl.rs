@@ -0,0 +1,110 @@
+use std::path::PathBuf;
+
+use interface_conformance::idl::{check_idl, load_idl};
+use serde_json::{json, Value};
+
+fn root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..")
+}
+
+/// The IDL of the last `anchor build`, so the check can't pass against a stale dump
+fn built_idl(name: &str) -> Value {
+    let path = root().join("target/idl").join(format!("{}.json", name));
+    assert!(
+        path.exists(),
+        "{} not found, run `anchor build` first",
+        path.display()
+    );
+    without_metadata(load_idl(path).unwrap())
+}
+
+/// `anchor build` records the deployed address under `metadata`, which isn't part of the interface
+fn without_metadata(mut idl: Value) -> Value {
+    if let Some(idl) = idl.as_object_mut() {
+        idl.remove("metadata");
+    }
+    idl
+}
+
+#[test]
+fn permissioned_token_wrapper_conforms() {
+    assert_eq!(check_idl(&built_idl("permissioned_token_wrapper")), vec![]);
+}
+
+#[test]
+fn twice_permissioned_conforms() {
+    assert_eq!(check_idl(&built_idl("twice_permissioned")), vec![]);
+}
+
+#[test]
+fn idl_dumps_match_the_built_idls() {
+    for (program, dump) in [
+        ("permissioned_token_wrapper", "permissioned_token_wrapper-idl.json"),
+        ("permissioned_token_wrapper", "permission_token_wrapper-idl.json"),
+        ("twice_permissioned", "twice_permissioned-idl.json"),
+    ] {
+        let shipped = without_metadata(load_idl(root().join("accounts").join(dump)).unwrap());
+        assert!(
+            shipped == built_idl(program),
+            "accounts/{} is stale, run the update_idl_dumps binary",
+            dump
+        );
+    }
+}
+
+#[test]
+fn detects_renamed_and_signer_accounts() {
+    let idl = json!({
+        "instructions": [
+            {
+                "name": "preflightUnlock",
+                "accounts": [
+                    { "name": "token", "isMut": false, "isSigner": false },
+                    { "name": "mint", "isMut": false, "isSigner": false },
+                    { "name": "owner", "isMut": false, "isSigner": false },
+                    { "name": "tokenProgram", "isMut": false, "isSigner": false }
+                ]
+            },
+            {
+                "name": "unlock",
+                "accounts": [
+                    { "name": "token", "isMut": true, "isSigner": false },
+                    { "name": "mint", "isMut": true, "isSigner": false },
+                    { "name": "delegate", "isMut": false, "isSigner": true },
+                    { "name": "tokenProgram", "isMut": false, "isSigner": true }
+                ]
+            }
+        ]
+    });
+    let messages: Vec<String> = check_idl(&idl).iter().map(|v| v.to_string()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "lock: instruction is missing",
+            "preflight_lock: instruction is missing",
+            "unlock: account `mint` must not be writable, the interface passes it read-only",
+            "unlock: account `tokenProgram` must not be a signer, the interface does not sign for it",
+            "preflight_unlock: account 2 is `owner`, expected `delegate`",
+        ]
+    );
+}
+
+#[test]
+fn preflight_never_receives_signers() {
+    let idl = json!({
+        "instructions": [
+            {
+                "name": "preflightUnlock",
+                "accounts": [
+                    { "name": "token", "isMut": false, "isSigner": false },
+                    { "name": "mint", "isMut": false, "isSigner": false },
+                    { "name": "delegate", "isMut": false, "isSigner": true },
+                    { "name": "tokenProgram", "isMut": false, "isSigner": false }
+                ]
+            }
+        ]
+    });
+    let violations = check_idl(&idl);
+    assert!(violations.iter().any(|v| v.instruction == "preflight_unlock"
+        && v.message == "account `delegate` must not be a signer, the interface does not sign for it"));
+}
@@ -0,0 +1,51 @@
+use interface_conformance::scenarios::{run, PermissionProgram};
+use test_fixtures::{deployed_program_path, program_control};
+
+#[tokio::test]
+async fn permissioned_token_wrapper_passes() {
+    let so_path = deployed_program_path("permissioned_token_wrapper");
+    let program_id = permissioned_token_wrapper::id();
+    let program =
+        PermissionProgram::from_file(program_id, so_path, program_control(&program_id), 1)
+            .unwrap();
+
+    let report = run(&program).await;
+    println!("{}", report);
+    assert!(report.passed());
+}
+
+#[tokio::test]
+async fn twice_permissioned_passes() {
+    let so_path = deployed_program_path("twice_permissioned");
+    let program_id = twice_permissioned::id();
+    let program = PermissionProgram::from_file(
+        program_id,
+        so_path,
+        program_control(&program_id),
+        twice_permissioned::DEFAULT_LOCK_THRESHOLD as usize,
+    )
+    .unwrap();
+
+    let report = run(&program).await;
+    println!("{}", report);
+    assert!(report.passed());
+}
+
+#[tokio::test]
+async fn twice_permissioned_passes_with_lockers_past_the_threshold() {
+    // the second delegate's approval replaces the first's, and its lock freezes the token
+    // before the owner locks
+    let so_path = deployed_program_path("twice_permissioned");
+    let program_id = twice_permissioned::id();
+    let program = PermissionProgram::from_file(
+        program_id,
+        so_path,
+        program_control(&program_id),
+        twice_permissioned::DEFAULT_LOCK_THRESHOLD as usize + 1,
+    )
+    .unwrap();
+
+    let report = run(&program).await;
+    println!("{}", report);
+    assert!(report.passed());
+}
@@ -6,7 +6,8 @@ use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 
 use anchor_lang::solana_program::{
     hash,
-    program::{get_return_data, invoke},
+    program::{get_return_data, invoke, invoke_signed},
+    program_option::COption,
 };
 
 #[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize)]
@@ -16,6 +17,15 @@ pub struct IAccountMeta {
     pub writable: bool,
 }
 
+/// Name, mutability and signer flag of an account an interface function receives.
+/// Preflight functions receive the same accounts, but never as signers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IAccountSpec {
+    pub name: &'static str,
+    pub writable: bool,
+    pub signer: bool,
+}
+
 #[derive(Debug, Clone, AnchorDeserialize, AnchorSerialize)]
 pub struct PreflightAccounts {
     pub accounts: Vec<IAccountMeta>,
@@ -47,6 +57,58 @@ impl PreflightAccounts {
     }
 }
 
+/// Splits remaining accounts into one partition per interface call, so an instruction
+/// making several calls hands each one exactly its own accounts.
+/// `lens[i]` is the number of accounts of the i-th call, partitions are laid out back to back
+/// and must cover the remaining accounts exactly.
+pub fn partition_accounts<'a, 'info>(
+    remaining_accounts: &'a [AccountInfo<'info>],
+    lens: &[u8],
+) -> Result<Vec<&'a [AccountInfo<'info>]>> {
+    let total: usize = lens.iter().map(|len| *len as usize).sum();
+    if total != remaining_accounts.len() {
+        msg!(
+            "partitions cover {:?} accounts, got {:?}",
+            total,
+            remaining_accounts.len()
+        );
+        return Err(ProgramError::NotEnoughAccountKeys.into());
+    }
+
+    let mut partitions = Vec::with_capacity(lens.len());
+    let mut rest = remaining_accounts;
+    for len in lens {
+        let (partition, tail) = rest.split_at(*len as usize);
+        partitions.push(partition);
+        rest = tail;
+    }
+    Ok(partitions)
+}
+
+/// Client side counterpart of `partition_accounts`: concatenates the accounts of each
+/// interface call and returns the partition lengths to pass alongside them.
+pub fn build_partitions(partitions: Vec<Vec<AccountMeta>>) -> Result<(Vec<u8>, Vec<AccountMeta>)> {
+    let mut lens = Vec::with_capacity(partitions.len());
+    let mut accounts = vec![];
+    for partition in partitions {
+        lens.push(u8::try_from(partition.len()).map_err(|_| ProgramError::InvalidArgument)?);
+        accounts.extend(partition);
+    }
+    Ok((lens, accounts))
+}
+
+/// Seed of the PDA permission programs freeze and thaw token accounts with
+pub const STATIC_PREFIX: &str = "static";
+
+/// Whether `perm_program` is the freeze authority of `mint` through its `"static"` PDA.
+/// Programs that sign as a token's delegate in calls to `perm_program` check this first,
+/// any other program would get the delegate's signature and could move the token.
+pub fn freezes_mint(perm_program: &Pubkey, mint: &Mint) -> bool {
+    let (program_control, _) =
+        Pubkey::find_program_address(&[STATIC_PREFIX.as_bytes()], perm_program);
+    mint.freeze_authority == COption::Some(program_control)
+}
+
 pub fn get_interface_accounts(program_key: &Pubkey) -> Result<PreflightAccounts> {
     let (key, program_data) = get_return_data().unwrap();
     assert_eq!(key, *program_key);
@@ -69,24 +131,199 @@ pub fn call<
     ctx: CpiContext<'_, '_, '_, 'info, C1>,
     log_info: bool,
 ) -> Result<()> {
+    call_with_nonce(ix_name, ctx, None, log_info)
+}
+
+/// Same as `call`, but passes an idempotency `nonce` to the interface function.
+/// The nonce is always appended to the instruction data as a borsh `Option<u64>`,
+/// permission programs that don't take it simply ignore the trailing bytes.
+pub fn call_with_nonce<
+    'info,
+    C1: ToAccountInfos<'info> + ToAccountMetas + ToTargetProgram<'info, TargetCtx<'info> = C2>,
+    C2: ToAccountInfos<'info> + ToAccountMetas,
+>(
+    ix_name: String,
+    ctx: CpiContext<'_, '_, '_, 'info, C1>,
+    nonce: Option<u64>,
+    log_info: bool,
+) -> Result<()> {
+    // execute against the target context so preflight sees the same accounts as the interface function
+    msg!("Convert into target context");
+    let cpi_ctx: CpiContext<C2> = ctx
+        .accounts
+        .to_target_context(ctx.remaining_accounts.to_vec())
+        .with_signer(ctx.signer_seeds);
+
     msg!("Preflight");
-    // preflight
-    call_preflight_interface_function(ix_name.clone(), &ctx)?;
+    let additional_interface_accounts = preflight(&ix_name, &cpi_ctx)?;
 
-    msg!("Parse return data");
-    // parse cpi return data
-    let additional_interface_accounts = get_interface_accounts(&ctx.accounts.to_target_program())?;
+    msg!("Execute {}", &ix_name);
+    call_interface_function(
+        ix_name.clone(),
+        cpi_ctx,
+        &[],
+        additional_interface_accounts,
+        &nonce.try_to_vec()?,
+        log_info,
+    )?;
+    Ok(())
+}
 
-    // execute
-    msg!("Convert into target context");
+/// Runs `preflight_{ix_name}` on the context's program and returns the accounts it resolved.
+/// The context's remaining accounts are passed after the base accounts, so middleware
+/// permission programs can find and forward to the program they wrap.
+pub fn preflight<'info, T: ToAccountInfos<'info> + ToAccountMetas>(
+    ix_name: &str,
+    ctx: &CpiContext<'_, '_, '_, 'info, T>,
+) -> Result<PreflightAccounts> {
+    call_preflight_interface_function(ix_name.to_string(), ctx, &ctx.remaining_accounts)?;
+    get_interface_accounts(&ctx.program.key())
+}
+
+/// Same as `call_with_nonce`, then fails the transaction if the permission program left
+/// any token account in a state `post_conditions` doesn't expect
+pub fn call_with_post_conditions<
+    'info,
+    C1: ToAccountInfos<'info> + ToAccountMetas + ToTargetProgram<'info, TargetCtx<'info> = C2>,
+    C2: ToAccountInfos<'info> + ToAccountMetas,
+>(
+    ix_name: String,
+    ctx: CpiContext<'_, '_, '_, 'info, C1>,
+    nonce: Option<u64>,
+    post_conditions: &PostConditions<'info>,
+    log_info: bool,
+) -> Result<()> {
+    call_with_nonce(ix_name, ctx, nonce, log_info)?;
+    // nothing was executed when only logging
+    if !log_info {
+        post_conditions.verify()?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expectation {
+    Frozen,
+    Balance(u64),
+    Owner(Pubkey),
+}
+
+/// Token account state an interface call must leave behind, so callers don't have to trust
+/// that the permission program did what it claims.
+/// Balance and owner expectations snapshot the account when they're added, add them before the call.
+#[derive(Default)]
+pub struct PostConditions<'info> {
+    expectations: Vec<(AccountInfo<'info>, Expectation)>,
+}
+
+impl<'info> PostConditions<'info> {
+    pub fn expect_frozen(mut self, token: &AccountInfo<'info>) -> Self {
+        self.expectations.push((token.clone(), Expectation::Frozen));
+        self
+    }
+
+    pub fn expect_balance_unchanged(mut self, token: &AccountInfo<'info>) -> Result<Self> {
+        let amount = token_state(token)?.amount;
+        self.expectations.push((token.clone(), Expectation::Balance(amount)));
+        Ok(self)
+    }
+
+    pub fn expect_owner_unchanged(mut self, token: &AccountInfo<'info>) -> Result<Self> {
+        let owner = token_state(token)?.owner;
+        self.expectations.push((token.clone(), Expectation::Owner(owner)));
+        Ok(self)
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        for (token, expectation) in self.expectations.iter() {
+            let state = token_state(token)?;
+            match *expectation {
+                Expectation::Frozen => {
+                    if !state.is_frozen() {
+                        msg!("token account not frozen: {:?}", token.key());
+                        return err!(InterfaceError::TokenNotFrozen);
+                    }
+                }
+                Expectation::Balance(amount) => {
+                    if state.amount != amount {
+                        msg!(
+                            "token account balance changed: {:?}, {:?} -> {:?}",
+                            token.key(),
+                            amount,
+                            state.amount
+                        );
+                        return err!(InterfaceError::BalanceChanged);
+                    }
+                }
+                Expectation::Owner(owner) => {
+                    if state.owner != owner {
+                        msg!(
+                            "token account owner changed: {:?}, {:?} -> {:?}",
+                            token.key(),
+                            owner,
+                            state.owner
+                        );
+                        return err!(InterfaceError::OwnerChanged);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn token_state(token: &AccountInfo) -> Result<TokenAccount> {
+    TokenAccount::try_deserialize(&mut &token.try_borrow_data()?[..])
+}
+
+// offset past the program errors of callers so both can be told apart
+#[error_code(offset = 9000)]
+pub enum InterfaceError {
+    #[msg("Interface call left the token account unfrozen")]
+    TokenNotFrozen,
+    #[msg("Interface call changed the token account balance")]
+    BalanceChanged,
+    #[msg("Interface call changed the token account owner")]
+    OwnerChanged,
+}
+
+/// Calls a batch interface function (e.g. `lock_many`) on every (token, mint) pair in
+/// `token_accounts`. The pairs are passed to both preflight and execution directly
+/// after the base accounts, followed by the accounts returned by preflight.
+pub fn call_many<
+    'info,
+    C1: ToAccountInfos<'info> + ToAccountMetas + ToTargetProgram<'info, TargetCtx<'info> = C2>,
+    C2: ToAccountInfos<'info> + ToAccountMetas,
+>(
+    ix_name: String,
+    ctx: CpiContext<'_, '_, '_, 'info, C1>,
+    token_accounts: &[AccountInfo<'info>],
+    log_info: bool,
+) -> Result<()> {
+    if token_accounts.is_empty() || token_accounts.len() % 2 != 0 {
+        msg!("expected (token, mint) pairs, got {:?} accounts", token_accounts.len());
+        return Err(ProgramError::NotEnoughAccountKeys.into());
+    }
+
+    // execute against the target context so batch accounts directly follow the base accounts
     let cpi_ctx: CpiContext<C2> = ctx
         .accounts
-        .to_target_context(ctx.remaining_accounts.to_vec());
+        .to_target_context(ctx.remaining_accounts.to_vec())
+        .with_signer(ctx.signer_seeds);
+
+    msg!("Preflight");
+    call_preflight_interface_function(ix_name.clone(), &cpi_ctx, token_accounts)?;
+
+    msg!("Parse return data");
+    let additional_interface_accounts = get_interface_accounts(&ctx.accounts.to_target_program())?;
+
     msg!("Execute {}", &ix_name);
     call_interface_function(
         ix_name.clone(),
         cpi_ctx,
+        token_accounts,
         additional_interface_accounts,
+        &[],
         log_info,
     )?;
     Ok(())
@@ -95,36 +332,56 @@ pub fn call<
 fn call_preflight_interface_function<'info, T: ToAccountInfos<'info> + ToAccountMetas>(
     function_name: String,
     ctx: &CpiContext<'_, '_, '_, 'info, T>,
+    batch_accounts: &[AccountInfo<'info>],
 ) -> Result<()> {
     // setup
     let ix_data: Vec<u8> = hash::hash(format!("global:preflight_{}", &function_name).as_bytes())
         .to_bytes()
         .to_vec();
-    let ix_account_metas = ctx.accounts.to_account_metas(Some(false));
+    let mut ix_account_metas = ctx.accounts.to_account_metas(Some(false));
+    ix_account_metas.extend(
+        batch_accounts
+            .iter()
+            .map(|acc| AccountMeta::new_readonly(acc.key(), false)),
+    );
     let ix = anchor_lang::solana_program::instruction::Instruction {
         program_id: ctx.program.key(),
         accounts: ix_account_metas,
         data: ix_data,
     };
 
+    let mut ix_ais: Vec<AccountInfo> = ctx.accounts.to_account_infos();
+    ix_ais.extend_from_slice(batch_accounts);
+
     // execute
-    invoke(&ix, &ctx.accounts.to_account_infos())?;
+    invoke(&ix, &ix_ais)?;
     Ok(())
 }
 
 fn call_interface_function<'info, T: ToAccountInfos<'info> + ToAccountMetas>(
     function_name: String,
     ctx: CpiContext<'_, '_, '_, 'info, T>,
+    batch_accounts: &[AccountInfo<'info>],
     additional_interface_accounts: PreflightAccounts,
+    args: &[u8],
     log_info: bool,
 ) -> Result<()> {
     // setup
     let remaining_accounts = ctx.remaining_accounts.to_vec();
 
-    let ix_data: Vec<u8> = hash::hash(format!("global:{}", &function_name).as_bytes())
-        .to_bytes()
+    // args are decoded right after the 8 byte sighash, so the rest of the hash must be dropped
+    let mut ix_data: Vec<u8> = hash::hash(format!("global:{}", &function_name).as_bytes())
+        .to_bytes()[..8]
         .to_vec();
+    ix_data.extend_from_slice(args);
     let mut ix_account_metas = ctx.accounts.to_account_metas(None);
+    ix_account_metas.extend(batch_accounts.iter().map(|acc| {
+        if acc.is_writable {
+            AccountMeta::new(acc.key(), false)
+        } else {
+            AccountMeta::new_readonly(acc.key(), false)
+        }
+    }));
     ix_account_metas.append(
         additional_interface_accounts
             .accounts
@@ -147,6 +404,7 @@ fn call_interface_function<'info, T: ToAccountInfos<'info> + ToAccountMetas>(
     };
 
     let mut ix_ais: Vec<AccountInfo> = ctx.accounts.to_account_infos();
+    ix_ais.extend_from_slice(batch_accounts);
     msg!("IX accounts: {:?}", &ix_ais.len());
     ix_ais.extend_from_slice(
         &mut additional_interface_accounts
@@ -166,8 +424,8 @@ fn call_interface_function<'info, T: ToAccountInfos<'info> + ToAccountMetas>(
             )
         });
     } else {
-        // execute
-        invoke(&ix, &ix_ais)?;
+        // execute, signing for the caller's PDAs if it passed signer seeds
+        invoke_signed(&ix, &ix_ais, ctx.signer_seeds)?;
     }
 
     Ok(())
@@ -184,6 +442,17 @@ pub struct ILock<'info> {
     pub token_program: AccountInfo<'info>,
 }
 
+impl<'info> ILock<'info> {
+    /// Accounts every permission program's `lock` must start with, in order
+    pub const ACCOUNTS: &'static [IAccountSpec] = &[
+        IAccountSpec { name: "token", writable: true, signer: false },
+        IAccountSpec { name: "mint", writable: false, signer: false },
+        IAccountSpec { name: "delegate", writable: false, signer: true },
+        IAccountSpec { name: "payer", writable: true, signer: true },
+        IAccountSpec { name: "token_program", writable: false, signer: false },
+    ];
+}
+
 #[derive(Accounts)]
 pub struct TILock<'info> {
     #[account(mut)]
@@ -256,6 +525,16 @@ pub struct IUnlock<'info> {
     pub token_program: AccountInfo<'info>,
 }
 
+impl<'info> IUnlock<'info> {
+    /// Accounts every permission program's `unlock` must start with, in order
+    pub const ACCOUNTS: &'static [IAccountSpec] = &[
+        IAccountSpec { name: "token", writable: true, signer: false },
+        IAccountSpec { name: "mint", writable: false, signer: false },
+        IAccountSpec { name: "delegate", writable: false, signer: true },
+        IAccountSpec { name: "token_program", writable: false, signer: false },
+    ];
+}
+
 impl<'info> ToTargetProgram<'info> for TIUnlock<'info> {
     type TargetCtx<'a> = IUnlock<'a>;
 
@@ -280,3 +559,82 @@ impl<'info> ToTargetProgram<'info> for TIUnlock<'info> {
             .with_remaining_accounts(remaining_accounts)
     }
 }
+
+#[derive(Accounts)]
+pub struct ILockMany<'info> {
+    pub delegate: AccountInfo<'info>,
+    #[account(mut)]
+    pub payer: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TILockMany<'info> {
+    pub delegate: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    /// CHECK: permission program
+    pub perm_program: AccountInfo<'info>,
+}
+
+impl<'info> ToTargetProgram<'info> for TILockMany<'info> {
+    type TargetCtx<'a> = ILockMany<'a>;
+
+    fn to_target_program(&self) -> Pubkey {
+        self.perm_program.key()
+    }
+    fn get_target_program(&self) -> AccountInfo<'info> {
+        self.perm_program.clone()
+    }
+
+    fn to_target_context(
+        &self,
+        remaining_accounts: Vec<AccountInfo<'info>>,
+    ) -> CpiContext<'_, '_, '_, 'info, Self::TargetCtx<'info>> {
+        let inner = ILockMany {
+            delegate: self.delegate.to_account_info(),
+            payer: self.payer.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+        };
+        CpiContext::new(self.get_target_program(), inner)
+            .with_remaining_accounts(remaining_accounts)
+    }
+}
+
+#[derive(Accounts)]
+pub struct IUnlockMany<'info> {
+    pub delegate: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TIUnlockMany<'info> {
+    pub delegate: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    /// CHECK: permission program
+    pub perm_program: AccountInfo<'info>,
+}
+
+impl<'info> ToTargetProgram<'info> for TIUnlockMany<'info> {
+    type TargetCtx<'a> = IUnlockMany<'a>;
+
+    fn to_target_program(&self) -> Pubkey {
+        self.perm_program.key()
+    }
+    fn get_target_program(&self) -> AccountInfo<'info> {
+        self.perm_program.clone()
+    }
+
+    fn to_target_context(
+        &self,
+        remaining_accounts: Vec<AccountInfo<'info>>,
+    ) -> CpiContext<'_, '_, '_, 'info, Self::TargetCtx<'info>> {
+        let inner = IUnlockMany {
+            delegate: self.delegate.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+        };
+        CpiContext::new(self.get_target_program(), inner)
+            .with_remaining_accounts(remaining_accounts)
+    }
+}
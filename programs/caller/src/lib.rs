@@ -1,105 +1,322 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program_option::COption;
 use anchor_spl::token_interface::{
     transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked,
 };
 
-use interface::{call, TILock as _TILock, TIUnlock as _TIUnlock};
+use interface::{
+    call_many, call_with_nonce, call_with_post_conditions, partition_accounts, PostConditions,
+    TILock as _TILock, TILockMany as _TILockMany, TIUnlock as _TIUnlock,
+    TIUnlockMany as _TIUnlockMany,
+};
 declare_id!("6Dmq9ijrYZio9ny6PezemaWe3kcs7qbJ8sB78LHgQDeY");
 
 #[program]
 pub mod caller {
     use super::*;
 
-    pub fn lock<'info>(ctx: Context<'_, '_, '_, 'info, TILock<'info>>) -> Result<()> {
+    /// With `use_authority` the delegate is the caller's authority PDA of `payer`
+    /// (see `tfer`) instead of an external signer.
+    /// With `expect_frozen` the lock fails unless the permission program froze the token,
+    /// leave it off for permission programs that only freeze past a threshold of lockers.
+    pub fn lock<'info>(
+        ctx: Context<'_, '_, '_, 'info, TILock<'info>>,
+        nonce: Option<u64>,
+        use_authority: bool,
+        expect_frozen: bool,
+    ) -> Result<()> {
+        let payer = ctx.accounts.payer.key();
+        let (delegate, bump) = delegate_signer(&ctx.accounts.delegate, &payer, use_authority)?;
+        let bump_seed = [bump.unwrap_or_default()];
+        let seeds: &[&[u8]] = &[AUTHORITY_PREFIX.as_bytes(), payer.as_ref(), &bump_seed];
+        let signer = [seeds];
+        let signer_seeds: &[&[&[u8]]] = if bump.is_some() { &signer } else { &[] };
+
         let cvt_ctx = CpiContext::new(
             ctx.accounts.perm_program.clone(),
             _TILock {
                 token: ctx.accounts.token.clone(),
                 mint: ctx.accounts.mint.clone(),
-                delegate: ctx.accounts.delegate.clone(),
+                delegate,
                 payer: ctx.accounts.payer.clone(),
                 token_program: ctx.accounts.token_program.clone(),
                 perm_program: ctx.accounts.perm_program.clone(),
             },
         )
+        .with_signer(signer_seeds)
         .with_remaining_accounts(ctx.remaining_accounts.to_vec());
 
-        call("lock".to_string(), cvt_ctx, false)?;
+        let post_conditions = post_conditions(&ctx.accounts.token.to_account_info(), expect_frozen)?;
+        call_with_post_conditions("lock".to_string(), cvt_ctx, nonce, &post_conditions, false)?;
         Ok(())
     }
 
-    pub fn unlock<'info>(ctx: Context<'_, '_, '_, 'info, TIUnlock<'info>>) -> Result<()> {
+    /// With `use_authority` the delegate is the caller's authority PDA of `operator`,
+    /// which must be the `payer` the token was locked with.
+    pub fn unlock<'info>(
+        ctx: Context<'_, '_, '_, 'info, TIUnlock<'info>>,
+        nonce: Option<u64>,
+        use_authority: bool,
+    ) -> Result<()> {
+        let operator = ctx.accounts.operator.key();
+        let (delegate, bump) = delegate_signer(&ctx.accounts.delegate, &operator, use_authority)?;
+        let bump_seed = [bump.unwrap_or_default()];
+        let seeds: &[&[u8]] = &[AUTHORITY_PREFIX.as_bytes(), operator.as_ref(), &bump_seed];
+        let signer = [seeds];
+        let signer_seeds: &[&[&[u8]]] = if bump.is_some() { &signer } else { &[] };
+
         let cvt_ctx = CpiContext::new(
             ctx.accounts.perm_program.clone(),
             _TIUnlock {
                 token: ctx.accounts.token.clone(),
                 mint: ctx.accounts.mint.clone(),
-                delegate: ctx.accounts.delegate.clone(),
+                delegate,
                 token_program: ctx.accounts.token_program.clone(),
                 perm_program: ctx.accounts.perm_program.clone(),
             },
         )
+        .with_signer(signer_seeds)
         .with_remaining_accounts(ctx.remaining_accounts.to_vec());
 
-        call("unlock".to_string(), cvt_ctx, false)?;
+        call_with_nonce("unlock".to_string(), cvt_ctx, nonce, false)?;
+        Ok(())
+    }
+
+    /// The first `2 * count` remaining accounts are the (token, mint) pairs to lock,
+    /// the rest are the accounts resolved by the permission program's preflight
+    pub fn lock_many<'info>(
+        ctx: Context<'_, '_, '_, 'info, TILockMany<'info>>,
+        count: u8,
+    ) -> Result<()> {
+        let (token_accounts, interface_accounts) =
+            split_token_accounts(ctx.remaining_accounts, count)?;
+        let cvt_ctx = CpiContext::new(
+            ctx.accounts.perm_program.clone(),
+            _TILockMany {
+                delegate: ctx.accounts.delegate.clone(),
+                payer: ctx.accounts.payer.clone(),
+                token_program: ctx.accounts.token_program.clone(),
+                perm_program: ctx.accounts.perm_program.clone(),
+            },
+        )
+        .with_remaining_accounts(interface_accounts.to_vec());
+
+        call_many("lock_many".to_string(), cvt_ctx, token_accounts, false)?;
         Ok(())
     }
 
+    /// Same remaining accounts layout as `lock_many`, `use_authority` as in `unlock`
+    pub fn unlock_many<'info>(
+        ctx: Context<'_, '_, '_, 'info, TIUnlockMany<'info>>,
+        count: u8,
+        use_authority: bool,
+    ) -> Result<()> {
+        let (token_accounts, interface_accounts) =
+            split_token_accounts(ctx.remaining_accounts, count)?;
+        let operator = ctx.accounts.operator.key();
+        let (delegate, bump) = delegate_signer(&ctx.accounts.delegate, &operator, use_authority)?;
+        let bump_seed = [bump.unwrap_or_default()];
+        let seeds: &[&[u8]] = &[AUTHORITY_PREFIX.as_bytes(), operator.as_ref(), &bump_seed];
+        let signer = [seeds];
+        let signer_seeds: &[&[&[u8]]] = if bump.is_some() { &signer } else { &[] };
+
+        let cvt_ctx = CpiContext::new(
+            ctx.accounts.perm_program.clone(),
+            _TIUnlockMany {
+                delegate,
+                token_program: ctx.accounts.token_program.clone(),
+                perm_program: ctx.accounts.perm_program.clone(),
+            },
+        )
+        .with_signer(signer_seeds)
+        .with_remaining_accounts(interface_accounts.to_vec());
+
+        call_many("unlock_many".to_string(), cvt_ctx, token_accounts, false)?;
+        Ok(())
+    }
+
+    /// Remaining accounts hold the unlock accounts of `source`, the lock accounts of `dest`
+    /// and the lock accounts of `source`, `account_partitions` gives the number of accounts of each.
+    /// `source` is relocked when it still holds a balance after the transfer and the delegate
+    /// still has authority over it, otherwise the last partition is ignored and may be empty.
+    /// An SPL delegate whose allowance the transfer used up is cleared by the token program,
+    /// so the rest of the balance is the owner's again and stays unlocked.
+    ///
+    /// With `use_authority` the delegate is the caller's authority PDA of `payer`, e.g. an
+    /// escrow the owner approved, and the caller signs for it in every CPI.
+    /// `expect_frozen` applies to locking `dest` and relocking `source`, as in `lock`.
+    ///
+    /// `dest` is locked with `lock` when the delegate owns it or is its SPL delegate, otherwise
+    /// with `lock_on_receive`, which fails unless the recipient opted in to the delegate
+    /// locking it. The lock accounts of `dest` must be resolved for the matching function.
     pub fn tfer<'info>(
         ctx: Context<'_, '_, '_, 'info, Transfer<'info>>,
         amount: u64,
+        account_partitions: [u8; 3],
+        use_authority: bool,
+        expect_frozen: bool,
     ) -> Result<()> {
+        let partitions = partition_accounts(ctx.remaining_accounts, &account_partitions)?;
+        let (unlock_accounts, lock_accounts, relock_accounts) =
+            (partitions[0], partitions[1], partitions[2]);
+
+        let payer = ctx.accounts.payer.key();
+        let (delegate, bump) = delegate_signer(&ctx.accounts.delegate, &payer, use_authority)?;
+        let bump_seed = [bump.unwrap_or_default()];
+        let seeds: &[&[u8]] = &[AUTHORITY_PREFIX.as_bytes(), payer.as_ref(), &bump_seed];
+        let signer = [seeds];
+        let signer_seeds: &[&[&[u8]]] = if bump.is_some() { &signer } else { &[] };
+
         let cvt_ctx = CpiContext::new(
             ctx.accounts.perm_program.clone(),
             _TIUnlock {
                 token: ctx.accounts.source.clone(),
                 mint: ctx.accounts.mint.clone(),
-                delegate: ctx.accounts.delegate.clone(),
+                delegate: delegate.clone(),
                 token_program: ctx.accounts.token_program.clone(),
                 perm_program: ctx.accounts.perm_program.clone(),
             },
         )
-        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
-        call("unlock".to_string(), cvt_ctx, false)?;
+        .with_signer(signer_seeds)
+        .with_remaining_accounts(unlock_accounts.to_vec());
+        let source = ctx.accounts.source.to_account_info();
+        call_with_post_conditions(
+            "unlock".to_string(),
+            cvt_ctx,
+            None,
+            &post_conditions(&source, false)?,
+            false,
+        )?;
 
         transfer_checked(
-            CpiContext::new(
+            CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
                 TransferChecked {
                     from: ctx.accounts.source.to_account_info(),
                     to: ctx.accounts.dest.to_account_info(),
                     mint: ctx.accounts.mint.to_account_info(),
-                    authority: ctx.accounts.delegate.to_account_info(),
+                    authority: delegate.to_account_info(),
                 },
+                signer_seeds,
             ),
             amount,
             ctx.accounts.mint.decimals,
         )?;
 
+        let dest = &ctx.accounts.dest;
+        let delegate_key = delegate.key();
+        let lock_dest = if dest.owner == delegate_key || dest.delegate == COption::Some(delegate_key) {
+            "lock"
+        } else {
+            "lock_on_receive"
+        };
+        let dest_post_conditions = post_conditions(&ctx.accounts.dest.to_account_info(), expect_frozen)?;
         let cvt_ctx = CpiContext::new(
             ctx.accounts.perm_program.clone(),
             _TILock {
                 token: ctx.accounts.dest.clone(),
                 mint: ctx.accounts.mint.clone(),
-                delegate: ctx.accounts.delegate.clone(),
+                delegate: delegate.clone(),
                 payer: ctx.accounts.payer.clone(),
                 token_program: ctx.accounts.token_program.clone(),
                 perm_program: ctx.accounts.perm_program.clone(),
             },
         )
-        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
-        call("lock".to_string(), cvt_ctx, false)?;
+        .with_signer(signer_seeds)
+        .with_remaining_accounts(lock_accounts.to_vec());
+        call_with_post_conditions(lock_dest.to_string(), cvt_ctx, None, &dest_post_conditions, false)?;
+
+        // a partial transfer must not leave the rest of the balance unprotected
+        ctx.accounts.source.reload()?;
+        let source_state = &ctx.accounts.source;
+        let still_delegate =
+            source_state.owner == delegate_key || source_state.delegate == COption::Some(delegate_key);
+        if source_state.amount > 0 && still_delegate {
+            let source_post_conditions = post_conditions(&source, expect_frozen)?;
+            let cvt_ctx = CpiContext::new(
+                ctx.accounts.perm_program.clone(),
+                _TILock {
+                    token: ctx.accounts.source.clone(),
+                    mint: ctx.accounts.mint.clone(),
+                    delegate,
+                    payer: ctx.accounts.payer.clone(),
+                    token_program: ctx.accounts.token_program.clone(),
+                    perm_program: ctx.accounts.perm_program.clone(),
+                },
+            )
+            .with_signer(signer_seeds)
+            .with_remaining_accounts(relock_accounts.to_vec());
+            call_with_post_conditions("lock".to_string(), cvt_ctx, None, &source_post_conditions, false)?;
+        }
 
         Ok(())
     }
 }
 
+pub const AUTHORITY_PREFIX: &'static str = "authority";
+
+/// Returns the delegate as a signer. With `use_authority` the delegate must be the caller's
+/// authority PDA of `operator`, which the caller signs for with the returned bump,
+/// otherwise the delegate must have signed the transaction.
+fn delegate_signer<'info>(
+    delegate: &AccountInfo<'info>,
+    operator: &Pubkey,
+    use_authority: bool,
+) -> Result<(Signer<'info>, Option<u8>)> {
+    if !use_authority {
+        return Ok((Signer::try_from(delegate)?, None));
+    }
+    let (authority, bump) = Pubkey::find_program_address(
+        &[AUTHORITY_PREFIX.as_bytes(), operator.as_ref()],
+        &crate::id(),
+    );
+    require_keys_eq!(delegate.key(), authority, CallerError::InvalidAuthority);
+    // signer privileges come from the seeds passed along with every CPI
+    let mut delegate = delegate.clone();
+    delegate.is_signer = true;
+    Ok((Signer::try_from(&delegate)?, Some(bump)))
+}
+
+/// Interface calls on `token` must leave it with its owner and balance,
+/// and with `expect_frozen` frozen
+fn post_conditions<'info>(
+    token: &AccountInfo<'info>,
+    expect_frozen: bool,
+) -> Result<PostConditions<'info>> {
+    let post_conditions = PostConditions::default()
+        .expect_balance_unchanged(token)?
+        .expect_owner_unchanged(token)?;
+    Ok(if expect_frozen {
+        post_conditions.expect_frozen(token)
+    } else {
+        post_conditions
+    })
+}
+
+#[error_code]
+pub enum CallerError {
+    #[msg("Delegate is not the caller's authority PDA of the payer")]
+    InvalidAuthority,
+}
+
+fn split_token_accounts<'a, 'info>(
+    remaining_accounts: &'a [AccountInfo<'info>],
+    count: u8,
+) -> Result<(&'a [AccountInfo<'info>], &'a [AccountInfo<'info>])> {
+    let split = 2 * count as usize;
+    if count == 0 || remaining_accounts.len() < split {
+        return Err(ProgramError::NotEnoughAccountKeys.into());
+    }
+    Ok(remaining_accounts.split_at(split))
+}
+
 #[derive(Accounts)]
 pub struct TILock<'info> {
     #[account(mut)]
     token: InterfaceAccount<'info, TokenAccount>,
     mint: InterfaceAccount<'info, Mint>,
-    delegate: Signer<'info>,
+    /// CHECK: signer, or the caller's authority PDA checked in `delegate_signer`
+    delegate: AccountInfo<'info>,
     #[account(mut)]
     payer: Signer<'info>,
     token_program: Interface<'info, TokenInterface>,
@@ -112,7 +329,29 @@ pub struct TIUnlock<'info> {
     #[account(mut)]
     token: InterfaceAccount<'info, TokenAccount>,
     mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: signer, or the caller's authority PDA checked in `delegate_signer`
+    delegate: AccountInfo<'info>,
+    operator: Signer<'info>,
+    token_program: Interface<'info, TokenInterface>,
+    /// CHECK: permission program
+    perm_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TILockMany<'info> {
     delegate: Signer<'info>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    token_program: Interface<'info, TokenInterface>,
+    /// CHECK: permission program
+    perm_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct TIUnlockMany<'info> {
+    /// CHECK: signer, or the caller's authority PDA checked in `delegate_signer`
+    delegate: AccountInfo<'info>,
+    operator: Signer<'info>,
     token_program: Interface<'info, TokenInterface>,
     /// CHECK: permission program
     perm_program: AccountInfo<'info>,
@@ -125,13 +364,11 @@ pub struct Transfer<'info> {
     #[account(mut)]
     dest: InterfaceAccount<'info, TokenAccount>,
     mint: InterfaceAccount<'info, Mint>,
-    delegate: Signer<'info>,
+    /// CHECK: signer, or the caller's authority PDA checked in `delegate_signer`
+    delegate: AccountInfo<'info>,
     #[account(mut)]
     payer: Signer<'info>,
     token_program: Interface<'info, TokenInterface>,
-    /// CHECK: fuck
-    #[account(seeds=["hello".as_ref()], bump)]
-    penguin: AccountInfo<'info>,
     /// CHECK: permission program
     perm_program: AccountInfo<'info>,
 }
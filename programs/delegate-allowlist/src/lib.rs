@@ -0,0 +1,148 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use interface::{IAccountMeta, PreflightAccounts};
+
+declare_id!("Ct9jb4VDZxdTrnTi8PFKiJuTxH3NgpLsBajiWEShrXNg");
+
+/// Permission program that approves locks by the delegates on a per-mint allowlist.
+///
+/// It never freezes or thaws the token account, so it can be chained by `permission_chain`
+/// next to the one program holding the mint's freeze authority, e.g. as a compliance check.
+/// Unlocks are always approved, the freezing program decides who may release its lock.
+#[program]
+pub mod delegate_allowlist {
+    use super::*;
+
+    pub fn init_allowlist(
+        ctx: Context<InitAllowlist>,
+        admin: Pubkey,
+        delegates: Vec<Pubkey>,
+    ) -> Result<()> {
+        let allowlist = &mut ctx.accounts.allowlist;
+        allowlist.admin = admin;
+        allowlist.set(delegates)
+    }
+
+    pub fn update_allowlist(ctx: Context<UpdateAllowlist>, delegates: Vec<Pubkey>) -> Result<()> {
+        ctx.accounts.allowlist.set(delegates)
+    }
+
+    pub fn preflight_lock(ctx: Context<ILock>) -> Result<Vec<u8>> {
+        Ok(PreflightAccounts { accounts: vec![
+            IAccountMeta { pubkey: allowlist_address(&ctx.accounts.mint.key()), signer: false, writable: false },
+        ] }.try_to_vec()?)
+    }
+
+    pub fn preflight_unlock(_ctx: Context<IUnlock>) -> Result<Vec<u8>> {
+        Ok(PreflightAccounts { accounts: vec![] }.try_to_vec()?)
+    }
+
+    pub fn lock(ctx: Context<Lock>) -> Result<()> {
+        require!(
+            ctx.accounts.allowlist.delegates.contains(&ctx.accounts.delegate.key()),
+            DelegateAllowlistError::DelegateNotAllowed
+        );
+        Ok(())
+    }
+
+    pub fn unlock(_ctx: Context<Unlock>) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub const ALLOWLIST_PREFIX: &'static str = "allowlist";
+pub const MAX_ALLOWLIST_DELEGATES: usize = 16;
+
+/// Delegates allowed to lock tokens of a mint
+#[account]
+pub struct Allowlist {
+    pub admin: Pubkey,
+    pub delegates: Vec<Pubkey>,
+}
+
+impl Allowlist {
+    pub const SPACE: usize = 8 + 32 + 4 + 32 * MAX_ALLOWLIST_DELEGATES;
+
+    pub fn set(&mut self, delegates: Vec<Pubkey>) -> Result<()> {
+        require!(
+            delegates.len() <= MAX_ALLOWLIST_DELEGATES,
+            DelegateAllowlistError::AllowlistFull
+        );
+        self.delegates = delegates;
+        Ok(())
+    }
+}
+
+pub fn allowlist_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[mint.as_ref(), ALLOWLIST_PREFIX.as_bytes()], &crate::id()).0
+}
+
+#[error_code]
+pub enum DelegateAllowlistError {
+    #[msg("Delegate is not on the mint's allowlist")]
+    DelegateNotAllowed,
+    #[msg("Allowlist exceeds the maximum number of delegates")]
+    AllowlistFull,
+}
+
+#[derive(Accounts)]
+pub struct InitAllowlist<'info> {
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = mint.mint_authority.is_some() && mint.mint_authority.unwrap() == mint_authority.key())]
+    mint_authority: Signer<'info>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    #[account(init, payer=payer, space=Allowlist::SPACE, seeds=[mint.key().as_ref(), ALLOWLIST_PREFIX.as_bytes()], bump)]
+    allowlist: Account<'info, Allowlist>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateAllowlist<'info> {
+    mint: InterfaceAccount<'info, Mint>,
+    admin: Signer<'info>,
+    #[account(mut, has_one=admin, seeds=[mint.key().as_ref(), ALLOWLIST_PREFIX.as_bytes()], bump)]
+    allowlist: Account<'info, Allowlist>,
+}
+
+#[derive(Accounts)]
+pub struct ILock<'info> {
+    token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: nil
+    delegate: AccountInfo<'info>,
+    /// CHECK: nil
+    payer: AccountInfo<'info>,
+    token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct IUnlock<'info> {
+    token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: nil
+    delegate: AccountInfo<'info>,
+    token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct Lock<'info> {
+    token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = token.owner == delegate.key() || token.delegate.is_some() && token.delegate.unwrap() == delegate.key())]
+    delegate: Signer<'info>,
+    /// CHECK: nil
+    payer: AccountInfo<'info>,
+    token_program: Interface<'info, TokenInterface>,
+    #[account(seeds=[mint.key().as_ref(), ALLOWLIST_PREFIX.as_bytes()], bump)]
+    allowlist: Account<'info, Allowlist>,
+}
+
+#[derive(Accounts)]
+pub struct Unlock<'info> {
+    token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    delegate: Signer<'info>,
+    token_program: Interface<'info, TokenInterface>,
+}
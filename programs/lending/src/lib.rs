@@ -0,0 +1,331 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{
+    self, Approve, Mint, Revoke, TokenAccount, TokenInterface, TransferChecked,
+};
+
+use interface::{
+    call, call_with_post_conditions, freezes_mint, PostConditions, TILock, TIUnlock,
+};
+
+declare_id!("4xxqvtmhMynk5H3tixTp9ijp24sUxnDNGibsZH8nKMGz");
+
+/// Collateralized SOL loans: collateral stays in the borrower's wallet, approved to and
+/// locked by the loan's authority PDA until the loan is repaid, or seized by the lender
+/// once the loan is overdue. Every loan has its own authority, so a permission program
+/// handed its signature can't reach the collateral of other loans.
+/// The authority locks the collateral exclusively, so the borrower can't add a lock of
+/// their own that would keep it frozen after the lender seizes it.
+#[program]
+pub mod lending {
+    use super::*;
+
+    /// Lends `principal` lamports against the whole balance of `token`, to be repaid
+    /// with `repay_amount` lamports within `duration` seconds. Fails unless the permission
+    /// program froze the collateral, a lock that only counts towards a quorum doesn't hold it.
+    /// Remaining accounts are the permission program's lock accounts
+    pub fn open_loan<'info>(
+        ctx: Context<'_, '_, '_, 'info, OpenLoan<'info>>,
+        principal: u64,
+        repay_amount: u64,
+        duration: i64,
+    ) -> Result<()> {
+        require!(duration > 0, LendingError::InvalidDuration);
+        let loan = &mut ctx.accounts.loan;
+        loan.borrower = ctx.accounts.borrower.key();
+        loan.lender = ctx.accounts.lender.key();
+        loan.token = ctx.accounts.token.key();
+        loan.mint = ctx.accounts.mint.key();
+        loan.perm_program = ctx.accounts.perm_program.key();
+        loan.collateral = ctx.accounts.token.amount;
+        loan.repay_amount = repay_amount;
+        loan.deadline = Clock::get()?
+            .unix_timestamp
+            .checked_add(duration)
+            .ok_or(LendingError::InvalidDuration)?;
+
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.lender.to_account_info(),
+                    to: ctx.accounts.borrower.to_account_info(),
+                },
+            ),
+            principal,
+        )?;
+
+        token_interface::approve(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Approve {
+                    to: ctx.accounts.token.to_account_info(),
+                    delegate: ctx.accounts.authority.to_account_info(),
+                    authority: ctx.accounts.borrower.to_account_info(),
+                },
+            ),
+            ctx.accounts.token.amount,
+        )?;
+
+        let loan = ctx.accounts.loan.key();
+        let bump = [*ctx.bumps.get("authority").unwrap()];
+        let seeds: &[&[u8]] = &[loan.as_ref(), AUTHORITY_PREFIX.as_bytes(), &bump];
+        let signer = [seeds];
+        let cvt_ctx = CpiContext::new_with_signer(
+            ctx.accounts.perm_program.clone(),
+            TILock {
+                token: ctx.accounts.token.clone(),
+                mint: ctx.accounts.mint.clone(),
+                delegate: authority_signer(&ctx.accounts.authority)?,
+                payer: ctx.accounts.borrower.clone(),
+                token_program: ctx.accounts.token_program.clone(),
+                perm_program: ctx.accounts.perm_program.clone(),
+            },
+            &signer,
+        )
+        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+        let post_conditions =
+            PostConditions::default().expect_frozen(&ctx.accounts.token.to_account_info());
+        call_with_post_conditions(
+            "lock_exclusive".to_string(),
+            cvt_ctx,
+            None,
+            &post_conditions,
+            false,
+        )
+    }
+
+    /// Pays back the lender and releases the collateral.
+    /// Remaining accounts are the permission program's unlock accounts
+    pub fn repay<'info>(ctx: Context<'_, '_, '_, 'info, Repay<'info>>) -> Result<()> {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.borrower.to_account_info(),
+                    to: ctx.accounts.lender.to_account_info(),
+                },
+            ),
+            ctx.accounts.loan.repay_amount,
+        )?;
+
+        unlock_collateral(
+            Collateral {
+                loan: ctx.accounts.loan.key(),
+                token: &ctx.accounts.token,
+                mint: &ctx.accounts.mint,
+                authority: &ctx.accounts.authority,
+                authority_bump: *ctx.bumps.get("authority").unwrap(),
+                token_program: &ctx.accounts.token_program,
+                perm_program: &ctx.accounts.perm_program,
+            },
+            ctx.remaining_accounts,
+        )?;
+
+        token_interface::revoke(CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Revoke {
+                source: ctx.accounts.token.to_account_info(),
+                authority: ctx.accounts.borrower.to_account_info(),
+            },
+        ))
+    }
+
+    /// Seizes the collateral of an overdue loan: unlocks it and moves it to the lender.
+    /// Remaining accounts are the permission program's unlock accounts
+    pub fn liquidate<'info>(ctx: Context<'_, '_, '_, 'info, Liquidate<'info>>) -> Result<()> {
+        require!(
+            Clock::get()?.unix_timestamp > ctx.accounts.loan.deadline,
+            LendingError::LoanNotOverdue
+        );
+        let collateral = Collateral {
+            loan: ctx.accounts.loan.key(),
+            token: &ctx.accounts.token,
+            mint: &ctx.accounts.mint,
+            authority: &ctx.accounts.authority,
+            authority_bump: *ctx.bumps.get("authority").unwrap(),
+            token_program: &ctx.accounts.token_program,
+            perm_program: &ctx.accounts.perm_program,
+        };
+        unlock_collateral(collateral, ctx.remaining_accounts)?;
+
+        let loan = ctx.accounts.loan.key();
+        let bump = [*ctx.bumps.get("authority").unwrap()];
+        let seeds: &[&[u8]] = &[loan.as_ref(), AUTHORITY_PREFIX.as_bytes(), &bump];
+        let signer = [seeds];
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.token.to_account_info(),
+                    to: ctx.accounts.lender_token.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    authority: ctx.accounts.authority.to_account_info(),
+                },
+                &signer,
+            ),
+            ctx.accounts.loan.collateral,
+            ctx.accounts.mint.decimals,
+        )
+    }
+}
+
+pub const LOAN_PREFIX: &'static str = "loan";
+pub const AUTHORITY_PREFIX: &'static str = "authority";
+
+#[account]
+pub struct Loan {
+    pub borrower: Pubkey,
+    pub lender: Pubkey,
+    pub token: Pubkey,
+    pub mint: Pubkey,
+    pub perm_program: Pubkey,
+    /// Amount of `token` locked as collateral
+    pub collateral: u64,
+    /// Lamports owed to the lender
+    pub repay_amount: u64,
+    /// Unix timestamp after which the loan can be liquidated
+    pub deadline: i64,
+}
+
+impl Loan {
+    pub const SPACE: usize = 8 + 32 * 5 + 8 * 3;
+}
+
+/// Accounts shared by `repay` and `liquidate` to unlock the collateral
+struct Collateral<'a, 'info> {
+    loan: Pubkey,
+    token: &'a InterfaceAccount<'info, TokenAccount>,
+    mint: &'a InterfaceAccount<'info, Mint>,
+    authority: &'a AccountInfo<'info>,
+    authority_bump: u8,
+    token_program: &'a Interface<'info, TokenInterface>,
+    perm_program: &'a AccountInfo<'info>,
+}
+
+fn unlock_collateral<'a, 'info>(
+    accounts: Collateral<'a, 'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    let bump = [accounts.authority_bump];
+    let seeds: &[&[u8]] = &[accounts.loan.as_ref(), AUTHORITY_PREFIX.as_bytes(), &bump];
+    let signer = [seeds];
+    let cvt_ctx = CpiContext::new_with_signer(
+        accounts.perm_program.clone(),
+        TIUnlock {
+            token: accounts.token.clone(),
+            mint: accounts.mint.clone(),
+            delegate: authority_signer(accounts.authority)?,
+            token_program: accounts.token_program.clone(),
+            perm_program: accounts.perm_program.clone(),
+        },
+        &signer,
+    )
+    .with_remaining_accounts(remaining_accounts.to_vec());
+    call("unlock".to_string(), cvt_ctx, false)
+}
+
+/// The loan authority as a signer, its signer privileges come from the seeds
+/// passed along with every CPI
+fn authority_signer<'info>(authority: &AccountInfo<'info>) -> Result<Signer<'info>> {
+    let mut authority = authority.clone();
+    authority.is_signer = true;
+    Signer::try_from(&authority)
+}
+
+#[error_code]
+pub enum LendingError {
+    #[msg("Loan duration must be positive")]
+    InvalidDuration,
+    #[msg("Token account holds no collateral")]
+    NoCollateral,
+    #[msg("Loan can only be liquidated after its deadline")]
+    LoanNotOverdue,
+    #[msg("Permission program is not the freeze authority of the mint")]
+    InvalidPermProgram,
+}
+
+#[derive(Accounts)]
+pub struct OpenLoan<'info> {
+    #[account(mut)]
+    borrower: Signer<'info>,
+    #[account(mut)]
+    lender: Signer<'info>,
+    #[account(
+        mut,
+        constraint = token.owner == borrower.key(),
+        constraint = token.mint == mint.key(),
+        constraint = token.amount > 0 @ LendingError::NoCollateral
+    )]
+    token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(init, payer=borrower, space=Loan::SPACE, seeds=[token.key().as_ref(), LOAN_PREFIX.as_bytes()], bump)]
+    loan: Account<'info, Loan>,
+    /// CHECK: PDA delegate of the loan's collateral
+    #[account(seeds=[loan.key().as_ref(), AUTHORITY_PREFIX.as_bytes()], bump)]
+    authority: AccountInfo<'info>,
+    token_program: Interface<'info, TokenInterface>,
+    /// CHECK: permission program, must freeze the mint's token accounts
+    #[account(constraint = freezes_mint(&perm_program.key(), &mint) @ LendingError::InvalidPermProgram)]
+    perm_program: AccountInfo<'info>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Repay<'info> {
+    #[account(mut)]
+    borrower: Signer<'info>,
+    /// CHECK: checked against the loan
+    #[account(mut)]
+    lender: AccountInfo<'info>,
+    #[account(mut)]
+    token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        close=borrower,
+        has_one=borrower,
+        has_one=lender,
+        has_one=token,
+        has_one=perm_program,
+        seeds=[token.key().as_ref(), LOAN_PREFIX.as_bytes()],
+        bump
+    )]
+    loan: Account<'info, Loan>,
+    /// CHECK: PDA delegate of the loan's collateral
+    #[account(seeds=[loan.key().as_ref(), AUTHORITY_PREFIX.as_bytes()], bump)]
+    authority: AccountInfo<'info>,
+    token_program: Interface<'info, TokenInterface>,
+    /// CHECK: permission program, checked against the loan
+    perm_program: AccountInfo<'info>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Liquidate<'info> {
+    lender: Signer<'info>,
+    /// CHECK: checked against the loan, gets the loan rent back
+    #[account(mut)]
+    borrower: AccountInfo<'info>,
+    #[account(mut)]
+    token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = lender_token.mint == mint.key(), constraint = lender_token.owner == lender.key())]
+    lender_token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        close=borrower,
+        has_one=borrower,
+        has_one=lender,
+        has_one=token,
+        has_one=perm_program,
+        seeds=[token.key().as_ref(), LOAN_PREFIX.as_bytes()],
+        bump
+    )]
+    loan: Account<'info, Loan>,
+    /// CHECK: PDA delegate of the loan's collateral
+    #[account(seeds=[loan.key().as_ref(), AUTHORITY_PREFIX.as_bytes()], bump)]
+    authority: AccountInfo<'info>,
+    token_program: Interface<'info, TokenInterface>,
+    /// CHECK: permission program, checked against the loan
+    perm_program: AccountInfo<'info>,
+}
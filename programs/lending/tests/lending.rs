@@ -0,0 +1,350 @@
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use interface::InterfaceError;
+use lending::{LendingError, Loan, AUTHORITY_PREFIX, LOAN_PREFIX};
+use permissioned_token_wrapper::{PermissionedTokenError, LOCKER_LIST_PREFIX, TOKEN_RECORD_PREFIX};
+use solana_program_test::{BanksClientError, ProgramTest};
+use solana_sdk::{
+    clock::Clock,
+    instruction::{AccountMeta, Instruction},
+    program_option::COption,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_program,
+};
+use test_fixtures::{
+    add_program, add_wallet, clone_keypair, create_token_account, custom_error, lamports,
+    lock_metas, preflight, process, program_control, token_account, unlock_metas, TokenFixture,
+};
+
+const COLLATERAL: u64 = 5;
+const PRINCIPAL: u64 = 100_000_000;
+const REPAY_AMOUNT: u64 = 110_000_000;
+const DURATION: i64 = 3600;
+
+fn authority(loan: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[loan.as_ref(), AUTHORITY_PREFIX.as_bytes()], &lending::id()).0
+}
+
+/// A mint frozen by `perm_program`, a borrower (`base.owner`) holding `COLLATERAL` tokens
+/// and a lender with an empty token account to receive seized collateral
+struct Fixture {
+    base: TokenFixture,
+    perm_program: Pubkey,
+    lender: Keypair,
+    lender_token: Pubkey,
+}
+
+impl Fixture {
+    async fn new() -> Self {
+        Self::with_perm_program(permissioned_token_wrapper::id()).await
+    }
+
+    async fn with_perm_program(perm_program: Pubkey) -> Self {
+        let mut program_test = ProgramTest::default();
+        add_program(&mut program_test, lending::id(), "lending");
+        add_program(&mut program_test, permissioned_token_wrapper::id(), "permissioned_token_wrapper");
+        add_program(&mut program_test, twice_permissioned::id(), "twice_permissioned");
+        let lender = add_wallet(&mut program_test);
+        let program_control = program_control(&perm_program);
+        let mut base = TokenFixture::start(program_test, &program_control, COLLATERAL).await;
+
+        let lender_token =
+            create_token_account(&mut base.context, &base.mint, &lender.pubkey(), 0).await;
+        Self {
+            base,
+            perm_program,
+            lender,
+            lender_token,
+        }
+    }
+
+    fn authority(&self) -> Pubkey {
+        authority(&self.loan())
+    }
+
+    fn loan(&self) -> Pubkey {
+        Pubkey::find_program_address(&[self.base.token.as_ref(), LOAN_PREFIX.as_bytes()], &lending::id()).0
+    }
+
+    async fn open_loan(&mut self) -> Result<(), BanksClientError> {
+        self.open_loan_through(self.perm_program).await
+    }
+
+    /// Opens the loan with `perm_program` as the collateral's permission program, the
+    /// remaining accounts are always the fixture's permission program's
+    async fn open_loan_through(&mut self, perm_program: Pubkey) -> Result<(), BanksClientError> {
+        let mut accounts = lending::accounts::OpenLoan {
+            borrower: self.base.owner.pubkey(),
+            lender: self.lender.pubkey(),
+            token: self.base.token,
+            mint: self.base.mint,
+            authority: self.authority(),
+            loan: self.loan(),
+            token_program: spl_token::id(),
+            perm_program,
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None);
+        accounts.extend(self.lock_accounts().await);
+        let ix = Instruction {
+            program_id: lending::id(),
+            accounts,
+            data: lending::instruction::OpenLoan {
+                principal: PRINCIPAL,
+                repay_amount: REPAY_AMOUNT,
+                duration: DURATION,
+            }
+            .data(),
+        };
+        let (borrower, lender) = (clone_keypair(&self.base.owner), clone_keypair(&self.lender));
+        process(&mut self.base.context, &[ix], &[&borrower, &lender]).await
+    }
+
+    async fn repay(&mut self) -> Result<(), BanksClientError> {
+        let mut accounts = lending::accounts::Repay {
+            borrower: self.base.owner.pubkey(),
+            lender: self.lender.pubkey(),
+            token: self.base.token,
+            mint: self.base.mint,
+            authority: self.authority(),
+            loan: self.loan(),
+            token_program: spl_token::id(),
+            perm_program: self.perm_program,
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None);
+        accounts.extend(self.unlock_accounts().await);
+        let ix = Instruction {
+            program_id: lending::id(),
+            accounts,
+            data: lending::instruction::Repay {}.data(),
+        };
+        let borrower = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&borrower]).await
+    }
+
+    async fn liquidate(&mut self) -> Result<(), BanksClientError> {
+        let mut accounts = lending::accounts::Liquidate {
+            lender: self.lender.pubkey(),
+            borrower: self.base.owner.pubkey(),
+            token: self.base.token,
+            lender_token: self.lender_token,
+            mint: self.base.mint,
+            authority: self.authority(),
+            loan: self.loan(),
+            token_program: spl_token::id(),
+            perm_program: self.perm_program,
+        }
+        .to_account_metas(None);
+        accounts.extend(self.unlock_accounts().await);
+        let ix = Instruction {
+            program_id: lending::id(),
+            accounts,
+            data: lending::instruction::Liquidate {}.data(),
+        };
+        let lender = clone_keypair(&self.lender);
+        process(&mut self.base.context, &[ix], &[&lender]).await
+    }
+
+    async fn lock_accounts(&mut self) -> Vec<AccountMeta> {
+        let (token, mint, borrower) = (self.base.token, self.base.mint, self.base.owner.pubkey());
+        let base = lock_metas(&token, &mint, &self.authority(), &borrower);
+        let perm_program = self.perm_program;
+        preflight(&mut self.base.context, &perm_program, "lock_exclusive", base).await
+    }
+
+    async fn unlock_accounts(&mut self) -> Vec<AccountMeta> {
+        let base = unlock_metas(&self.base.token, &self.base.mint, &self.authority());
+        let perm_program = self.perm_program;
+        preflight(&mut self.base.context, &perm_program, "unlock", base).await
+    }
+
+    /// Lock of the borrower's own, taken directly through the wrapper
+    async fn borrower_lock(&mut self) -> Result<(), BanksClientError> {
+        let (token, mint) = (self.base.token, self.base.mint);
+        let borrower = self.base.owner.pubkey();
+        let ix = Instruction {
+            program_id: permissioned_token_wrapper::id(),
+            accounts: permissioned_token_wrapper::accounts::Lock {
+                token,
+                mint,
+                delegate: borrower,
+                payer: borrower,
+                token_program: spl_token::id(),
+                program_control: program_control(&permissioned_token_wrapper::id()),
+                token_record: Pubkey::find_program_address(
+                    &[token.as_ref(), TOKEN_RECORD_PREFIX.as_bytes()],
+                    &permissioned_token_wrapper::id(),
+                )
+                .0,
+                system_program: system_program::id(),
+                locker_list: Pubkey::find_program_address(
+                    &[mint.as_ref(), LOCKER_LIST_PREFIX.as_bytes()],
+                    &permissioned_token_wrapper::id(),
+                )
+                .0,
+            }
+            .to_account_metas(None),
+            data: permissioned_token_wrapper::instruction::Lock {}.data(),
+        };
+        let borrower = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&borrower]).await
+    }
+
+    /// Direct transfer of the collateral by the borrower, bypassing the lending program
+    async fn borrower_transfer(&mut self) -> Result<(), BanksClientError> {
+        let ix = spl_token::instruction::transfer(
+            &spl_token::id(),
+            &self.base.token,
+            &self.lender_token,
+            &self.base.owner.pubkey(),
+            &[],
+            COLLATERAL,
+        )
+        .unwrap();
+        let borrower = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&borrower]).await
+    }
+
+    async fn read_loan(&mut self) -> Option<Loan> {
+        let key = self.loan();
+        let account = self.base.context.banks_client.get_account(key).await.unwrap()?;
+        Some(Loan::try_deserialize(&mut account.data.as_slice()).unwrap())
+    }
+
+    /// Moves the clock past the loan deadline
+    async fn pass_deadline(&mut self) {
+        let mut clock: Clock = self.base.context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp += DURATION + 1;
+        self.base.context.set_sysvar(&clock);
+    }
+}
+
+#[tokio::test]
+async fn open_loan_locks_collateral_in_borrower_wallet() {
+    let mut fixture = Fixture::new().await;
+    let borrower = fixture.base.owner.pubkey();
+    let borrower_lamports = lamports(&mut fixture.base.context, borrower).await;
+    fixture.open_loan().await.unwrap();
+
+    let token = token_account(&mut fixture.base.context, fixture.base.token).await;
+    assert!(token.is_frozen());
+    assert_eq!(token.owner, borrower);
+    assert_eq!(token.amount, COLLATERAL);
+    assert_eq!(token.delegate, COption::Some(fixture.authority()));
+    // the borrower also pays the loan rent
+    assert!(lamports(&mut fixture.base.context, borrower).await > borrower_lamports + PRINCIPAL / 2);
+
+    let loan = fixture.read_loan().await.unwrap();
+    assert_eq!(loan.borrower, borrower);
+    assert_eq!(loan.lender, fixture.lender.pubkey());
+    assert_eq!(loan.collateral, COLLATERAL);
+    assert_eq!(loan.repay_amount, REPAY_AMOUNT);
+
+    assert!(fixture.borrower_transfer().await.is_err());
+}
+
+#[tokio::test]
+async fn open_loan_rejects_program_that_doesnt_freeze_the_mint() {
+    let mut fixture = Fixture::new().await;
+    // would be handed the loan authority's signature over the collateral
+    let malicious_program = Pubkey::new_unique();
+
+    let err = fixture.open_loan_through(malicious_program).await.unwrap_err();
+    assert_eq!(custom_error(&err), Some(LendingError::InvalidPermProgram.into()));
+    let token = token_account(&mut fixture.base.context, fixture.base.token).await;
+    assert!(!token.is_frozen());
+    assert!(token.delegate.is_none());
+    assert!(fixture.read_loan().await.is_none());
+}
+
+#[tokio::test]
+async fn repay_unlocks_collateral() {
+    let mut fixture = Fixture::new().await;
+    fixture.open_loan().await.unwrap();
+    let lender = fixture.lender.pubkey();
+    let lender_lamports = lamports(&mut fixture.base.context, lender).await;
+
+    fixture.repay().await.unwrap();
+
+    assert_eq!(lamports(&mut fixture.base.context, lender).await, lender_lamports + REPAY_AMOUNT);
+    let token = token_account(&mut fixture.base.context, fixture.base.token).await;
+    assert!(!token.is_frozen());
+    assert!(token.delegate.is_none());
+    assert!(fixture.read_loan().await.is_none());
+
+    fixture.borrower_transfer().await.unwrap();
+}
+
+#[tokio::test]
+async fn liquidate_before_deadline_fails() {
+    let mut fixture = Fixture::new().await;
+    fixture.open_loan().await.unwrap();
+
+    assert!(fixture.liquidate().await.is_err());
+    assert!(token_account(&mut fixture.base.context, fixture.base.token).await.is_frozen());
+    assert!(fixture.read_loan().await.is_some());
+}
+
+#[tokio::test]
+async fn liquidate_moves_collateral_to_lender() {
+    let mut fixture = Fixture::new().await;
+    fixture.open_loan().await.unwrap();
+    fixture.pass_deadline().await;
+
+    fixture.liquidate().await.unwrap();
+
+    let (token, lender_token) = (fixture.base.token, fixture.lender_token);
+    let token = token_account(&mut fixture.base.context, token).await;
+    assert_eq!(token.amount, 0);
+    assert!(!token.is_frozen());
+    let lender_token = token_account(&mut fixture.base.context, lender_token).await;
+    assert_eq!(lender_token.amount, COLLATERAL);
+    assert!(!lender_token.is_frozen());
+    assert!(fixture.read_loan().await.is_none());
+    // a liquidated loan can't be repaid
+    assert!(fixture.repay().await.is_err());
+}
+
+#[tokio::test]
+async fn open_loan_rejects_a_lock_that_leaves_collateral_unfrozen() {
+    // twice_permissioned only freezes the token once a second delegate locks it
+    let mut fixture = Fixture::with_perm_program(twice_permissioned::id()).await;
+
+    let err = fixture.open_loan().await.unwrap_err();
+    assert_eq!(custom_error(&err), Some(InterfaceError::TokenNotFrozen.into()));
+    let token = token_account(&mut fixture.base.context, fixture.base.token).await;
+    assert!(!token.is_frozen());
+    assert!(token.delegate.is_none());
+    assert!(fixture.read_loan().await.is_none());
+}
+
+#[tokio::test]
+async fn open_loan_rejects_collateral_the_borrower_locked() {
+    let mut fixture = Fixture::new().await;
+    fixture.borrower_lock().await.unwrap();
+
+    let err = fixture.open_loan().await.unwrap_err();
+    assert_eq!(
+        custom_error(&err),
+        Some(spl_token::error::TokenError::AccountFrozen as u32)
+    );
+    assert!(fixture.read_loan().await.is_none());
+}
+
+#[tokio::test]
+async fn borrower_cannot_co_lock_collateral() {
+    let mut fixture = Fixture::new().await;
+    fixture.open_loan().await.unwrap();
+
+    // a lock of the borrower's own would keep seized collateral frozen
+    let err = fixture.borrower_lock().await.unwrap_err();
+    assert_eq!(custom_error(&err), Some(PermissionedTokenError::ExclusivelyLocked.into()));
+
+    fixture.pass_deadline().await;
+    fixture.liquidate().await.unwrap();
+    let lender_token = fixture.lender_token;
+    let lender_token = token_account(&mut fixture.base.context, lender_token).await;
+    assert_eq!(lender_token.amount, COLLATERAL);
+    assert!(!lender_token.is_frozen());
+}
@@ -0,0 +1,409 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{
+    self, Approve, Mint, Revoke, TokenAccount, TokenInterface, TransferChecked,
+};
+
+use interface::{
+    call, call_with_post_conditions, freezes_mint, partition_accounts, PostConditions, TILock,
+    TIUnlock,
+};
+
+declare_id!("6dzHf2KRtPBvykHL9J5DTJ5ASUqZ47Gu3QSiRRmL8qjW");
+
+/// Escrowless marketplace: listed tokens stay in the seller's wallet, approved to and
+/// locked by the listing's authority PDA through the token's permission program.
+/// Every listing has its own authority, so a permission program handed its signature
+/// can't reach the tokens of other listings. The authority locks the token exclusively,
+/// a lock of the seller's own would keep it frozen and make every purchase fail.
+#[program]
+pub mod marketplace {
+    use super::*;
+
+    /// Fails unless the permission program froze the token.
+    /// Remaining accounts are the permission program's lock accounts
+    pub fn list<'info>(
+        ctx: Context<'_, '_, '_, 'info, List<'info>>,
+        price: u64,
+        payment_mint: Option<Pubkey>,
+    ) -> Result<()> {
+        let listing = &mut ctx.accounts.listing;
+        listing.seller = ctx.accounts.seller.key();
+        listing.token = ctx.accounts.token.key();
+        listing.mint = ctx.accounts.mint.key();
+        listing.perm_program = ctx.accounts.perm_program.key();
+        listing.price = price;
+        listing.payment_mint = payment_mint;
+
+        token_interface::approve(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Approve {
+                    to: ctx.accounts.token.to_account_info(),
+                    delegate: ctx.accounts.authority.to_account_info(),
+                    authority: ctx.accounts.seller.to_account_info(),
+                },
+            ),
+            1,
+        )?;
+
+        let token = ctx.accounts.token.key();
+        let bump = [*ctx.bumps.get("authority").unwrap()];
+        let seeds: &[&[u8]] = &[token.as_ref(), AUTHORITY_PREFIX.as_bytes(), &bump];
+        let signer = [seeds];
+        let cvt_ctx = CpiContext::new_with_signer(
+            ctx.accounts.perm_program.clone(),
+            TILock {
+                token: ctx.accounts.token.clone(),
+                mint: ctx.accounts.mint.clone(),
+                delegate: authority_signer(&ctx.accounts.authority)?,
+                payer: ctx.accounts.seller.clone(),
+                token_program: ctx.accounts.token_program.clone(),
+                perm_program: ctx.accounts.perm_program.clone(),
+            },
+            &signer,
+        )
+        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+        let post_conditions =
+            PostConditions::default().expect_frozen(&ctx.accounts.token.to_account_info());
+        call_with_post_conditions(
+            "lock_exclusive".to_string(),
+            cvt_ctx,
+            None,
+            &post_conditions,
+            false,
+        )
+    }
+
+    /// Remaining accounts are the permission program's unlock accounts
+    pub fn delist<'info>(ctx: Context<'_, '_, '_, 'info, Delist<'info>>) -> Result<()> {
+        let token = ctx.accounts.token.key();
+        let bump = [*ctx.bumps.get("authority").unwrap()];
+        let seeds: &[&[u8]] = &[token.as_ref(), AUTHORITY_PREFIX.as_bytes(), &bump];
+        let signer = [seeds];
+        let cvt_ctx = CpiContext::new_with_signer(
+            ctx.accounts.perm_program.clone(),
+            TIUnlock {
+                token: ctx.accounts.token.clone(),
+                mint: ctx.accounts.mint.clone(),
+                delegate: authority_signer(&ctx.accounts.authority)?,
+                token_program: ctx.accounts.token_program.clone(),
+                perm_program: ctx.accounts.perm_program.clone(),
+            },
+            &signer,
+        )
+        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+        call("unlock".to_string(), cvt_ctx, false)?;
+
+        token_interface::revoke(CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Revoke {
+                source: ctx.accounts.token.to_account_info(),
+                authority: ctx.accounts.seller.to_account_info(),
+            },
+        ))
+    }
+
+    /// Buys a listing priced in SOL. Remaining accounts are the permission program's
+    /// unlock accounts of `token`, followed by its lock accounts of `buyer_token` when
+    /// `relock` is set, `account_partitions` gives the number of accounts of each.
+    pub fn buy<'info>(
+        ctx: Context<'_, '_, '_, 'info, Buy<'info>>,
+        account_partitions: [u8; 2],
+        relock: bool,
+    ) -> Result<()> {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.buyer.to_account_info(),
+                    to: ctx.accounts.seller.to_account_info(),
+                },
+            ),
+            ctx.accounts.listing.price,
+        )?;
+
+        settle(
+            Settlement {
+                buyer: &ctx.accounts.buyer,
+                token: &ctx.accounts.token,
+                buyer_token: &ctx.accounts.buyer_token,
+                mint: &ctx.accounts.mint,
+                authority: &ctx.accounts.authority,
+                authority_bump: *ctx.bumps.get("authority").unwrap(),
+                token_program: &ctx.accounts.token_program,
+                perm_program: &ctx.accounts.perm_program,
+            },
+            ctx.remaining_accounts,
+            account_partitions,
+            relock,
+        )
+    }
+
+    /// Same as `buy`, for listings priced in an SPL token
+    pub fn buy_with_token<'info>(
+        ctx: Context<'_, '_, '_, 'info, BuyWithToken<'info>>,
+        account_partitions: [u8; 2],
+        relock: bool,
+    ) -> Result<()> {
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.payment_token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.buyer_payment.to_account_info(),
+                    to: ctx.accounts.seller_payment.to_account_info(),
+                    mint: ctx.accounts.payment_mint.to_account_info(),
+                    authority: ctx.accounts.buyer.to_account_info(),
+                },
+            ),
+            ctx.accounts.listing.price,
+            ctx.accounts.payment_mint.decimals,
+        )?;
+
+        settle(
+            Settlement {
+                buyer: &ctx.accounts.buyer,
+                token: &ctx.accounts.token,
+                buyer_token: &ctx.accounts.buyer_token,
+                mint: &ctx.accounts.mint,
+                authority: &ctx.accounts.authority,
+                authority_bump: *ctx.bumps.get("authority").unwrap(),
+                token_program: &ctx.accounts.token_program,
+                perm_program: &ctx.accounts.perm_program,
+            },
+            ctx.remaining_accounts,
+            account_partitions,
+            relock,
+        )
+    }
+}
+
+pub const LISTING_PREFIX: &'static str = "listing";
+pub const AUTHORITY_PREFIX: &'static str = "authority";
+
+#[account]
+pub struct Listing {
+    pub seller: Pubkey,
+    pub token: Pubkey,
+    pub mint: Pubkey,
+    pub perm_program: Pubkey,
+    pub price: u64,
+    /// `None` for listings priced in SOL
+    pub payment_mint: Option<Pubkey>,
+}
+
+impl Listing {
+    pub const SPACE: usize = 8 + 32 * 4 + 8 + 1 + 32;
+}
+
+/// Accounts shared by `buy` and `buy_with_token` once the seller is paid
+struct Settlement<'a, 'info> {
+    buyer: &'a Signer<'info>,
+    token: &'a InterfaceAccount<'info, TokenAccount>,
+    buyer_token: &'a InterfaceAccount<'info, TokenAccount>,
+    mint: &'a InterfaceAccount<'info, Mint>,
+    authority: &'a AccountInfo<'info>,
+    authority_bump: u8,
+    token_program: &'a Interface<'info, TokenInterface>,
+    perm_program: &'a AccountInfo<'info>,
+}
+
+/// Unlocks the listed token, moves it to the buyer and, with `relock`, locks it again
+/// with the buyer as the locker
+fn settle<'a, 'info>(
+    accounts: Settlement<'a, 'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    account_partitions: [u8; 2],
+    relock: bool,
+) -> Result<()> {
+    let partitions = partition_accounts(remaining_accounts, &account_partitions)?;
+    let (unlock_accounts, relock_accounts) = (partitions[0], partitions[1]);
+
+    let token = accounts.token.key();
+    let bump = [accounts.authority_bump];
+    let seeds: &[&[u8]] = &[token.as_ref(), AUTHORITY_PREFIX.as_bytes(), &bump];
+    let signer = [seeds];
+    let authority = authority_signer(accounts.authority)?;
+
+    let cvt_ctx = CpiContext::new_with_signer(
+        accounts.perm_program.clone(),
+        TIUnlock {
+            token: accounts.token.clone(),
+            mint: accounts.mint.clone(),
+            delegate: authority.clone(),
+            token_program: accounts.token_program.clone(),
+            perm_program: accounts.perm_program.clone(),
+        },
+        &signer,
+    )
+    .with_remaining_accounts(unlock_accounts.to_vec());
+    call("unlock".to_string(), cvt_ctx, false)?;
+
+    token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: accounts.token.to_account_info(),
+                to: accounts.buyer_token.to_account_info(),
+                mint: accounts.mint.to_account_info(),
+                authority: authority.to_account_info(),
+            },
+            &signer,
+        ),
+        1,
+        accounts.mint.decimals,
+    )?;
+
+    if relock {
+        let cvt_ctx = CpiContext::new(
+            accounts.perm_program.clone(),
+            TILock {
+                token: accounts.buyer_token.clone(),
+                mint: accounts.mint.clone(),
+                delegate: accounts.buyer.clone(),
+                payer: accounts.buyer.clone(),
+                token_program: accounts.token_program.clone(),
+                perm_program: accounts.perm_program.clone(),
+            },
+        )
+        .with_remaining_accounts(relock_accounts.to_vec());
+        call("lock".to_string(), cvt_ctx, false)?;
+    }
+    Ok(())
+}
+
+/// The listing authority as a signer, its signer privileges come from the seeds
+/// passed along with every CPI
+fn authority_signer<'info>(authority: &AccountInfo<'info>) -> Result<Signer<'info>> {
+    let mut authority = authority.clone();
+    authority.is_signer = true;
+    Signer::try_from(&authority)
+}
+
+#[error_code]
+pub enum MarketplaceError {
+    #[msg("Only token accounts holding a single token of a zero decimal mint can be listed")]
+    NotAnNft,
+    #[msg("Listing is priced in a different currency")]
+    WrongPaymentMethod,
+    #[msg("Permission program is not the freeze authority of the mint")]
+    InvalidPermProgram,
+}
+
+#[derive(Accounts)]
+pub struct List<'info> {
+    #[account(mut)]
+    seller: Signer<'info>,
+    #[account(
+        mut,
+        constraint = token.owner == seller.key(),
+        constraint = token.mint == mint.key(),
+        constraint = token.amount == 1 @ MarketplaceError::NotAnNft
+    )]
+    token: InterfaceAccount<'info, TokenAccount>,
+    #[account(constraint = mint.decimals == 0 @ MarketplaceError::NotAnNft)]
+    mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: PDA delegate of the listed token
+    #[account(seeds=[token.key().as_ref(), AUTHORITY_PREFIX.as_bytes()], bump)]
+    authority: AccountInfo<'info>,
+    #[account(init, payer=seller, space=Listing::SPACE, seeds=[token.key().as_ref(), LISTING_PREFIX.as_bytes()], bump)]
+    listing: Account<'info, Listing>,
+    token_program: Interface<'info, TokenInterface>,
+    /// CHECK: permission program, must freeze the mint's token accounts
+    #[account(constraint = freezes_mint(&perm_program.key(), &mint) @ MarketplaceError::InvalidPermProgram)]
+    perm_program: AccountInfo<'info>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Delist<'info> {
+    #[account(mut)]
+    seller: Signer<'info>,
+    #[account(mut)]
+    token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: PDA delegate of the listed token
+    #[account(seeds=[token.key().as_ref(), AUTHORITY_PREFIX.as_bytes()], bump)]
+    authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        close=seller,
+        has_one=seller,
+        has_one=token,
+        has_one=perm_program,
+        seeds=[token.key().as_ref(), LISTING_PREFIX.as_bytes()],
+        bump
+    )]
+    listing: Account<'info, Listing>,
+    token_program: Interface<'info, TokenInterface>,
+    /// CHECK: permission program, checked against the listing
+    perm_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Buy<'info> {
+    #[account(mut)]
+    buyer: Signer<'info>,
+    /// CHECK: checked against the listing
+    #[account(mut)]
+    seller: AccountInfo<'info>,
+    #[account(mut)]
+    token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = buyer_token.mint == mint.key(), constraint = buyer_token.owner == buyer.key())]
+    buyer_token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: PDA delegate of the listed token
+    #[account(seeds=[token.key().as_ref(), AUTHORITY_PREFIX.as_bytes()], bump)]
+    authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        close=seller,
+        has_one=seller,
+        has_one=token,
+        has_one=perm_program,
+        constraint = listing.payment_mint.is_none() @ MarketplaceError::WrongPaymentMethod,
+        seeds=[token.key().as_ref(), LISTING_PREFIX.as_bytes()],
+        bump
+    )]
+    listing: Account<'info, Listing>,
+    token_program: Interface<'info, TokenInterface>,
+    /// CHECK: permission program, checked against the listing
+    perm_program: AccountInfo<'info>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BuyWithToken<'info> {
+    #[account(mut)]
+    buyer: Signer<'info>,
+    /// CHECK: checked against the listing
+    #[account(mut)]
+    seller: AccountInfo<'info>,
+    #[account(mut)]
+    token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = buyer_token.mint == mint.key(), constraint = buyer_token.owner == buyer.key())]
+    buyer_token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: PDA delegate of the listed token
+    #[account(seeds=[token.key().as_ref(), AUTHORITY_PREFIX.as_bytes()], bump)]
+    authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        close=seller,
+        has_one=seller,
+        has_one=token,
+        has_one=perm_program,
+        constraint = listing.payment_mint == Some(payment_mint.key()) @ MarketplaceError::WrongPaymentMethod,
+        seeds=[token.key().as_ref(), LISTING_PREFIX.as_bytes()],
+        bump
+    )]
+    listing: Account<'info, Listing>,
+    payment_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, constraint = buyer_payment.mint == payment_mint.key(), constraint = buyer_payment.owner == buyer.key())]
+    buyer_payment: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = seller_payment.mint == payment_mint.key(), constraint = seller_payment.owner == seller.key())]
+    seller_payment: InterfaceAccount<'info, TokenAccount>,
+    token_program: Interface<'info, TokenInterface>,
+    payment_token_program: Interface<'info, TokenInterface>,
+    /// CHECK: permission program, checked against the listing
+    perm_program: AccountInfo<'info>,
+}
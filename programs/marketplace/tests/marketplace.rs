@@ -0,0 +1,400 @@
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use marketplace::{Listing, MarketplaceError, AUTHORITY_PREFIX, LISTING_PREFIX};
+use permissioned_token_wrapper::PermissionedTokenError;
+use solana_program_test::{BanksClientError, ProgramTest};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    program_option::COption,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_program,
+};
+use test_fixtures::{
+    add_program, add_wallet, clone_keypair, create_mint, create_token_account, custom_error,
+    lamports, lock_metas, preflight, process, program_control, sighash, token_account,
+    unlock_metas, TokenFixture,
+};
+
+const PRICE: u64 = 1_000_000;
+
+fn authority(token: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[token.as_ref(), AUTHORITY_PREFIX.as_bytes()], &marketplace::id()).0
+}
+
+/// A permissioned NFT held by `base.owner`, the seller, with an empty token account for `buyer`
+struct Fixture {
+    base: TokenFixture,
+    buyer: Keypair,
+    buyer_token: Pubkey,
+}
+
+impl Fixture {
+    async fn new() -> Self {
+        let mut program_test = ProgramTest::default();
+        add_program(&mut program_test, marketplace::id(), "marketplace");
+        add_program(&mut program_test, permissioned_token_wrapper::id(), "permissioned_token_wrapper");
+        let buyer = add_wallet(&mut program_test);
+        let program_control = program_control(&permissioned_token_wrapper::id());
+        let mut base = TokenFixture::start(program_test, &program_control, 1).await;
+
+        let buyer_token =
+            create_token_account(&mut base.context, &base.mint, &buyer.pubkey(), 0).await;
+        Self {
+            base,
+            buyer,
+            buyer_token,
+        }
+    }
+
+    fn authority(&self) -> Pubkey {
+        authority(&self.base.token)
+    }
+
+    fn listing(&self) -> Pubkey {
+        Pubkey::find_program_address(
+            &[self.base.token.as_ref(), LISTING_PREFIX.as_bytes()],
+            &marketplace::id(),
+        )
+        .0
+    }
+
+    async fn list(&mut self, payment_mint: Option<Pubkey>) -> Result<(), BanksClientError> {
+        self.list_through(permissioned_token_wrapper::id(), payment_mint).await
+    }
+
+    /// Lists with `perm_program` as the token's permission program, the remaining accounts
+    /// are always the wrapper's
+    async fn list_through(
+        &mut self,
+        perm_program: Pubkey,
+        payment_mint: Option<Pubkey>,
+    ) -> Result<(), BanksClientError> {
+        let mut accounts = marketplace::accounts::List {
+            seller: self.base.owner.pubkey(),
+            token: self.base.token,
+            mint: self.base.mint,
+            authority: self.authority(),
+            listing: self.listing(),
+            token_program: spl_token::id(),
+            perm_program,
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None);
+        let (token, seller) = (self.base.token, self.base.owner.pubkey());
+        let authority = self.authority();
+        accounts.extend(self.lock_accounts("lock_exclusive", &token, &authority, &seller).await);
+        let ix = Instruction {
+            program_id: marketplace::id(),
+            accounts,
+            data: marketplace::instruction::List {
+                price: PRICE,
+                payment_mint,
+            }
+            .data(),
+        };
+        let seller = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&seller]).await
+    }
+
+    async fn delist(&mut self) -> Result<(), BanksClientError> {
+        let mut accounts = marketplace::accounts::Delist {
+            seller: self.base.owner.pubkey(),
+            token: self.base.token,
+            mint: self.base.mint,
+            authority: self.authority(),
+            listing: self.listing(),
+            token_program: spl_token::id(),
+            perm_program: permissioned_token_wrapper::id(),
+        }
+        .to_account_metas(None);
+        let token = self.base.token;
+        accounts.extend(self.unlock_accounts(&token, &self.authority()).await);
+        let ix = Instruction {
+            program_id: marketplace::id(),
+            accounts,
+            data: marketplace::instruction::Delist {}.data(),
+        };
+        let seller = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&seller]).await
+    }
+
+    /// Unlock accounts of the listed token, then lock accounts of the buyer's when relocking
+    async fn settlement_accounts(&mut self, relock: bool) -> ([u8; 2], Vec<AccountMeta>) {
+        let (token, buyer_token, buyer) = (self.base.token, self.buyer_token, self.buyer.pubkey());
+        let unlock_accounts = self.unlock_accounts(&token, &self.authority()).await;
+        let relock_accounts = if relock {
+            self.lock_accounts("lock", &buyer_token, &buyer, &buyer).await
+        } else {
+            vec![]
+        };
+        let (lens, accounts) = interface::build_partitions(vec![unlock_accounts, relock_accounts]).unwrap();
+        ([lens[0], lens[1]], accounts)
+    }
+
+    async fn buy(&mut self, relock: bool) -> Result<(), BanksClientError> {
+        let mut accounts = marketplace::accounts::Buy {
+            buyer: self.buyer.pubkey(),
+            seller: self.base.owner.pubkey(),
+            token: self.base.token,
+            buyer_token: self.buyer_token,
+            mint: self.base.mint,
+            authority: self.authority(),
+            listing: self.listing(),
+            token_program: spl_token::id(),
+            perm_program: permissioned_token_wrapper::id(),
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None);
+        let (account_partitions, remaining_accounts) = self.settlement_accounts(relock).await;
+        accounts.extend(remaining_accounts);
+        let ix = Instruction {
+            program_id: marketplace::id(),
+            accounts,
+            data: marketplace::instruction::Buy {
+                account_partitions,
+                relock,
+            }
+            .data(),
+        };
+        let buyer = clone_keypair(&self.buyer);
+        process(&mut self.base.context, &[ix], &[&buyer]).await
+    }
+
+    async fn buy_with_token(
+        &mut self,
+        payment_mint: Pubkey,
+        buyer_payment: Pubkey,
+        seller_payment: Pubkey,
+    ) -> Result<(), BanksClientError> {
+        let mut accounts = marketplace::accounts::BuyWithToken {
+            buyer: self.buyer.pubkey(),
+            seller: self.base.owner.pubkey(),
+            token: self.base.token,
+            buyer_token: self.buyer_token,
+            mint: self.base.mint,
+            authority: self.authority(),
+            listing: self.listing(),
+            payment_mint,
+            buyer_payment,
+            seller_payment,
+            token_program: spl_token::id(),
+            payment_token_program: spl_token::id(),
+            perm_program: permissioned_token_wrapper::id(),
+        }
+        .to_account_metas(None);
+        let (account_partitions, remaining_accounts) = self.settlement_accounts(false).await;
+        accounts.extend(remaining_accounts);
+        let ix = Instruction {
+            program_id: marketplace::id(),
+            accounts,
+            data: marketplace::instruction::BuyWithToken {
+                account_partitions,
+                relock: false,
+            }
+            .data(),
+        };
+        let buyer = clone_keypair(&self.buyer);
+        process(&mut self.base.context, &[ix], &[&buyer]).await
+    }
+
+    async fn lock_accounts(
+        &mut self,
+        ix_name: &str,
+        token: &Pubkey,
+        delegate: &Pubkey,
+        payer: &Pubkey,
+    ) -> Vec<AccountMeta> {
+        let base = lock_metas(token, &self.base.mint, delegate, payer);
+        preflight(&mut self.base.context, &permissioned_token_wrapper::id(), ix_name, base).await
+    }
+
+    async fn unlock_accounts(&mut self, token: &Pubkey, delegate: &Pubkey) -> Vec<AccountMeta> {
+        let base = unlock_metas(token, &self.base.mint, delegate);
+        preflight(&mut self.base.context, &permissioned_token_wrapper::id(), "unlock", base).await
+    }
+
+    /// Direct transfer by the seller, bypassing the marketplace
+    async fn seller_transfer(&mut self) -> Result<(), BanksClientError> {
+        let ix = spl_token::instruction::transfer(
+            &spl_token::id(),
+            &self.base.token,
+            &self.buyer_token,
+            &self.base.owner.pubkey(),
+            &[],
+            1,
+        )
+        .unwrap();
+        let seller = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&seller]).await
+    }
+}
+
+#[tokio::test]
+async fn list_locks_token_in_seller_wallet() {
+    let mut fixture = Fixture::new().await;
+    fixture.list(None).await.unwrap();
+
+    let token = token_account(&mut fixture.base.context, fixture.base.token).await;
+    assert!(token.is_frozen());
+    assert_eq!(token.owner, fixture.base.owner.pubkey());
+    assert_eq!(token.amount, 1);
+    assert_eq!(token.delegate, COption::Some(fixture.authority()));
+
+    let listing_key = fixture.listing();
+    let listing = fixture
+        .base
+        .context
+        .banks_client
+        .get_account(listing_key)
+        .await
+        .unwrap()
+        .unwrap();
+    let listing = Listing::try_deserialize(&mut listing.data.as_slice()).unwrap();
+    assert_eq!(listing.seller, fixture.base.owner.pubkey());
+    assert_eq!(listing.price, PRICE);
+
+    assert!(fixture.seller_transfer().await.is_err());
+}
+
+#[tokio::test]
+async fn list_rejects_program_that_doesnt_freeze_the_mint() {
+    let mut fixture = Fixture::new().await;
+    // would be handed the listing authority's signature over the seller's token
+    let malicious_program = Pubkey::new_unique();
+
+    let err = fixture.list_through(malicious_program, None).await.unwrap_err();
+    assert_eq!(
+        custom_error(&err),
+        Some(MarketplaceError::InvalidPermProgram.into())
+    );
+    let token = token_account(&mut fixture.base.context, fixture.base.token).await;
+    assert!(!token.is_frozen());
+    assert!(token.delegate.is_none());
+}
+
+#[tokio::test]
+async fn seller_cannot_unlock_listed_token() {
+    let mut fixture = Fixture::new().await;
+    fixture.list(None).await.unwrap();
+
+    let (token, seller) = (fixture.base.token, fixture.base.owner.pubkey());
+    let mut accounts = vec![
+        AccountMeta::new(token, false),
+        AccountMeta::new_readonly(fixture.base.mint, false),
+        AccountMeta::new_readonly(seller, true),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    accounts.extend(fixture.unlock_accounts(&token, &seller).await);
+    let ix = Instruction {
+        program_id: permissioned_token_wrapper::id(),
+        accounts,
+        data: sighash("unlock"),
+    };
+    let seller = clone_keypair(&fixture.base.owner);
+    assert!(process(&mut fixture.base.context, &[ix], &[&seller]).await.is_err());
+    assert!(token_account(&mut fixture.base.context, token).await.is_frozen());
+}
+
+#[tokio::test]
+async fn seller_cannot_co_lock_listed_token() {
+    let mut fixture = Fixture::new().await;
+    fixture.list(None).await.unwrap();
+
+    // a lock of the seller's own would keep the token frozen through the purchase
+    let (token, seller) = (fixture.base.token, fixture.base.owner.pubkey());
+    let mut accounts = vec![
+        AccountMeta::new(token, false),
+        AccountMeta::new_readonly(fixture.base.mint, false),
+        AccountMeta::new_readonly(seller, true),
+        AccountMeta::new(seller, true),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    accounts.extend(fixture.lock_accounts("lock", &token, &seller, &seller).await);
+    let ix = Instruction {
+        program_id: permissioned_token_wrapper::id(),
+        accounts,
+        data: sighash("lock"),
+    };
+    let seller = clone_keypair(&fixture.base.owner);
+    let err = process(&mut fixture.base.context, &[ix], &[&seller]).await.unwrap_err();
+    assert_eq!(
+        custom_error(&err),
+        Some(PermissionedTokenError::ExclusivelyLocked.into())
+    );
+
+    fixture.buy(false).await.unwrap();
+    let buyer_token = fixture.buyer_token;
+    let buyer_token = token_account(&mut fixture.base.context, buyer_token).await;
+    assert_eq!(buyer_token.amount, 1);
+    assert!(!buyer_token.is_frozen());
+}
+
+#[tokio::test]
+async fn delist_unlocks_token() {
+    let mut fixture = Fixture::new().await;
+    fixture.list(None).await.unwrap();
+    fixture.delist().await.unwrap();
+
+    let token = token_account(&mut fixture.base.context, fixture.base.token).await;
+    assert!(!token.is_frozen());
+    assert!(token.delegate.is_none());
+    let listing_key = fixture.listing();
+    assert!(fixture.base.context.banks_client.get_account(listing_key).await.unwrap().is_none());
+
+    fixture.seller_transfer().await.unwrap();
+}
+
+#[tokio::test]
+async fn buy_with_sol_moves_token_to_buyer() {
+    let mut fixture = Fixture::new().await;
+    fixture.list(None).await.unwrap();
+    let seller = fixture.base.owner.pubkey();
+    let seller_lamports = lamports(&mut fixture.base.context, seller).await;
+
+    fixture.buy(false).await.unwrap();
+
+    let (token, buyer_token) = (fixture.base.token, fixture.buyer_token);
+    assert_eq!(token_account(&mut fixture.base.context, token).await.amount, 0);
+    let buyer_token = token_account(&mut fixture.base.context, buyer_token).await;
+    assert_eq!(buyer_token.amount, 1);
+    assert!(!buyer_token.is_frozen());
+    // the seller also gets the listing rent back
+    assert!(lamports(&mut fixture.base.context, seller).await >= seller_lamports + PRICE);
+    let listing_key = fixture.listing();
+    assert!(fixture.base.context.banks_client.get_account(listing_key).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn buy_with_relock_locks_for_buyer() {
+    let mut fixture = Fixture::new().await;
+    fixture.list(None).await.unwrap();
+    fixture.buy(true).await.unwrap();
+
+    let buyer_token = fixture.buyer_token;
+    let buyer_token = token_account(&mut fixture.base.context, buyer_token).await;
+    assert_eq!(buyer_token.amount, 1);
+    assert!(buyer_token.is_frozen());
+}
+
+#[tokio::test]
+async fn buy_with_token_pays_seller() {
+    let mut fixture = Fixture::new().await;
+    let payment_mint = create_mint(&mut fixture.base.context, 6, None).await;
+    let (seller, buyer) = (fixture.base.owner.pubkey(), fixture.buyer.pubkey());
+    let buyer_payment = create_token_account(&mut fixture.base.context, &payment_mint, &buyer, PRICE).await;
+    let seller_payment = create_token_account(&mut fixture.base.context, &payment_mint, &seller, 0).await;
+
+    fixture.list(Some(payment_mint)).await.unwrap();
+    // SOL payment is rejected for listings priced in a token
+    assert!(fixture.buy(false).await.is_err());
+
+    fixture
+        .buy_with_token(payment_mint, buyer_payment, seller_payment)
+        .await
+        .unwrap();
+
+    assert_eq!(token_account(&mut fixture.base.context, seller_payment).await.amount, PRICE);
+    assert_eq!(token_account(&mut fixture.base.context, buyer_payment).await.amount, 0);
+    let buyer_token = fixture.buyer_token;
+    assert_eq!(token_account(&mut fixture.base.context, buyer_token).await.amount, 1);
+}
@@ -0,0 +1,311 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use interface::{IAccountMeta, PreflightAccounts};
+
+declare_id!("7xa7Yr4NoPhS3rQY4itq7rcSytNvZT9rRTwf6n2pahNz");
+
+/// Permission program that requires every program in a per-mint chain to approve.
+///
+/// `lock` runs each downstream program's `lock` in chain order, `unlock` runs them in
+/// reverse order. The chain config is the first remaining account, the downstream
+/// programs and their extra accounts follow in any order.
+///
+/// A mint has a single freeze authority, so at most one program in a chain can freeze,
+/// the others must approve without freezing, like `delegate_allowlist`.
+/// `permissioned_token_wrapper`, `twice_permissioned` and anything wrapping them each
+/// require their own `program_control` PDA as the freeze authority, so no two of them can
+/// be chained: whichever one doesn't hold it fails every lock.
+#[program]
+pub mod permission_chain {
+    use super::*;
+
+    pub fn init_chain_config(
+        ctx: Context<InitChainConfig>,
+        admin: Pubkey,
+        programs: Vec<Pubkey>,
+    ) -> Result<()> {
+        let chain_config = &mut ctx.accounts.chain_config;
+        chain_config.admin = admin;
+        chain_config.set(programs)
+    }
+
+    pub fn update_chain_config(ctx: Context<UpdateChainConfig>, programs: Vec<Pubkey>) -> Result<()> {
+        ctx.accounts.chain_config.set(programs)
+    }
+
+    pub fn preflight_lock<'info>(ctx: Context<'_, '_, '_, 'info, ILock<'info>>) -> Result<Vec<u8>> {
+        let (chain_config, downstream_accounts) =
+            load_chain_config(&ctx.accounts.mint, ctx.remaining_accounts)?;
+
+        let mut accounts = vec![IAccountMeta {
+            pubkey: chain_config_address(&ctx.accounts.mint.key()),
+            signer: false,
+            writable: false,
+        }];
+        for program in chain_config.programs.iter() {
+            let program = find_program(program, downstream_accounts)?;
+            let downstream_ctx = CpiContext::new(
+                program.clone(),
+                interface::ILock {
+                    token: ctx.accounts.token.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    delegate: ctx.accounts.delegate.to_account_info(),
+                    payer: ctx.accounts.payer.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(downstream_accounts.to_vec());
+
+            let downstream_preflight = interface::preflight("lock", &downstream_ctx)?;
+            merge_accounts(&mut accounts, program.key(), downstream_preflight);
+        }
+        Ok(PreflightAccounts { accounts }.try_to_vec()?)
+    }
+
+    pub fn preflight_unlock<'info>(
+        ctx: Context<'_, '_, '_, 'info, IUnlock<'info>>,
+    ) -> Result<Vec<u8>> {
+        let (chain_config, downstream_accounts) =
+            load_chain_config(&ctx.accounts.mint, ctx.remaining_accounts)?;
+
+        let mut accounts = vec![IAccountMeta {
+            pubkey: chain_config_address(&ctx.accounts.mint.key()),
+            signer: false,
+            writable: false,
+        }];
+        for program in chain_config.programs.iter().rev() {
+            let program = find_program(program, downstream_accounts)?;
+            let downstream_ctx = CpiContext::new(
+                program.clone(),
+                interface::IUnlock {
+                    token: ctx.accounts.token.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    delegate: ctx.accounts.delegate.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                },
+            )
+            .with_remaining_accounts(downstream_accounts.to_vec());
+
+            let downstream_preflight = interface::preflight("unlock", &downstream_ctx)?;
+            merge_accounts(&mut accounts, program.key(), downstream_preflight);
+        }
+        Ok(PreflightAccounts { accounts }.try_to_vec()?)
+    }
+
+    pub fn lock<'info>(
+        ctx: Context<'_, '_, '_, 'info, Lock<'info>>,
+        nonce: Option<u64>,
+    ) -> Result<()> {
+        let mint = ctx.accounts.mint.to_account_info();
+        let (chain_config, downstream_accounts) = load_chain_config(&mint, ctx.remaining_accounts)?;
+
+        for program in chain_config.programs.iter() {
+            let program = find_program(program, downstream_accounts)?;
+            msg!("Lock with {:?}", program.key());
+            let downstream_ctx = CpiContext::new(
+                program.clone(),
+                interface::TILock {
+                    token: ctx.accounts.token.clone(),
+                    mint: ctx.accounts.mint.clone(),
+                    delegate: ctx.accounts.delegate.clone(),
+                    payer: ctx.accounts.payer.clone(),
+                    token_program: ctx.accounts.token_program.clone(),
+                    perm_program: program.clone(),
+                },
+            )
+            .with_remaining_accounts(downstream_accounts.to_vec());
+
+            interface::call_with_nonce("lock".to_string(), downstream_ctx, nonce, false)?;
+        }
+        Ok(())
+    }
+
+    pub fn unlock<'info>(
+        ctx: Context<'_, '_, '_, 'info, Unlock<'info>>,
+        nonce: Option<u64>,
+    ) -> Result<()> {
+        let mint = ctx.accounts.mint.to_account_info();
+        let (chain_config, downstream_accounts) = load_chain_config(&mint, ctx.remaining_accounts)?;
+
+        for program in chain_config.programs.iter().rev() {
+            let program = find_program(program, downstream_accounts)?;
+            msg!("Unlock with {:?}", program.key());
+            let downstream_ctx = CpiContext::new(
+                program.clone(),
+                interface::TIUnlock {
+                    token: ctx.accounts.token.clone(),
+                    mint: ctx.accounts.mint.clone(),
+                    delegate: ctx.accounts.delegate.clone(),
+                    token_program: ctx.accounts.token_program.clone(),
+                    perm_program: program.clone(),
+                },
+            )
+            .with_remaining_accounts(downstream_accounts.to_vec());
+
+            interface::call_with_nonce("unlock".to_string(), downstream_ctx, nonce, false)?;
+        }
+        Ok(())
+    }
+}
+
+pub const CHAIN_CONFIG_PREFIX: &'static str = "chain_config";
+pub const MAX_CHAIN_PROGRAMS: usize = 4;
+
+/// Ordered list of permission programs that must all approve a lock on a mint
+#[account]
+pub struct ChainConfig {
+    pub admin: Pubkey,
+    pub programs: Vec<Pubkey>,
+}
+
+impl ChainConfig {
+    pub const SPACE: usize = 8 + 32 + 4 + 32 * MAX_CHAIN_PROGRAMS;
+
+    pub fn set(&mut self, programs: Vec<Pubkey>) -> Result<()> {
+        require!(
+            !programs.is_empty() && programs.len() <= MAX_CHAIN_PROGRAMS,
+            PermissionChainError::InvalidChain
+        );
+        for (i, program) in programs.iter().enumerate() {
+            require!(
+                *program != crate::id() && !programs[..i].contains(program),
+                PermissionChainError::InvalidChain
+            );
+        }
+        self.programs = programs;
+        Ok(())
+    }
+}
+
+pub fn chain_config_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[mint.as_ref(), CHAIN_CONFIG_PREFIX.as_bytes()], &crate::id()).0
+}
+
+/// Reads the chain config from the first remaining account and returns it along with
+/// the accounts that get forwarded to the downstream programs
+fn load_chain_config<'a, 'info>(
+    mint: &AccountInfo<'info>,
+    remaining_accounts: &'a [AccountInfo<'info>],
+) -> Result<(ChainConfig, &'a [AccountInfo<'info>])> {
+    let (chain_config, downstream_accounts) = remaining_accounts
+        .split_first()
+        .ok_or(PermissionChainError::InvalidChainConfig)?;
+    require_keys_eq!(
+        chain_config.key(),
+        chain_config_address(&mint.key()),
+        PermissionChainError::InvalidChainConfig
+    );
+    require_keys_eq!(*chain_config.owner, crate::id(), PermissionChainError::InvalidChainConfig);
+    let chain_config = ChainConfig::try_deserialize(&mut &chain_config.data.borrow()[..])?;
+    Ok((chain_config, downstream_accounts))
+}
+
+fn find_program<'a, 'info>(
+    program: &Pubkey,
+    accounts: &'a [AccountInfo<'info>],
+) -> Result<&'a AccountInfo<'info>> {
+    accounts
+        .iter()
+        .find(|acc| acc.key == program && acc.executable)
+        .ok_or_else(|| {
+            msg!("downstream program not found: {:?}", program);
+            error!(PermissionChainError::MissingDownstreamProgram)
+        })
+}
+
+/// Appends a downstream program and the accounts its preflight returned, skipping
+/// accounts already requested by an earlier program. An account requested by several
+/// programs keeps the most permissive flags.
+fn merge_accounts(accounts: &mut Vec<IAccountMeta>, program: Pubkey, preflight: PreflightAccounts) {
+    let program = IAccountMeta {
+        pubkey: program,
+        signer: false,
+        writable: false,
+    };
+    for acc in std::iter::once(program).chain(preflight.accounts) {
+        match accounts.iter_mut().find(|existing| existing.pubkey == acc.pubkey) {
+            Some(existing) => {
+                existing.writable |= acc.writable;
+                existing.signer |= acc.signer;
+            }
+            None => accounts.push(acc),
+        }
+    }
+}
+
+#[error_code]
+pub enum PermissionChainError {
+    #[msg("Chain must list between one and MAX_CHAIN_PROGRAMS distinct downstream programs")]
+    InvalidChain,
+    #[msg("First remaining account must be the mint's chain config")]
+    InvalidChainConfig,
+    #[msg("Downstream program was not passed in remaining accounts")]
+    MissingDownstreamProgram,
+}
+
+#[derive(Accounts)]
+pub struct InitChainConfig<'info> {
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = mint.mint_authority.is_some() && mint.mint_authority.unwrap() == mint_authority.key())]
+    mint_authority: Signer<'info>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    #[account(init, payer=payer, space=ChainConfig::SPACE, seeds=[mint.key().as_ref(), CHAIN_CONFIG_PREFIX.as_bytes()], bump)]
+    chain_config: Account<'info, ChainConfig>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateChainConfig<'info> {
+    mint: InterfaceAccount<'info, Mint>,
+    admin: Signer<'info>,
+    #[account(mut, has_one=admin, seeds=[mint.key().as_ref(), CHAIN_CONFIG_PREFIX.as_bytes()], bump)]
+    chain_config: Account<'info, ChainConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ILock<'info> {
+    /// CHECK: nil
+    token: AccountInfo<'info>,
+    /// CHECK: nil
+    mint: AccountInfo<'info>,
+    /// CHECK: nil
+    delegate: AccountInfo<'info>,
+    /// CHECK: nil
+    payer: AccountInfo<'info>,
+    /// CHECK: nil
+    token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct IUnlock<'info> {
+    /// CHECK: nil
+    token: AccountInfo<'info>,
+    /// CHECK: nil
+    mint: AccountInfo<'info>,
+    /// CHECK: nil
+    delegate: AccountInfo<'info>,
+    /// CHECK: nil
+    token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Lock<'info> {
+    #[account(mut)]
+    token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    delegate: Signer<'info>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct Unlock<'info> {
+    #[account(mut)]
+    token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    delegate: Signer<'info>,
+    token_program: Interface<'info, TokenInterface>,
+}
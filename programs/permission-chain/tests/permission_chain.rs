@@ -0,0 +1,226 @@
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use delegate_allowlist::{allowlist_address, DelegateAllowlistError};
+use permission_chain::chain_config_address;
+use permissioned_token_wrapper::{TokenRecord, TOKEN_RECORD_PREFIX};
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::Signer,
+    system_program,
+};
+use test_fixtures::{
+    clone_keypair, custom_error, lock_metas, preflight, process, token_account, unlock_metas,
+    TokenFixture,
+};
+
+/// A token of a mint whose freeze authority is permissioned_token_wrapper's, and whose
+/// chain config lists `programs`
+struct Fixture {
+    base: TokenFixture,
+    programs: Vec<Pubkey>,
+}
+
+impl Fixture {
+    /// The owner is on the mint's delegate_allowlist when `allow_owner` is set
+    async fn new(programs: Vec<Pubkey>, allow_owner: bool) -> Self {
+        let deployed = [
+            (permission_chain::id(), "permission_chain"),
+            (
+                permissioned_token_wrapper::id(),
+                "permissioned_token_wrapper",
+            ),
+            (twice_permissioned::id(), "twice_permissioned"),
+            (delegate_allowlist::id(), "delegate_allowlist"),
+        ];
+        let mut base = TokenFixture::new(&deployed, &permissioned_token_wrapper::id(), 1).await;
+        let (mint, payer) = (base.mint, base.context.payer.pubkey());
+        let init_chain = Instruction {
+            program_id: permission_chain::id(),
+            accounts: permission_chain::accounts::InitChainConfig {
+                mint,
+                mint_authority: payer,
+                payer,
+                chain_config: chain_config_address(&mint),
+                system_program: system_program::id(),
+            }
+            .to_account_metas(None),
+            data: permission_chain::instruction::InitChainConfig {
+                admin: payer,
+                programs: programs.clone(),
+            }
+            .data(),
+        };
+        let init_allowlist = Instruction {
+            program_id: delegate_allowlist::id(),
+            accounts: delegate_allowlist::accounts::InitAllowlist {
+                mint,
+                mint_authority: payer,
+                payer,
+                allowlist: allowlist_address(&mint),
+                system_program: system_program::id(),
+            }
+            .to_account_metas(None),
+            data: delegate_allowlist::instruction::InitAllowlist {
+                admin: payer,
+                delegates: if allow_owner {
+                    vec![base.owner.pubkey()]
+                } else {
+                    vec![]
+                },
+            }
+            .data(),
+        };
+        process(&mut base.context, &[init_chain, init_allowlist], &[])
+            .await
+            .unwrap();
+
+        Self { base, programs }
+    }
+
+    /// Interface accounts followed by the chain config and every downstream program,
+    /// as the chain's preflight expects them
+    fn with_chain(&self, mut base: Vec<AccountMeta>) -> Vec<AccountMeta> {
+        base.push(AccountMeta::new_readonly(
+            chain_config_address(&self.base.mint),
+            false,
+        ));
+        base.extend(
+            self.programs
+                .iter()
+                .map(|program| AccountMeta::new_readonly(*program, false)),
+        );
+        base
+    }
+
+    async fn lock(&mut self) -> Result<(), BanksClientError> {
+        let (token, mint, owner, payer) = (
+            self.base.token,
+            self.base.mint,
+            self.base.owner.pubkey(),
+            self.base.context.payer.pubkey(),
+        );
+        let base = self.with_chain(lock_metas(&token, &mint, &owner, &payer));
+        let mut accounts = permission_chain::accounts::Lock {
+            token,
+            mint,
+            delegate: owner,
+            payer,
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None);
+        accounts.extend(
+            preflight(
+                &mut self.base.context,
+                &permission_chain::id(),
+                "lock",
+                base,
+            )
+            .await,
+        );
+        let ix = Instruction {
+            program_id: permission_chain::id(),
+            accounts,
+            data: permission_chain::instruction::Lock { nonce: None }.data(),
+        };
+        let owner = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&owner]).await
+    }
+
+    async fn unlock(&mut self) -> Result<(), BanksClientError> {
+        let (token, mint, owner) = (self.base.token, self.base.mint, self.base.owner.pubkey());
+        let base = self.with_chain(unlock_metas(&token, &mint, &owner));
+        let mut accounts = permission_chain::accounts::Unlock {
+            token,
+            mint,
+            delegate: owner,
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None);
+        accounts.extend(
+            preflight(
+                &mut self.base.context,
+                &permission_chain::id(),
+                "unlock",
+                base,
+            )
+            .await,
+        );
+        let ix = Instruction {
+            program_id: permission_chain::id(),
+            accounts,
+            data: permission_chain::instruction::Unlock { nonce: None }.data(),
+        };
+        let owner = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&owner]).await
+    }
+
+    async fn is_frozen(&mut self) -> bool {
+        token_account(&mut self.base.context, self.base.token)
+            .await
+            .is_frozen()
+    }
+
+    async fn wrapper_lockers(&mut self) -> Vec<Pubkey> {
+        let token_record = Pubkey::find_program_address(
+            &[self.base.token.as_ref(), TOKEN_RECORD_PREFIX.as_bytes()],
+            &permissioned_token_wrapper::id(),
+        )
+        .0;
+        let account = self
+            .context
+            .banks_client
+            .get_account(token_record)
+            .await
+            .unwrap()
+            .unwrap();
+        TokenRecord::try_deserialize(&mut &account.data[..])
+            .unwrap()
+            .lockers
+    }
+}
+
+#[tokio::test]
+async fn locks_when_the_freezing_program_and_the_approver_agree() {
+    let programs = vec![permissioned_token_wrapper::id(), delegate_allowlist::id()];
+    let mut fixture = Fixture::new(programs, true).await;
+
+    fixture.lock().await.unwrap();
+    assert!(fixture.is_frozen().await);
+    assert_eq!(
+        fixture.wrapper_lockers().await,
+        vec![fixture.base.owner.pubkey()]
+    );
+
+    fixture.unlock().await.unwrap();
+    assert!(!fixture.is_frozen().await);
+    assert!(fixture.wrapper_lockers().await.is_empty());
+}
+
+#[tokio::test]
+async fn approver_rejects_a_lock_the_freezing_program_accepts() {
+    let programs = vec![permissioned_token_wrapper::id(), delegate_allowlist::id()];
+    let mut fixture = Fixture::new(programs, false).await;
+
+    let err = fixture.lock().await.unwrap_err();
+    assert_eq!(
+        custom_error(&err),
+        Some(DelegateAllowlistError::DelegateNotAllowed.into())
+    );
+    assert!(!fixture.is_frozen().await);
+}
+
+#[tokio::test]
+async fn two_freezing_programs_cannot_be_chained() {
+    // the mint's freeze authority is the wrapper's, so twice_permissioned's freeze
+    // authority constraint fails after the wrapper froze the token
+    let programs = vec![permissioned_token_wrapper::id(), twice_permissioned::id()];
+    let mut fixture = Fixture::new(programs, true).await;
+
+    let err = fixture.lock().await.unwrap_err();
+    assert_eq!(
+        custom_error(&err),
+        Some(anchor_lang::error::ErrorCode::ConstraintRaw.into())
+    );
+    assert!(!fixture.is_frozen().await);
+}
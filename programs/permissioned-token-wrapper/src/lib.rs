@@ -1,4 +1,5 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program_option::COption;
 use anchor_spl::token_interface::{Mint, TokenInterface, TokenAccount, self};
 
 use interface::{IAccountMeta, PreflightAccounts};
@@ -28,15 +29,37 @@ pub mod permissioned_token_wrapper {
         }
     }
 
+    pub fn init_locker_list(
+        ctx: Context<InitLockerList>,
+        admin: Pubkey,
+        allowlist: Vec<Pubkey>,
+        denylist: Vec<Pubkey>,
+    ) -> Result<()> {
+        let locker_list = &mut ctx.accounts.locker_list;
+        locker_list.admin = admin;
+        locker_list.set(allowlist, denylist)
+    }
+
+    pub fn update_locker_list(
+        ctx: Context<UpdateLockerList>,
+        allowlist: Vec<Pubkey>,
+        denylist: Vec<Pubkey>,
+    ) -> Result<()> {
+        ctx.accounts.locker_list.set(allowlist, denylist)
+    }
+
     pub fn preflight_lock(ctx: Context<ILock>) -> Result<Vec<u8>> {
         let token = ctx.accounts.token.key();
+        let mint = ctx.accounts.mint.key();
         let (program_control, _) = Pubkey::find_program_address(&[STATIC_PREFIX.as_bytes()], &crate::id());
         let (token_record, _) = Pubkey::find_program_address(&[token.key().as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], &crate::id());
+        let (locker_list, _) = Pubkey::find_program_address(&[mint.as_ref(), LOCKER_LIST_PREFIX.as_bytes()], &crate::id());
         let system_program = System::id();
         Ok(PreflightAccounts { accounts: vec![
             IAccountMeta { pubkey: program_control, signer: false, writable: false }, 
             IAccountMeta { pubkey: token_record, signer: false, writable: true }, 
-            IAccountMeta { pubkey: system_program, signer: false, writable: false }
+            IAccountMeta { pubkey: system_program, signer: false, writable: false },
+            IAccountMeta { pubkey: locker_list, signer: false, writable: false }
         ] }.try_to_vec()?)
     }
 
@@ -53,57 +76,654 @@ pub mod permissioned_token_wrapper {
     }
 
     pub fn lock(ctx: Context<Lock>) -> Result<()> {
-        ctx.accounts.token_record.locked = 1;
-
-        let static_seeds: &[u8] = &STATIC_PREFIX.as_bytes();
-        let bump_seed = ctx.bumps.get("program_control").unwrap();
-        let seeds = &[&static_seeds[..], &[*bump_seed]];
-        let binding = [&seeds[..]];
-        let ctx = CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            token_interface::FreezeAccount {
-                account: ctx.accounts.token.to_account_info(),
-                mint: ctx.accounts.mint.to_account_info(),
-                authority: ctx.accounts.program_control.to_account_info(),
-            },
-            &binding,
-        );
-        token_interface::freeze_account(ctx)?;
+        ctx.accounts.token_record.version = TOKEN_RECORD_VERSION;
+        let delegate = ctx.accounts.delegate.key();
+        let is_frozen = ctx.accounts.token.is_frozen();
+        if !ctx.accounts.token_record.lock(delegate, is_frozen)? {
+            return Ok(());
+        }
+        freeze(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.token.to_account_info(),
+            &ctx.accounts.mint.to_account_info(),
+            &ctx.accounts.program_control,
+            *ctx.bumps.get("program_control").unwrap(),
+        )
+    }
+
+    /// Same accounts as `lock`
+    pub fn preflight_lock_exclusive(ctx: Context<ILock>) -> Result<Vec<u8>> {
+        preflight_lock(ctx)
+    }
+
+    /// Locks the token for `delegate` alone: fails if anyone else holds a lock, and no other
+    /// delegate can lock until `delegate` unlocks. Lets programs that must thaw the token on
+    /// their own later, e.g. to seize collateral, rule out co-lockers.
+    pub fn lock_exclusive(ctx: Context<Lock>) -> Result<()> {
+        ctx.accounts.token_record.version = TOKEN_RECORD_VERSION;
+        let delegate = ctx.accounts.delegate.key();
+        let is_frozen = ctx.accounts.token.is_frozen();
+        ctx.accounts.token_record.lock_exclusive(delegate, is_frozen)?;
+        freeze(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.token.to_account_info(),
+            &ctx.accounts.mint.to_account_info(),
+            &ctx.accounts.program_control,
+            *ctx.bumps.get("program_control").unwrap(),
+        )
+    }
+
+    /// Lets `delegate` lock `token` with `lock_on_receive`, the owner's consent to being sent
+    /// tokens that stay locked. Unlike approving the delegate, this works while `token` is
+    /// frozen and leaves its SPL delegate alone
+    pub fn opt_in_to_receive(ctx: Context<OptInToReceive>, delegate: Pubkey) -> Result<()> {
+        let receive_opt_in = &mut ctx.accounts.receive_opt_in;
+        receive_opt_in.token = ctx.accounts.token.key();
+        receive_opt_in.owner = ctx.accounts.owner.key();
+        receive_opt_in.delegate = delegate;
         Ok(())
     }
 
+    /// Withdraws the consent given with `opt_in_to_receive`, locks already taken stay
+    pub fn opt_out_of_receive(_ctx: Context<OptOutOfReceive>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Same accounts as `lock`, followed by the receive opt-in
+    pub fn preflight_lock_on_receive(ctx: Context<ILock>) -> Result<Vec<u8>> {
+        let token = ctx.accounts.token.key();
+        let delegate = ctx.accounts.delegate.key();
+        let mut accounts = PreflightAccounts::try_from_slice(&preflight_lock(ctx)?)?.accounts;
+        let (receive_opt_in, _) = Pubkey::find_program_address(&[token.as_ref(), delegate.as_ref(), RECEIVE_OPT_IN_PREFIX.as_bytes()], &crate::id());
+        accounts.push(IAccountMeta { pubkey: receive_opt_in, signer: false, writable: false });
+        Ok(PreflightAccounts { accounts }.try_to_vec()?)
+    }
+
+    /// Locks a token account the delegate has no authority over, e.g. one it just transferred
+    /// tokens to, as long as its owner opted in with `opt_in_to_receive`
+    pub fn lock_on_receive(ctx: Context<LockOnReceive>) -> Result<()> {
+        ctx.accounts.token_record.version = TOKEN_RECORD_VERSION;
+        let delegate = ctx.accounts.delegate.key();
+        let is_frozen = ctx.accounts.token.is_frozen();
+        if !ctx.accounts.token_record.lock(delegate, is_frozen)? {
+            return Ok(());
+        }
+        freeze(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.token.to_account_info(),
+            &ctx.accounts.mint.to_account_info(),
+            &ctx.accounts.program_control,
+            *ctx.bumps.get("program_control").unwrap(),
+        )
+    }
+
     pub fn unlock(ctx: Context<Unlock>) -> Result<()> {
-        ctx.accounts.token_record.locked = 0;
-
-        let static_seeds: &[u8] = &STATIC_PREFIX.as_bytes();
-        let bump_seed = ctx.bumps.get("program_control").unwrap();
-        let seeds = &[&static_seeds[..], &[*bump_seed]];
-        let binding = [&seeds[..]];
-        let ctx = CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            token_interface::ThawAccount {
-                account: ctx.accounts.token.to_account_info(),
-                mint: ctx.accounts.mint.to_account_info(),
-                authority: ctx.accounts.program_control.to_account_info(),
-            },
-            &binding,
+        let delegate = ctx.accounts.delegate.key();
+        if !ctx.accounts.token_record.unlock(delegate)? {
+            return Ok(());
+        }
+        thaw(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.token.to_account_info(),
+            &ctx.accounts.mint.to_account_info(),
+            &ctx.accounts.program_control,
+            *ctx.bumps.get("program_control").unwrap(),
+        )
+    }
+
+    /// Reallocs a token record written by an older program version into the current layout.
+    ///
+    /// v0 records are detected by their length alone: their only field, `locked`, sits where
+    /// `version` is now and a locked v0 record stores 1 there, the same byte as version 1.
+    pub fn migrate_record(ctx: Context<MigrateRecord>) -> Result<()> {
+        let token_record = &ctx.accounts.token_record;
+        let record = {
+            let data = token_record.try_borrow_data()?;
+            require!(
+                data.len() > 8 && data[..8] == TokenRecord::discriminator(),
+                PermissionedTokenError::UnknownRecordVersion
+            );
+            if data.len() == LEGACY_TOKEN_RECORD_SPACE {
+                // v0 records only stored `locked`, so credit the lock to everyone who was
+                // allowed to unlock it: the token owner and its delegate, if any
+                let locked = data[8];
+                let token = &ctx.accounts.token;
+                let mut lockers = vec![];
+                if locked == 1 {
+                    lockers.push(token.owner);
+                    if let COption::Some(delegate) = token.delegate {
+                        if delegate != token.owner {
+                            lockers.push(delegate);
+                        }
+                    }
+                }
+                TokenRecord {
+                    version: TOKEN_RECORD_VERSION,
+                    locked,
+                    lockers,
+                }
+            } else if data[8] == TOKEN_RECORD_VERSION {
+                return Ok(());
+            } else {
+                return err!(PermissionedTokenError::UnknownRecordVersion);
+            }
+        };
+
+        let rent = Rent::get()?.minimum_balance(TokenRecord::SPACE);
+        let lamports = token_record.lamports();
+        if lamports < rent {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: token_record.to_account_info(),
+                    },
+                ),
+                rent - lamports,
+            )?;
+        }
+        token_record.realloc(TokenRecord::SPACE, true)?;
+        record.try_serialize(&mut &mut token_record.try_borrow_mut_data()?[..])?;
+        Ok(())
+    }
+
+    /// Remaining accounts are `n` (token, mint) pairs
+    pub fn preflight_lock_many<'info>(ctx: Context<'_, '_, '_, 'info, ILockMany<'info>>) -> Result<Vec<u8>> {
+        let pairs = ctx.remaining_accounts;
+        require!(!pairs.is_empty() && pairs.len() % 2 == 0, PermissionedTokenError::InvalidBatchAccounts);
+        let (program_control, _) = Pubkey::find_program_address(&[STATIC_PREFIX.as_bytes()], &crate::id());
+        let system_program = System::id();
+        let mut accounts = vec![
+            IAccountMeta { pubkey: program_control, signer: false, writable: false },
+            IAccountMeta { pubkey: system_program, signer: false, writable: false },
+        ];
+        for pair in pairs.chunks(2) {
+            let (token_record, _) = Pubkey::find_program_address(&[pair[0].key.as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], &crate::id());
+            let (locker_list, _) = Pubkey::find_program_address(&[pair[1].key.as_ref(), LOCKER_LIST_PREFIX.as_bytes()], &crate::id());
+            accounts.push(IAccountMeta { pubkey: token_record, signer: false, writable: true });
+            accounts.push(IAccountMeta { pubkey: locker_list, signer: false, writable: false });
+        }
+        Ok(PreflightAccounts { accounts }.try_to_vec()?)
+    }
+
+    /// Remaining accounts are `n` (token, mint) pairs
+    pub fn preflight_unlock_many<'info>(ctx: Context<'_, '_, '_, 'info, IUnlockMany<'info>>) -> Result<Vec<u8>> {
+        let pairs = ctx.remaining_accounts;
+        require!(!pairs.is_empty() && pairs.len() % 2 == 0, PermissionedTokenError::InvalidBatchAccounts);
+        let (program_control, _) = Pubkey::find_program_address(&[STATIC_PREFIX.as_bytes()], &crate::id());
+        let mut accounts = vec![
+            IAccountMeta { pubkey: program_control, signer: false, writable: false },
+        ];
+        for pair in pairs.chunks(2) {
+            let (token_record, _) = Pubkey::find_program_address(&[pair[0].key.as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], &crate::id());
+            accounts.push(IAccountMeta { pubkey: token_record, signer: false, writable: true });
+        }
+        Ok(PreflightAccounts { accounts }.try_to_vec()?)
+    }
+
+    /// Remaining accounts are `n` (token, mint) pairs, `program_control`, `system_program`,
+    /// then `n` (token_record, locker_list) pairs
+    pub fn lock_many<'info>(ctx: Context<'_, '_, '_, 'info, LockMany<'info>>) -> Result<()> {
+        let remaining_accounts = ctx.remaining_accounts;
+        require!(
+            remaining_accounts.len() > 2 && (remaining_accounts.len() - 2) % 4 == 0,
+            PermissionedTokenError::InvalidBatchAccounts
+        );
+        let n = (remaining_accounts.len() - 2) / 4;
+        let (pairs, rest) = remaining_accounts.split_at(2 * n);
+        let program_control = &rest[0];
+        let system_program = &rest[1];
+        let records = &rest[2..];
+        let bump = check_program_control(program_control)?;
+        require_keys_eq!(system_program.key(), System::id(), PermissionedTokenError::InvalidBatchAccounts);
+
+        let delegate = ctx.accounts.delegate.key();
+        let token_program = ctx.accounts.token_program.to_account_info();
+        for (pair, record_accounts) in pairs.chunks(2).zip(records.chunks(2)) {
+            let (token, mint) = (&pair[0], &pair[1]);
+            let (token_record, locker_list) = (&record_accounts[0], &record_accounts[1]);
+
+            let token_account = load_token(token, mint, program_control)?;
+            require!(
+                token_account.owner == delegate || token_account.delegate == COption::Some(delegate),
+                PermissionedTokenError::UnauthorizedDelegate
+            );
+            let (expected_list, _) = Pubkey::find_program_address(&[mint.key.as_ref(), LOCKER_LIST_PREFIX.as_bytes()], &crate::id());
+            require_keys_eq!(locker_list.key(), expected_list, PermissionedTokenError::InvalidBatchAccounts);
+            require!(locker_list_permits(locker_list, &delegate), PermissionedTokenError::LockerNotPermitted);
+
+            let mut record = load_or_init_token_record(
+                token,
+                token_record,
+                &ctx.accounts.payer.to_account_info(),
+                system_program,
+            )?;
+            let should_freeze = record.lock(delegate, token_account.is_frozen())?;
+            record.try_serialize(&mut &mut token_record.try_borrow_mut_data()?[..])?;
+            if should_freeze {
+                freeze(&token_program, token, mint, program_control, bump)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remaining accounts are `n` (token, mint) pairs, `program_control`, then `n` token records
+    pub fn unlock_many<'info>(ctx: Context<'_, '_, '_, 'info, UnlockMany<'info>>) -> Result<()> {
+        let remaining_accounts = ctx.remaining_accounts;
+        require!(
+            remaining_accounts.len() > 1 && (remaining_accounts.len() - 1) % 3 == 0,
+            PermissionedTokenError::InvalidBatchAccounts
         );
-        token_interface::thaw_account(ctx)?;
+        let n = (remaining_accounts.len() - 1) / 3;
+        let (pairs, rest) = remaining_accounts.split_at(2 * n);
+        let program_control = &rest[0];
+        let records = &rest[1..];
+        let bump = check_program_control(program_control)?;
+
+        let delegate = ctx.accounts.delegate.key();
+        let token_program = ctx.accounts.token_program.to_account_info();
+        for (pair, token_record) in pairs.chunks(2).zip(records.iter()) {
+            let (token, mint) = (&pair[0], &pair[1]);
+            load_token(token, mint, program_control)?;
+
+            let mut record = load_token_record(token, token_record)?;
+            let should_thaw = record.unlock(delegate)?;
+            record.try_serialize(&mut &mut token_record.try_borrow_mut_data()?[..])?;
+            if should_thaw {
+                thaw(&token_program, token, mint, program_control, bump)?;
+            }
+        }
         Ok(())
     }
 }
 
+fn freeze<'info>(
+    token_program: &AccountInfo<'info>,
+    token: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    program_control: &AccountInfo<'info>,
+    bump: u8,
+) -> Result<()> {
+    let static_seeds: &[u8] = &STATIC_PREFIX.as_bytes();
+    let bump_seed = [bump];
+    let seeds = &[&static_seeds[..], &bump_seed[..]];
+    let binding = [&seeds[..]];
+    let ctx = CpiContext::new_with_signer(
+        token_program.clone(),
+        token_interface::FreezeAccount {
+            account: token.clone(),
+            mint: mint.clone(),
+            authority: program_control.clone(),
+        },
+        &binding,
+    );
+    token_interface::freeze_account(ctx)
+}
+
+fn thaw<'info>(
+    token_program: &AccountInfo<'info>,
+    token: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    program_control: &AccountInfo<'info>,
+    bump: u8,
+) -> Result<()> {
+    let static_seeds: &[u8] = &STATIC_PREFIX.as_bytes();
+    let bump_seed = [bump];
+    let seeds = &[&static_seeds[..], &bump_seed[..]];
+    let binding = [&seeds[..]];
+    let ctx = CpiContext::new_with_signer(
+        token_program.clone(),
+        token_interface::ThawAccount {
+            account: token.clone(),
+            mint: mint.clone(),
+            authority: program_control.clone(),
+        },
+        &binding,
+    );
+    token_interface::thaw_account(ctx)
+}
+
+/// Returns the bump of `program_control` after checking its address
+fn check_program_control(program_control: &AccountInfo) -> Result<u8> {
+    let (expected, bump) = Pubkey::find_program_address(&[STATIC_PREFIX.as_bytes()], &crate::id());
+    require_keys_eq!(program_control.key(), expected, PermissionedTokenError::InvalidBatchAccounts);
+    Ok(bump)
+}
+
+/// Batch equivalent of the `token`, `mint` and `program_control` constraints on `Lock`/`Unlock`
+fn load_token<'info>(
+    token: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    program_control: &AccountInfo<'info>,
+) -> Result<InterfaceAccount<'info, TokenAccount>> {
+    let token_account = InterfaceAccount::<TokenAccount>::try_from(token)?;
+    let mint_account = InterfaceAccount::<Mint>::try_from(mint)?;
+    require_keys_eq!(token_account.mint, mint.key(), PermissionedTokenError::InvalidBatchAccounts);
+    require!(
+        mint_account.freeze_authority == COption::Some(program_control.key()),
+        PermissionedTokenError::InvalidFreezeAuthority
+    );
+    Ok(token_account)
+}
+
+fn load_token_record(token: &AccountInfo, token_record: &AccountInfo) -> Result<TokenRecord> {
+    let (expected, _) = Pubkey::find_program_address(&[token.key.as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], &crate::id());
+    require_keys_eq!(token_record.key(), expected, PermissionedTokenError::InvalidBatchAccounts);
+    require_keys_eq!(*token_record.owner, crate::id(), PermissionedTokenError::InvalidBatchAccounts);
+    TokenRecord::try_deserialize(&mut &token_record.try_borrow_data()?[..])
+}
+
+/// Batch equivalent of `init_if_needed` on `Lock::token_record`
+fn load_or_init_token_record<'info>(
+    token: &AccountInfo<'info>,
+    token_record: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<TokenRecord> {
+    if !token_record.data_is_empty() {
+        return load_token_record(token, token_record);
+    }
+    let (expected, bump) = Pubkey::find_program_address(&[token.key.as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], &crate::id());
+    require_keys_eq!(token_record.key(), expected, PermissionedTokenError::InvalidBatchAccounts);
+
+    let bump_seed = [bump];
+    let seeds = &[token.key.as_ref(), TOKEN_RECORD_PREFIX.as_bytes(), &bump_seed[..]];
+    let binding = [&seeds[..]];
+    let rent = Rent::get()?.minimum_balance(TokenRecord::SPACE);
+    let current_lamports = token_record.lamports();
+    if current_lamports == 0 {
+        anchor_lang::system_program::create_account(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                anchor_lang::system_program::CreateAccount {
+                    from: payer.clone(),
+                    to: token_record.clone(),
+                },
+                &binding,
+            ),
+            rent,
+            TokenRecord::SPACE as u64,
+            &crate::id(),
+        )?;
+    } else {
+        // anyone can send lamports to the PDA before it's created, which makes
+        // `create_account` fail, so top it up and allocate it in place instead
+        let shortfall = rent.saturating_sub(current_lamports);
+        if shortfall > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    system_program.clone(),
+                    anchor_lang::system_program::Transfer {
+                        from: payer.clone(),
+                        to: token_record.clone(),
+                    },
+                ),
+                shortfall,
+            )?;
+        }
+        anchor_lang::system_program::allocate(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                anchor_lang::system_program::Allocate {
+                    account_to_allocate: token_record.clone(),
+                },
+                &binding,
+            ),
+            TokenRecord::SPACE as u64,
+        )?;
+        anchor_lang::system_program::assign(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                anchor_lang::system_program::Assign {
+                    account_to_assign: token_record.clone(),
+                },
+                &binding,
+            ),
+            &crate::id(),
+        )?;
+    }
+    Ok(TokenRecord { version: TOKEN_RECORD_VERSION, locked: 0, lockers: vec![] })
+}
+
 pub const STATIC_PREFIX: &'static str = "static";
 pub const TOKEN_RECORD_PREFIX: &'static str = "token_record";
+pub const LOCKER_LIST_PREFIX: &'static str = "locker_list";
+pub const RECEIVE_OPT_IN_PREFIX: &'static str = "receive_opt_in";
+/// Maximum number of distinct delegates that can hold a lock on one token account
+pub const MAX_LOCKERS: usize = 8;
+/// Layout version written to every `TokenRecord`, bump it whenever the layout changes
+/// and teach `migrate_record` to upgrade from the previous one
+pub const TOKEN_RECORD_VERSION: u8 = 1;
+/// Size of the unversioned v0 record, which only stored `locked`. This size is the only
+/// way to tell v0 apart, its `locked` byte is indistinguishable from `version`
+pub const LEGACY_TOKEN_RECORD_SPACE: usize = 8 + 1;
+/// `locked` of a record whose single locker took the lock with `lock_exclusive`
+pub const LOCKED_EXCLUSIVE: u8 = 2;
+
+/// Tracks every delegate currently holding a lock on a token account.
+/// The account is frozen while `lockers` is non-empty.
+///
+/// State machine (`Unlocked` is `locked == 0`, `Locked(n)` is `locked == 1` with n lockers,
+/// `Exclusive` is `locked == LOCKED_EXCLUSIVE` with a single locker):
+///
+/// - `Unlocked` --lock--> `Locked(1)`, freezes the token account.
+///   Fails with `FrozenExternally` if the account was already frozen by other means.
+/// - `Locked(n)` --lock by a new delegate--> `Locked(n + 1)`, fails with `TooManyLockers`
+///   past `MAX_LOCKERS`.
+/// - `Locked(n)` --lock by a current locker--> fails with `AlreadyLocked`.
+/// - `Locked(n > 1)` --unlock by a current locker--> `Locked(n - 1)`.
+/// - `Locked(1)` --unlock by the last locker--> `Unlocked`, thaws the token account.
+/// - `Locked(n)` --unlock by a non-locker--> fails with `LockNotHeld`.
+/// - `Unlocked` --unlock--> fails with `NotLocked`.
+/// - `Unlocked` --lock_exclusive--> `Exclusive`, freezes the token account.
+///   Fails with `FrozenExternally` like `lock`.
+/// - `Locked(n)` --lock_exclusive--> fails with `AlreadyLocked` for a current locker,
+///   `LockedByOthers` otherwise.
+/// - `Exclusive` --lock or lock_exclusive by another delegate--> fails with
+///   `ExclusivelyLocked`.
+/// - `Exclusive` --unlock by the locker--> `Unlocked`, thaws the token account.
 #[account]
 pub struct TokenRecord {
+    pub version: u8,
     pub locked: u8,
+    pub lockers: Vec<Pubkey>,
+}
+
+impl TokenRecord {
+    pub const SPACE: usize = 8 + 1 + 1 + 4 + 32 * MAX_LOCKERS;
+
+    /// Adds `delegate` as a locker, returning whether the token account must now be frozen
+    pub fn lock(&mut self, delegate: Pubkey, is_frozen: bool) -> Result<bool> {
+        require!(
+            !self.lockers.contains(&delegate),
+            PermissionedTokenError::AlreadyLocked
+        );
+        require!(
+            self.locked != LOCKED_EXCLUSIVE,
+            PermissionedTokenError::ExclusivelyLocked
+        );
+        require!(
+            self.lockers.len() < MAX_LOCKERS,
+            PermissionedTokenError::TooManyLockers
+        );
+        self.lockers.push(delegate);
+
+        // Only the first locker actually freezes the account
+        if self.lockers.len() > 1 {
+            return Ok(false);
+        }
+        require!(!is_frozen, PermissionedTokenError::FrozenExternally);
+        self.locked = 1;
+        Ok(true)
+    }
+
+    /// Makes `delegate` the only locker, the token account must then be frozen
+    pub fn lock_exclusive(&mut self, delegate: Pubkey, is_frozen: bool) -> Result<()> {
+        require!(
+            !self.lockers.contains(&delegate),
+            PermissionedTokenError::AlreadyLocked
+        );
+        require!(
+            self.locked != LOCKED_EXCLUSIVE,
+            PermissionedTokenError::ExclusivelyLocked
+        );
+        require!(
+            self.lockers.is_empty(),
+            PermissionedTokenError::LockedByOthers
+        );
+        require!(!is_frozen, PermissionedTokenError::FrozenExternally);
+        self.lockers.push(delegate);
+        self.locked = LOCKED_EXCLUSIVE;
+        Ok(())
+    }
+
+    /// Removes `delegate`'s lock, returning whether the token account must now be thawed
+    pub fn unlock(&mut self, delegate: Pubkey) -> Result<bool> {
+        require!(
+            self.locked != 0 && !self.lockers.is_empty(),
+            PermissionedTokenError::NotLocked
+        );
+        let position = self
+            .lockers
+            .iter()
+            .position(|locker| *locker == delegate)
+            .ok_or(PermissionedTokenError::LockNotHeld)?;
+        self.lockers.remove(position);
+
+        // The account stays frozen until every locker has released it
+        if !self.lockers.is_empty() {
+            return Ok(false);
+        }
+        self.locked = 0;
+        Ok(true)
+    }
+}
+
+/// Maximum number of entries in each of a mint's allowlist and denylist
+pub const MAX_LOCKER_LIST_ENTRIES: usize = 16;
+
+/// Per-mint list of delegates the issuer permits (or forbids) to lock its tokens.
+/// An empty allowlist permits every delegate that is not denylisted.
+#[account]
+pub struct LockerList {
+    pub admin: Pubkey,
+    pub allowlist: Vec<Pubkey>,
+    pub denylist: Vec<Pubkey>,
+}
+
+impl LockerList {
+    pub const SPACE: usize = 8 + 32 + 2 * (4 + 32 * MAX_LOCKER_LIST_ENTRIES);
+
+    pub fn set(&mut self, allowlist: Vec<Pubkey>, denylist: Vec<Pubkey>) -> Result<()> {
+        require!(
+            allowlist.len() <= MAX_LOCKER_LIST_ENTRIES && denylist.len() <= MAX_LOCKER_LIST_ENTRIES,
+            PermissionedTokenError::LockerListFull
+        );
+        self.allowlist = allowlist;
+        self.denylist = denylist;
+        Ok(())
+    }
+
+    pub fn permits(&self, delegate: &Pubkey) -> bool {
+        !self.denylist.contains(delegate)
+            && (self.allowlist.is_empty() || self.allowlist.contains(delegate))
+    }
+}
+
+/// Consent of `owner` to `delegate` locking `token` through `lock_on_receive`.
+/// It lapses when the token account changes owner
+#[account]
+pub struct ReceiveOptIn {
+    pub token: Pubkey,
+    pub owner: Pubkey,
+    pub delegate: Pubkey,
+}
+
+impl ReceiveOptIn {
+    pub const SPACE: usize = 8 + 32 * 3;
+}
+
+fn receive_opt_in_valid(receive_opt_in: &AccountInfo, token: &TokenAccount) -> bool {
+    if receive_opt_in.data_is_empty() || *receive_opt_in.owner != crate::id() {
+        return false;
+    }
+    match ReceiveOptIn::try_deserialize(&mut &receive_opt_in.data.borrow()[..]) {
+        Ok(opt_in) => opt_in.owner == token.owner,
+        Err(_) => false,
+    }
+}
+
+/// Mints that never created a locker list are open to any delegate
+fn locker_list_permits(locker_list: &AccountInfo, delegate: &Pubkey) -> bool {
+    if locker_list.data_is_empty() {
+        return true;
+    }
+    if *locker_list.owner != crate::id() {
+        return false;
+    }
+    match LockerList::try_deserialize(&mut &locker_list.data.borrow()[..]) {
+        Ok(list) => list.permits(delegate),
+        Err(_) => false,
+    }
+}
+
+#[error_code]
+pub enum PermissionedTokenError {
+    #[msg("Token account already has the maximum number of lockers")]
+    TooManyLockers,
+    #[msg("Delegate does not hold a lock on this token account")]
+    LockNotHeld,
+    #[msg("Delegate already holds a lock on this token account")]
+    AlreadyLocked,
+    #[msg("Token account is not locked")]
+    NotLocked,
+    #[msg("Token account was frozen outside of this program")]
+    FrozenExternally,
+    #[msg("Batch accounts do not match the expected layout")]
+    InvalidBatchAccounts,
+    #[msg("Delegate is neither the owner nor the delegate of the token account")]
+    UnauthorizedDelegate,
+    #[msg("Mint freeze authority is not the program control account")]
+    InvalidFreezeAuthority,
+    #[msg("Token record has an unknown layout version")]
+    UnknownRecordVersion,
+    #[msg("Delegate is not permitted to lock tokens of this mint")]
+    LockerNotPermitted,
+    #[msg("Locker list exceeds the maximum number of entries")]
+    LockerListFull,
+    #[msg("Token account is locked exclusively by another delegate")]
+    ExclusivelyLocked,
+    #[msg("Token account is already locked by other delegates")]
+    LockedByOthers,
+    #[msg("Owner of the receiving token account has not opted in to this delegate locking it")]
+    ReceiverNotOptedIn,
 }
 
 #[derive(Accounts)]
 pub struct SetIxAccounts {
 }
 
+#[derive(Accounts)]
+pub struct InitLockerList<'info> {
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = mint.mint_authority.is_some() && mint.mint_authority.unwrap() == mint_authority.key())]
+    mint_authority: Signer<'info>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    #[account(init, payer=payer, space=LockerList::SPACE, seeds=[mint.key().as_ref(), LOCKER_LIST_PREFIX.as_bytes()], bump)]
+    locker_list: Account<'info, LockerList>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateLockerList<'info> {
+    mint: InterfaceAccount<'info, Mint>,
+    admin: Signer<'info>,
+    #[account(mut, has_one=admin, seeds=[mint.key().as_ref(), LOCKER_LIST_PREFIX.as_bytes()], bump)]
+    locker_list: Account<'info, LockerList>,
+}
+
 #[derive(Accounts)]
 pub struct ILock<'info> {
     token: InterfaceAccount<'info, TokenAccount>,
@@ -134,9 +754,82 @@ pub struct Lock<'info> {
         constraint = mint.freeze_authority.is_some() && mint.freeze_authority.unwrap() == program_control.key()
     )]
     program_control: AccountInfo<'info>,
-    #[account(init_if_needed, payer=payer, space=8 + std::mem::size_of::<TokenRecord>(), seeds=[token.key().as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], bump)]
+    #[account(init_if_needed, payer=payer, space=TokenRecord::SPACE, seeds=[token.key().as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], bump)]
     token_record: Account<'info, TokenRecord>,
     system_program: Program<'info, System>,
+    /// CHECK: deserialized in `locker_list_permits`, may not be initialized
+    #[account(
+        seeds=[mint.key().as_ref(), LOCKER_LIST_PREFIX.as_bytes()],
+        bump,
+        constraint = locker_list_permits(&locker_list, &delegate.key()) @ PermissionedTokenError::LockerNotPermitted
+    )]
+    locker_list: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delegate: Pubkey)]
+pub struct OptInToReceive<'info> {
+    #[account(constraint = token.owner == owner.key())]
+    token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    owner: Signer<'info>,
+    #[account(init, payer=owner, space=ReceiveOptIn::SPACE, seeds=[token.key().as_ref(), delegate.as_ref(), RECEIVE_OPT_IN_PREFIX.as_bytes()], bump)]
+    receive_opt_in: Account<'info, ReceiveOptIn>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OptOutOfReceive<'info> {
+    #[account(mut)]
+    owner: Signer<'info>,
+    #[account(mut, close=owner, has_one=owner)]
+    receive_opt_in: Account<'info, ReceiveOptIn>,
+}
+
+#[derive(Accounts)]
+pub struct LockOnReceive<'info> {
+    #[account(mut, constraint = token.mint == mint.key())]
+    token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    delegate: Signer<'info>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    token_program: Interface<'info, TokenInterface>,
+    /// CHECK: nothing
+    #[account(
+        seeds=[STATIC_PREFIX.as_bytes()], 
+        bump,
+        constraint = mint.freeze_authority.is_some() && mint.freeze_authority.unwrap() == program_control.key()
+    )]
+    program_control: AccountInfo<'info>,
+    #[account(init_if_needed, payer=payer, space=TokenRecord::SPACE, seeds=[token.key().as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], bump)]
+    token_record: Account<'info, TokenRecord>,
+    system_program: Program<'info, System>,
+    /// CHECK: deserialized in `locker_list_permits`, may not be initialized
+    #[account(
+        seeds=[mint.key().as_ref(), LOCKER_LIST_PREFIX.as_bytes()],
+        bump,
+        constraint = locker_list_permits(&locker_list, &delegate.key()) @ PermissionedTokenError::LockerNotPermitted
+    )]
+    locker_list: AccountInfo<'info>,
+    /// CHECK: deserialized in `receive_opt_in_valid`, may not be initialized
+    #[account(
+        seeds=[token.key().as_ref(), delegate.key().as_ref(), RECEIVE_OPT_IN_PREFIX.as_bytes()],
+        bump,
+        constraint = receive_opt_in_valid(&receive_opt_in, &token) @ PermissionedTokenError::ReceiverNotOptedIn
+    )]
+    receive_opt_in: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateRecord<'info> {
+    token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    /// CHECK: may hold any past layout, checked in `migrate_record`
+    #[account(mut, owner = crate::id(), seeds=[token.key().as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], bump)]
+    token_record: AccountInfo<'info>,
+    system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
@@ -144,7 +837,7 @@ pub struct IUnlock<'info> {
     token: InterfaceAccount<'info, TokenAccount>,
     mint: InterfaceAccount<'info, Mint>,
     /// CHECK: nil
-    delegate: Signer<'info>,
+    delegate: AccountInfo<'info>,
     token_program: Interface<'info, TokenInterface>,
     // #[account(seeds=["unlock".as_bytes()], bump)]
     // ix_accounts: Option<Account<'info, IxAccounts>>
@@ -155,7 +848,6 @@ pub struct Unlock<'info> {
     #[account(mut)]
     token: InterfaceAccount<'info, TokenAccount>,
     mint: InterfaceAccount<'info, Mint>,
-    #[account(constraint = token.owner == delegate.key() || token.delegate.is_some() && token.delegate.unwrap() == delegate.key())]
     delegate: Signer<'info>,
     token_program: Interface<'info, TokenInterface>,
     /// CHECK: nothing
@@ -168,4 +860,33 @@ pub struct Unlock<'info> {
     #[account(mut, seeds=[token.key().as_ref(), b"token_record"], bump)]
     token_record: Account<'info, TokenRecord>,
     system_program: Program<'info, System>,
-}
\ No newline at end of file
+}
+#[derive(Accounts)]
+pub struct ILockMany<'info> {
+    /// CHECK: nil
+    delegate: AccountInfo<'info>,
+    /// CHECK: nil
+    payer: AccountInfo<'info>,
+    token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct LockMany<'info> {
+    delegate: Signer<'info>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct IUnlockMany<'info> {
+    /// CHECK: nil
+    delegate: AccountInfo<'info>,
+    token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct UnlockMany<'info> {
+    delegate: Signer<'info>,
+    token_program: Interface<'info, TokenInterface>,
+}
@@ -0,0 +1,199 @@
+use anchor_lang::{
+    solana_program::program_option::COption, AccountDeserialize, Discriminator, InstructionData,
+    ToAccountMetas,
+};
+use permissioned_token_wrapper::{
+    PermissionedTokenError, TokenRecord, LEGACY_TOKEN_RECORD_SPACE, TOKEN_RECORD_PREFIX,
+    TOKEN_RECORD_VERSION,
+};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    instruction::Instruction,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signer},
+    system_program,
+};
+use spl_token::state::AccountState;
+use test_fixtures::{
+    clone_keypair, custom_error, process, program_control, token_account, TokenFixture,
+};
+
+/// A token of a wrapper mint whose record was written by the unversioned v0 program:
+/// the discriminator followed by `locked` alone
+struct Fixture {
+    base: TokenFixture,
+}
+
+impl Fixture {
+    async fn new(locked: bool) -> Self {
+        let programs = [(permissioned_token_wrapper::id(), "permissioned_token_wrapper")];
+        let mut fixture = Self {
+            base: TokenFixture::new(&programs, &permissioned_token_wrapper::id(), 1).await,
+        };
+        let token = fixture.base.token;
+
+        let mut data = TokenRecord::discriminator().to_vec();
+        data.push(locked as u8);
+        assert_eq!(data.len(), LEGACY_TOKEN_RECORD_SPACE);
+        fixture.set_account(fixture.token_record(), data, permissioned_token_wrapper::id());
+
+        if locked {
+            // v0 froze the token when it locked it
+            let mut state = token_account(&mut fixture.base.context, token).await;
+            state.state = AccountState::Frozen;
+            let mut data = vec![0; spl_token::state::Account::LEN];
+            state.pack_into_slice(&mut data);
+            fixture.set_account(token, data, spl_token::id());
+        }
+        fixture
+    }
+
+    /// Sets the token's SPL delegate in place, as v0 allowed approving before the lock
+    async fn set_delegate(&mut self, delegate: Pubkey) {
+        let mut state = token_account(&mut self.base.context, self.base.token).await;
+        state.delegate = COption::Some(delegate);
+        state.delegated_amount = state.amount;
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        state.pack_into_slice(&mut data);
+        self.set_account(self.base.token, data, spl_token::id());
+    }
+
+    fn set_account(&mut self, key: Pubkey, data: Vec<u8>, owner: Pubkey) {
+        let account = Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner,
+            executable: false,
+            rent_epoch: 0,
+        };
+        self.base.context.set_account(&key, &AccountSharedData::from(account));
+    }
+
+    fn token_record(&self) -> Pubkey {
+        Pubkey::find_program_address(
+            &[self.base.token.as_ref(), TOKEN_RECORD_PREFIX.as_bytes()],
+            &permissioned_token_wrapper::id(),
+        )
+        .0
+    }
+
+    async fn migrate(&mut self) {
+        let ix = self.migrate_ix();
+        process(&mut self.base.context, &[ix], &[]).await.unwrap();
+    }
+
+    fn migrate_ix(&self) -> Instruction {
+        Instruction {
+            program_id: permissioned_token_wrapper::id(),
+            accounts: permissioned_token_wrapper::accounts::MigrateRecord {
+                token: self.base.token,
+                payer: self.base.context.payer.pubkey(),
+                token_record: self.token_record(),
+                system_program: system_program::id(),
+            }
+            .to_account_metas(None),
+            data: permissioned_token_wrapper::instruction::MigrateRecord {}.data(),
+        }
+    }
+
+    /// Size and contents of the token record
+    async fn record(&mut self) -> (usize, TokenRecord) {
+        let account = self
+            .base
+            .context
+            .banks_client
+            .get_account(self.token_record())
+            .await
+            .unwrap()
+            .unwrap();
+        let record = TokenRecord::try_deserialize(&mut &account.data[..]).unwrap();
+        (account.data.len(), record)
+    }
+}
+
+#[tokio::test]
+async fn migrates_a_locked_v0_record_to_the_token_owner() {
+    let mut fixture = Fixture::new(true).await;
+    fixture.migrate().await;
+
+    let (len, record) = fixture.record().await;
+    assert_eq!(len, TokenRecord::SPACE);
+    assert_eq!(record.version, TOKEN_RECORD_VERSION);
+    assert_eq!(record.locked, 1);
+    assert_eq!(record.lockers, vec![fixture.base.owner.pubkey()]);
+
+    // the owner the lock was credited to can release it
+    let ix = Instruction {
+        program_id: permissioned_token_wrapper::id(),
+        accounts: permissioned_token_wrapper::accounts::Unlock {
+            token: fixture.base.token,
+            mint: fixture.base.mint,
+            delegate: fixture.base.owner.pubkey(),
+            token_program: spl_token::id(),
+            program_control: program_control(&permissioned_token_wrapper::id()),
+            token_record: fixture.token_record(),
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: permissioned_token_wrapper::instruction::Unlock {}.data(),
+    };
+    let owner = clone_keypair(&fixture.base.owner);
+    process(&mut fixture.base.context, &[ix], &[&owner]).await.unwrap();
+    assert!(!token_account(&mut fixture.base.context, fixture.base.token).await.is_frozen());
+    let (_, record) = fixture.record().await;
+    assert_eq!(record.locked, 0);
+    assert!(record.lockers.is_empty());
+}
+
+#[tokio::test]
+async fn migrates_a_locked_v0_record_to_the_owner_and_delegate() {
+    let mut fixture = Fixture::new(true).await;
+    let delegate = Keypair::new();
+    fixture.set_delegate(delegate.pubkey()).await;
+    fixture.migrate().await;
+
+    let (_, record) = fixture.record().await;
+    assert_eq!(record.locked, 1);
+    assert_eq!(record.lockers, vec![fixture.base.owner.pubkey(), delegate.pubkey()]);
+}
+
+#[tokio::test]
+async fn rejects_a_record_without_data() {
+    let mut fixture = Fixture::new(false).await;
+    let data = TokenRecord::discriminator().to_vec();
+    fixture.set_account(fixture.token_record(), data, permissioned_token_wrapper::id());
+
+    let ix = fixture.migrate_ix();
+    let err = process(&mut fixture.base.context, &[ix], &[]).await.unwrap_err();
+    assert_eq!(
+        custom_error(&err),
+        Some(PermissionedTokenError::UnknownRecordVersion.into())
+    );
+}
+
+#[tokio::test]
+async fn migrates_an_unlocked_v0_record_without_lockers() {
+    let mut fixture = Fixture::new(false).await;
+    fixture.migrate().await;
+
+    let (len, record) = fixture.record().await;
+    assert_eq!(len, TokenRecord::SPACE);
+    assert_eq!(record.version, TOKEN_RECORD_VERSION);
+    assert_eq!(record.locked, 0);
+    assert!(record.lockers.is_empty());
+}
+
+#[tokio::test]
+async fn migrating_a_current_record_is_a_no_op() {
+    let mut fixture = Fixture::new(true).await;
+    fixture.migrate().await;
+    let (_, migrated) = fixture.record().await;
+
+    fixture.migrate().await;
+    let (len, record) = fixture.record().await;
+    assert_eq!(len, TokenRecord::SPACE);
+    assert_eq!(record.locked, migrated.locked);
+    assert_eq!(record.lockers, migrated.lockers);
+}
@@ -0,0 +1,375 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{
+    self, Approve, Mint, Revoke, TokenAccount, TokenInterface, TransferChecked,
+};
+
+use interface::{
+    call, call_with_post_conditions, freezes_mint, PostConditions, TILock, TIUnlock,
+};
+
+declare_id!("5GNFq35TYBkPK4r7NQfM8iunLh8D1rvWHRGkUa5fFuVj");
+
+/// Non-custodial staking: staked tokens stay in the owner's wallet, approved to and locked
+/// by a delegate PDA of their stake record for as long as they're staked, and earn
+/// `reward_rate` reward tokens per staked token per slot.
+/// The pool's permission program is fixed when the pool is created, and the pool PDA,
+/// which owns the reward vault, never signs calls into it.
+#[program]
+pub mod staking {
+    use super::*;
+
+    pub fn init_pool(ctx: Context<InitPool>, reward_rate: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.admin = ctx.accounts.mint_authority.key();
+        pool.mint = ctx.accounts.mint.key();
+        pool.reward_mint = ctx.accounts.reward_mint.key();
+        pool.perm_program = ctx.accounts.perm_program.key();
+        pool.reward_rate = reward_rate;
+        pool.reward_per_token = 0;
+        pool.last_update_slot = Clock::get()?.slot;
+        Ok(())
+    }
+
+    /// Rewards earned up to now are accrued at the previous rate
+    pub fn update_pool(ctx: Context<UpdatePool>, reward_rate: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.accrue(Clock::get()?.slot)?;
+        pool.reward_rate = reward_rate;
+        Ok(())
+    }
+
+    /// Fails unless the permission program froze the token, a lock that only counts
+    /// towards a quorum doesn't keep the stake in place.
+    /// Remaining accounts are the permission program's lock accounts
+    pub fn stake<'info>(ctx: Context<'_, '_, '_, 'info, Stake<'info>>) -> Result<()> {
+        let slot = Clock::get()?.slot;
+        ctx.accounts.pool.accrue(slot)?;
+        let stake_record = &mut ctx.accounts.stake_record;
+        stake_record.owner = ctx.accounts.owner.key();
+        stake_record.token = ctx.accounts.token.key();
+        stake_record.pool = ctx.accounts.pool.key();
+        stake_record.amount = ctx.accounts.token.amount;
+        stake_record.last_update_slot = slot;
+        stake_record.reward_per_token_paid = ctx.accounts.pool.reward_per_token;
+        stake_record.accrued = 0;
+
+        token_interface::approve(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Approve {
+                    to: ctx.accounts.token.to_account_info(),
+                    delegate: ctx.accounts.delegate.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            ),
+            ctx.accounts.token.amount,
+        )?;
+
+        let stake_record = ctx.accounts.stake_record.key();
+        let bump = [*ctx.bumps.get("delegate").unwrap()];
+        let seeds: &[&[u8]] = &[stake_record.as_ref(), STAKE_DELEGATE_PREFIX.as_bytes(), &bump];
+        let signer = [seeds];
+        let cvt_ctx = CpiContext::new_with_signer(
+            ctx.accounts.perm_program.clone(),
+            TILock {
+                token: ctx.accounts.token.clone(),
+                mint: ctx.accounts.mint.clone(),
+                delegate: delegate_signer(&ctx.accounts.delegate)?,
+                payer: ctx.accounts.owner.clone(),
+                token_program: ctx.accounts.token_program.clone(),
+                perm_program: ctx.accounts.perm_program.clone(),
+            },
+            &signer,
+        )
+        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+        let post_conditions =
+            PostConditions::default().expect_frozen(&ctx.accounts.token.to_account_info());
+        call_with_post_conditions("lock".to_string(), cvt_ctx, None, &post_conditions, false)
+    }
+
+    pub fn claim(ctx: Context<Claim>) -> Result<()> {
+        let slot = Clock::get()?.slot;
+        ctx.accounts.pool.accrue(slot)?;
+        let stake_record = &mut ctx.accounts.stake_record;
+        stake_record.accrue(&ctx.accounts.pool, slot)?;
+
+        let paid = pay_rewards(
+            stake_record.accrued,
+            &ctx.accounts.pool,
+            *ctx.bumps.get("pool").unwrap(),
+            &ctx.accounts.reward_mint,
+            &ctx.accounts.reward_vault,
+            &ctx.accounts.owner_reward,
+            &ctx.accounts.reward_token_program,
+        )?;
+        ctx.accounts.stake_record.accrued -= paid;
+        Ok(())
+    }
+
+    /// Pays out accrued rewards and unlocks the token.
+    /// Remaining accounts are the permission program's unlock accounts
+    pub fn unstake<'info>(ctx: Context<'_, '_, '_, 'info, Unstake<'info>>) -> Result<()> {
+        let slot = Clock::get()?.slot;
+        ctx.accounts.pool.accrue(slot)?;
+        let stake_record = &mut ctx.accounts.stake_record;
+        stake_record.accrue(&ctx.accounts.pool, slot)?;
+
+        // rewards the vault can't cover are forfeited, an underfunded pool must not trap stakes
+        pay_rewards(
+            stake_record.accrued,
+            &ctx.accounts.pool,
+            *ctx.bumps.get("pool").unwrap(),
+            &ctx.accounts.reward_mint,
+            &ctx.accounts.reward_vault,
+            &ctx.accounts.owner_reward,
+            &ctx.accounts.reward_token_program,
+        )?;
+
+        let stake_record = ctx.accounts.stake_record.key();
+        let bump = [*ctx.bumps.get("delegate").unwrap()];
+        let seeds: &[&[u8]] = &[stake_record.as_ref(), STAKE_DELEGATE_PREFIX.as_bytes(), &bump];
+        let signer = [seeds];
+        let cvt_ctx = CpiContext::new_with_signer(
+            ctx.accounts.perm_program.clone(),
+            TIUnlock {
+                token: ctx.accounts.token.clone(),
+                mint: ctx.accounts.mint.clone(),
+                delegate: delegate_signer(&ctx.accounts.delegate)?,
+                token_program: ctx.accounts.token_program.clone(),
+                perm_program: ctx.accounts.perm_program.clone(),
+            },
+            &signer,
+        )
+        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+        call("unlock".to_string(), cvt_ctx, false)?;
+
+        token_interface::revoke(CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Revoke {
+                source: ctx.accounts.token.to_account_info(),
+                authority: ctx.accounts.owner.to_account_info(),
+            },
+        ))
+    }
+}
+
+pub const STAKE_POOL_PREFIX: &'static str = "stake_pool";
+pub const STAKE_RECORD_PREFIX: &'static str = "stake_record";
+pub const STAKE_DELEGATE_PREFIX: &'static str = "delegate";
+
+/// Staking configuration of a mint, also the owner of the reward vault
+#[account]
+pub struct StakePool {
+    pub admin: Pubkey,
+    pub mint: Pubkey,
+    pub reward_mint: Pubkey,
+    /// Permission program staked tokens are locked with, the freeze authority of `mint`
+    pub perm_program: Pubkey,
+    /// Reward tokens per staked token per slot
+    pub reward_rate: u64,
+    /// Reward tokens earned per staked token since the pool was created
+    pub reward_per_token: u128,
+    /// Slot `reward_per_token` was last brought up to date at
+    pub last_update_slot: u64,
+}
+
+impl StakePool {
+    pub const SPACE: usize = 8 + 32 * 4 + 8 + 16 + 8;
+
+    /// Adds the rewards per token earned at the current rate since the last update,
+    /// must run before anything that reads `reward_per_token` or changes the rate
+    pub fn accrue(&mut self, slot: u64) -> Result<()> {
+        let slots = slot.saturating_sub(self.last_update_slot);
+        self.reward_per_token = (self.reward_rate as u128)
+            .checked_mul(slots as u128)
+            .and_then(|earned| self.reward_per_token.checked_add(earned))
+            .ok_or(StakingError::RewardOverflow)?;
+        self.last_update_slot = slot;
+        Ok(())
+    }
+}
+
+#[account]
+pub struct StakeRecord {
+    pub owner: Pubkey,
+    pub token: Pubkey,
+    pub pool: Pubkey,
+    /// Balance of `token` when it was staked
+    pub amount: u64,
+    pub last_update_slot: u64,
+    /// Pool's `reward_per_token` the record's rewards were last accrued up to
+    pub reward_per_token_paid: u128,
+    /// Rewards earned but not paid out yet
+    pub accrued: u64,
+}
+
+impl StakeRecord {
+    pub const SPACE: usize = 8 + 32 * 3 + 8 * 3 + 16;
+
+    /// Adds the rewards earned since the last update, `pool` must be accrued up to `slot`
+    pub fn accrue(&mut self, pool: &StakePool, slot: u64) -> Result<()> {
+        let earned = pool
+            .reward_per_token
+            .checked_sub(self.reward_per_token_paid)
+            .and_then(|per_token| per_token.checked_mul(self.amount as u128))
+            .and_then(|rewards| u64::try_from(rewards).ok())
+            .ok_or(StakingError::RewardOverflow)?;
+        self.accrued = self
+            .accrued
+            .checked_add(earned)
+            .ok_or(StakingError::RewardOverflow)?;
+        self.reward_per_token_paid = pool.reward_per_token;
+        self.last_update_slot = slot;
+        Ok(())
+    }
+}
+
+/// Pays as much of `accrued` as the vault holds and returns the amount paid
+fn pay_rewards<'info>(
+    accrued: u64,
+    pool: &Account<'info, StakePool>,
+    pool_bump: u8,
+    reward_mint: &InterfaceAccount<'info, Mint>,
+    reward_vault: &InterfaceAccount<'info, TokenAccount>,
+    owner_reward: &InterfaceAccount<'info, TokenAccount>,
+    reward_token_program: &Interface<'info, TokenInterface>,
+) -> Result<u64> {
+    let paid = accrued.min(reward_vault.amount);
+    if paid == 0 {
+        return Ok(0);
+    }
+    let bump = [pool_bump];
+    let seeds: &[&[u8]] = &[pool.mint.as_ref(), STAKE_POOL_PREFIX.as_bytes(), &bump];
+    let signer = [seeds];
+    token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            reward_token_program.to_account_info(),
+            TransferChecked {
+                from: reward_vault.to_account_info(),
+                to: owner_reward.to_account_info(),
+                mint: reward_mint.to_account_info(),
+                authority: pool.to_account_info(),
+            },
+            &signer,
+        ),
+        paid,
+        reward_mint.decimals,
+    )?;
+    Ok(paid)
+}
+
+/// The stake record's delegate as a signer, its signer privileges come from the seeds
+/// passed along with every CPI
+fn delegate_signer<'info>(delegate: &AccountInfo<'info>) -> Result<Signer<'info>> {
+    let mut delegate = delegate.clone();
+    delegate.is_signer = true;
+    Signer::try_from(&delegate)
+}
+
+#[error_code]
+pub enum StakingError {
+    #[msg("Rewards overflowed")]
+    RewardOverflow,
+    #[msg("Token account holds nothing to stake")]
+    NothingToStake,
+    #[msg("Permission program is not the freeze authority of the mint")]
+    InvalidPermProgram,
+}
+
+#[derive(Accounts)]
+pub struct InitPool<'info> {
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = mint.mint_authority.is_some() && mint.mint_authority.unwrap() == mint_authority.key())]
+    mint_authority: Signer<'info>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(init, payer=payer, space=StakePool::SPACE, seeds=[mint.key().as_ref(), STAKE_POOL_PREFIX.as_bytes()], bump)]
+    pool: Account<'info, StakePool>,
+    /// CHECK: permission program, must freeze the mint's token accounts
+    #[account(constraint = freezes_mint(&perm_program.key(), &mint) @ StakingError::InvalidPermProgram)]
+    perm_program: AccountInfo<'info>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePool<'info> {
+    mint: InterfaceAccount<'info, Mint>,
+    admin: Signer<'info>,
+    #[account(mut, has_one=admin, has_one=mint, seeds=[mint.key().as_ref(), STAKE_POOL_PREFIX.as_bytes()], bump)]
+    pool: Account<'info, StakePool>,
+}
+
+#[derive(Accounts)]
+pub struct Stake<'info> {
+    #[account(mut)]
+    owner: Signer<'info>,
+    #[account(
+        mut,
+        constraint = token.owner == owner.key(),
+        constraint = token.mint == mint.key(),
+        constraint = token.amount > 0 @ StakingError::NothingToStake
+    )]
+    token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, has_one=mint, has_one=perm_program, seeds=[mint.key().as_ref(), STAKE_POOL_PREFIX.as_bytes()], bump)]
+    pool: Account<'info, StakePool>,
+    #[account(init, payer=owner, space=StakeRecord::SPACE, seeds=[token.key().as_ref(), STAKE_RECORD_PREFIX.as_bytes()], bump)]
+    stake_record: Account<'info, StakeRecord>,
+    /// CHECK: PDA delegate of the staked token
+    #[account(seeds=[stake_record.key().as_ref(), STAKE_DELEGATE_PREFIX.as_bytes()], bump)]
+    delegate: AccountInfo<'info>,
+    token_program: Interface<'info, TokenInterface>,
+    /// CHECK: permission program, checked against the pool
+    perm_program: AccountInfo<'info>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Claim<'info> {
+    owner: Signer<'info>,
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, has_one=mint, has_one=reward_mint, seeds=[mint.key().as_ref(), STAKE_POOL_PREFIX.as_bytes()], bump)]
+    pool: Account<'info, StakePool>,
+    #[account(mut, has_one=owner, has_one=pool)]
+    stake_record: Account<'info, StakeRecord>,
+    reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, constraint = reward_vault.owner == pool.key(), constraint = reward_vault.mint == reward_mint.key())]
+    reward_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = owner_reward.mint == reward_mint.key())]
+    owner_reward: InterfaceAccount<'info, TokenAccount>,
+    reward_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct Unstake<'info> {
+    #[account(mut)]
+    owner: Signer<'info>,
+    #[account(mut)]
+    token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, has_one=mint, has_one=reward_mint, has_one=perm_program, seeds=[mint.key().as_ref(), STAKE_POOL_PREFIX.as_bytes()], bump)]
+    pool: Account<'info, StakePool>,
+    #[account(
+        mut,
+        close=owner,
+        has_one=owner,
+        has_one=token,
+        has_one=pool,
+        seeds=[token.key().as_ref(), STAKE_RECORD_PREFIX.as_bytes()],
+        bump
+    )]
+    stake_record: Account<'info, StakeRecord>,
+    /// CHECK: PDA delegate of the staked token
+    #[account(seeds=[stake_record.key().as_ref(), STAKE_DELEGATE_PREFIX.as_bytes()], bump)]
+    delegate: AccountInfo<'info>,
+    reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, constraint = reward_vault.owner == pool.key(), constraint = reward_vault.mint == reward_mint.key())]
+    reward_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = owner_reward.mint == reward_mint.key())]
+    owner_reward: InterfaceAccount<'info, TokenAccount>,
+    token_program: Interface<'info, TokenInterface>,
+    reward_token_program: Interface<'info, TokenInterface>,
+    /// CHECK: permission program, checked against the pool
+    perm_program: AccountInfo<'info>,
+}
@@ -0,0 +1,385 @@
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use interface::InterfaceError;
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    program_option::COption,
+    pubkey::Pubkey,
+    signature::Signer,
+    system_program,
+};
+use staking::{
+    StakePool, StakeRecord, StakingError, STAKE_DELEGATE_PREFIX, STAKE_POOL_PREFIX,
+    STAKE_RECORD_PREFIX,
+};
+use test_fixtures::{
+    clone_keypair, create_mint, create_token_account, custom_error, lock_metas, preflight,
+    process, token_account, unlock_metas, TokenFixture,
+};
+
+const STAKE_AMOUNT: u64 = 10;
+const REWARD_RATE: u64 = 3;
+const VAULT_FUNDING: u64 = 1_000_000;
+
+fn pool(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[mint.as_ref(), STAKE_POOL_PREFIX.as_bytes()], &staking::id()).0
+}
+
+/// A pool over a mint frozen by `perm_program` whose reward vault holds `vault_funding`,
+/// and an owner holding `STAKE_AMOUNT` tokens and an empty reward account
+struct Fixture {
+    base: TokenFixture,
+    perm_program: Pubkey,
+    reward_mint: Pubkey,
+    reward_vault: Pubkey,
+    owner_reward: Pubkey,
+}
+
+impl Fixture {
+    async fn new(vault_funding: u64) -> Self {
+        Self::with_perm_program(vault_funding, permissioned_token_wrapper::id()).await
+    }
+
+    async fn with_perm_program(vault_funding: u64, perm_program: Pubkey) -> Self {
+        let mut fixture = Self::without_pool(vault_funding, perm_program).await;
+        fixture.init_pool(perm_program).await.unwrap();
+        fixture
+    }
+
+    async fn without_pool(vault_funding: u64, perm_program: Pubkey) -> Self {
+        let programs = [
+            (staking::id(), "staking"),
+            (permissioned_token_wrapper::id(), "permissioned_token_wrapper"),
+            (twice_permissioned::id(), "twice_permissioned"),
+        ];
+        let mut base = TokenFixture::new(&programs, &perm_program, STAKE_AMOUNT).await;
+
+        let context = &mut base.context;
+        let reward_mint = create_mint(context, 6, None).await;
+        let pool = pool(&base.mint);
+        let owner = base.owner.pubkey();
+        let owner_reward = create_token_account(context, &reward_mint, &owner, 0).await;
+        let reward_vault = create_token_account(context, &reward_mint, &pool, vault_funding).await;
+        Self {
+            base,
+            perm_program,
+            reward_mint,
+            reward_vault,
+            owner_reward,
+        }
+    }
+
+    fn pool(&self) -> Pubkey {
+        pool(&self.base.mint)
+    }
+
+    fn stake_record(&self) -> Pubkey {
+        Pubkey::find_program_address(
+            &[self.base.token.as_ref(), STAKE_RECORD_PREFIX.as_bytes()],
+            &staking::id(),
+        )
+        .0
+    }
+
+    /// PDA delegate the stake record locks the token with
+    fn delegate(&self) -> Pubkey {
+        Pubkey::find_program_address(
+            &[self.stake_record().as_ref(), STAKE_DELEGATE_PREFIX.as_bytes()],
+            &staking::id(),
+        )
+        .0
+    }
+
+    async fn init_pool(&mut self, perm_program: Pubkey) -> Result<(), BanksClientError> {
+        let payer = self.base.context.payer.pubkey();
+        let ix = Instruction {
+            program_id: staking::id(),
+            accounts: staking::accounts::InitPool {
+                mint: self.base.mint,
+                mint_authority: payer,
+                payer,
+                reward_mint: self.reward_mint,
+                pool: self.pool(),
+                perm_program,
+                system_program: system_program::id(),
+            }
+            .to_account_metas(None),
+            data: staking::instruction::InitPool {
+                reward_rate: REWARD_RATE,
+            }
+            .data(),
+        };
+        process(&mut self.base.context, &[ix], &[]).await
+    }
+
+    async fn update_pool(&mut self, reward_rate: u64) -> Result<(), BanksClientError> {
+        let ix = Instruction {
+            program_id: staking::id(),
+            accounts: staking::accounts::UpdatePool {
+                mint: self.base.mint,
+                admin: self.base.context.payer.pubkey(),
+                pool: self.pool(),
+            }
+            .to_account_metas(None),
+            data: staking::instruction::UpdatePool { reward_rate }.data(),
+        };
+        process(&mut self.base.context, &[ix], &[]).await
+    }
+
+    async fn stake(&mut self) -> Result<(), BanksClientError> {
+        self.stake_through(self.perm_program).await
+    }
+
+    /// Stakes with `perm_program` as the token's permission program, the remaining accounts
+    /// are always the fixture's permission program's
+    async fn stake_through(&mut self, perm_program: Pubkey) -> Result<(), BanksClientError> {
+        let mut accounts = staking::accounts::Stake {
+            owner: self.base.owner.pubkey(),
+            token: self.base.token,
+            mint: self.base.mint,
+            pool: self.pool(),
+            stake_record: self.stake_record(),
+            delegate: self.delegate(),
+            token_program: spl_token::id(),
+            perm_program,
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None);
+        accounts.extend(self.lock_accounts().await);
+        let ix = Instruction {
+            program_id: staking::id(),
+            accounts,
+            data: staking::instruction::Stake {}.data(),
+        };
+        let owner = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&owner]).await
+    }
+
+    async fn claim(&mut self) -> Result<(), BanksClientError> {
+        let ix = Instruction {
+            program_id: staking::id(),
+            accounts: staking::accounts::Claim {
+                owner: self.base.owner.pubkey(),
+                mint: self.base.mint,
+                pool: self.pool(),
+                stake_record: self.stake_record(),
+                reward_mint: self.reward_mint,
+                reward_vault: self.reward_vault,
+                owner_reward: self.owner_reward,
+                reward_token_program: spl_token::id(),
+            }
+            .to_account_metas(None),
+            data: staking::instruction::Claim {}.data(),
+        };
+        let owner = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&owner]).await
+    }
+
+    async fn unstake(&mut self) -> Result<(), BanksClientError> {
+        let mut accounts = staking::accounts::Unstake {
+            owner: self.base.owner.pubkey(),
+            token: self.base.token,
+            mint: self.base.mint,
+            pool: self.pool(),
+            stake_record: self.stake_record(),
+            delegate: self.delegate(),
+            reward_mint: self.reward_mint,
+            reward_vault: self.reward_vault,
+            owner_reward: self.owner_reward,
+            token_program: spl_token::id(),
+            reward_token_program: spl_token::id(),
+            perm_program: self.perm_program,
+        }
+        .to_account_metas(None);
+        accounts.extend(self.unlock_accounts().await);
+        let ix = Instruction {
+            program_id: staking::id(),
+            accounts,
+            data: staking::instruction::Unstake {}.data(),
+        };
+        let owner = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&owner]).await
+    }
+
+    async fn lock_accounts(&mut self) -> Vec<AccountMeta> {
+        let (token, mint, owner) = (self.base.token, self.base.mint, self.base.owner.pubkey());
+        let base = lock_metas(&token, &mint, &self.delegate(), &owner);
+        let perm_program = self.perm_program;
+        preflight(&mut self.base.context, &perm_program, "lock", base).await
+    }
+
+    async fn unlock_accounts(&mut self) -> Vec<AccountMeta> {
+        let base = unlock_metas(&self.base.token, &self.base.mint, &self.delegate());
+        let perm_program = self.perm_program;
+        preflight(&mut self.base.context, &perm_program, "unlock", base).await
+    }
+
+    /// Direct transfer of the staked tokens by their owner, bypassing the pool
+    async fn owner_transfer(&mut self) -> Result<(), BanksClientError> {
+        let (mint, owner) = (self.base.mint, self.base.owner.pubkey());
+        let dest = create_token_account(&mut self.base.context, &mint, &owner, 0).await;
+        let ix = spl_token::instruction::transfer(
+            &spl_token::id(),
+            &self.base.token,
+            &dest,
+            &owner,
+            &[],
+            STAKE_AMOUNT,
+        )
+        .unwrap();
+        let owner = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&owner]).await
+    }
+
+    async fn read_pool(&mut self) -> StakePool {
+        let key = self.pool();
+        let account = self.base.context.banks_client.get_account(key).await.unwrap().unwrap();
+        StakePool::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    async fn read_stake_record(&mut self) -> Option<StakeRecord> {
+        let key = self.stake_record();
+        let account = self.base.context.banks_client.get_account(key).await.unwrap()?;
+        Some(StakeRecord::try_deserialize(&mut account.data.as_slice()).unwrap())
+    }
+
+    async fn warp_slots(&mut self, slots: u64) {
+        let slot = self.base.context.banks_client.get_root_slot().await.unwrap();
+        self.base.context.warp_to_slot(slot + slots).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn stake_locks_token_in_owner_wallet() {
+    let mut fixture = Fixture::new(VAULT_FUNDING).await;
+    fixture.stake().await.unwrap();
+
+    let token = token_account(&mut fixture.base.context, fixture.base.token).await;
+    assert!(token.is_frozen());
+    assert_eq!(token.owner, fixture.base.owner.pubkey());
+    assert_eq!(token.amount, STAKE_AMOUNT);
+    // the pool, which owns the reward vault, never becomes the token's delegate
+    assert_eq!(token.delegate, COption::Some(fixture.delegate()));
+
+    let stake_record = fixture.read_stake_record().await.unwrap();
+    assert_eq!(stake_record.owner, fixture.base.owner.pubkey());
+    assert_eq!(stake_record.amount, STAKE_AMOUNT);
+    assert_eq!(stake_record.accrued, 0);
+
+    assert!(fixture.owner_transfer().await.is_err());
+}
+
+#[tokio::test]
+async fn claim_pays_rewards_per_slot() {
+    let mut fixture = Fixture::new(VAULT_FUNDING).await;
+    fixture.stake().await.unwrap();
+    let staked_slot = fixture.read_stake_record().await.unwrap().last_update_slot;
+
+    fixture.warp_slots(20).await;
+    fixture.claim().await.unwrap();
+
+    let stake_record = fixture.read_stake_record().await.unwrap();
+    let slots = stake_record.last_update_slot - staked_slot;
+    assert!(slots >= 20);
+    assert_eq!(stake_record.accrued, 0);
+    let owner_reward = fixture.owner_reward;
+    assert_eq!(
+        token_account(&mut fixture.base.context, owner_reward).await.amount,
+        STAKE_AMOUNT * REWARD_RATE * slots
+    );
+    // the token stays staked
+    assert!(token_account(&mut fixture.base.context, fixture.base.token).await.is_frozen());
+}
+
+#[tokio::test]
+async fn update_pool_keeps_rewards_earned_at_the_previous_rate() {
+    let mut fixture = Fixture::new(VAULT_FUNDING).await;
+    fixture.stake().await.unwrap();
+    let staked_slot = fixture.read_stake_record().await.unwrap().last_update_slot;
+
+    fixture.warp_slots(20).await;
+    fixture.update_pool(0).await.unwrap();
+    let updated_slot = fixture.read_pool().await.last_update_slot;
+    assert!(updated_slot >= staked_slot + 20);
+
+    fixture.warp_slots(20).await;
+    fixture.claim().await.unwrap();
+
+    // nothing accrues at the new rate, nothing earned before the update is lost
+    let owner_reward = fixture.owner_reward;
+    assert_eq!(
+        token_account(&mut fixture.base.context, owner_reward).await.amount,
+        STAKE_AMOUNT * REWARD_RATE * (updated_slot - staked_slot)
+    );
+}
+
+#[tokio::test]
+async fn unstake_unlocks_and_closes_record() {
+    let mut fixture = Fixture::new(VAULT_FUNDING).await;
+    fixture.stake().await.unwrap();
+    fixture.warp_slots(10).await;
+    fixture.unstake().await.unwrap();
+
+    let token = token_account(&mut fixture.base.context, fixture.base.token).await;
+    assert!(!token.is_frozen());
+    assert!(token.delegate.is_none());
+    assert!(fixture.read_stake_record().await.is_none());
+    let owner_reward = fixture.owner_reward;
+    assert!(token_account(&mut fixture.base.context, owner_reward).await.amount >= STAKE_AMOUNT * REWARD_RATE * 10);
+
+    fixture.owner_transfer().await.unwrap();
+}
+
+#[tokio::test]
+async fn unstake_from_underfunded_pool_still_unlocks() {
+    let mut fixture = Fixture::new(5).await;
+    fixture.stake().await.unwrap();
+    fixture.warp_slots(10).await;
+    fixture.unstake().await.unwrap();
+
+    assert!(!token_account(&mut fixture.base.context, fixture.base.token).await.is_frozen());
+    let (owner_reward, reward_vault) = (fixture.owner_reward, fixture.reward_vault);
+    assert_eq!(token_account(&mut fixture.base.context, owner_reward).await.amount, 5);
+    assert_eq!(token_account(&mut fixture.base.context, reward_vault).await.amount, 0);
+}
+
+#[tokio::test]
+async fn init_pool_rejects_program_that_doesnt_freeze_the_mint() {
+    let mut fixture = Fixture::without_pool(VAULT_FUNDING, permissioned_token_wrapper::id()).await;
+    let malicious_program = Pubkey::new_unique();
+
+    let err = fixture.init_pool(malicious_program).await.unwrap_err();
+    assert_eq!(custom_error(&err), Some(StakingError::InvalidPermProgram.into()));
+}
+
+#[tokio::test]
+async fn stake_rejects_a_different_permission_program() {
+    let mut fixture = Fixture::new(VAULT_FUNDING).await;
+    let pool = fixture.read_pool().await;
+    assert_eq!(pool.perm_program, permissioned_token_wrapper::id());
+
+    // would be handed the delegate's signature over the owner's tokens
+    let malicious_program = Pubkey::new_unique();
+    let err = fixture.stake_through(malicious_program).await.unwrap_err();
+    assert_eq!(
+        custom_error(&err),
+        Some(anchor_lang::error::ErrorCode::ConstraintHasOne.into())
+    );
+    let token = token_account(&mut fixture.base.context, fixture.base.token).await;
+    assert!(!token.is_frozen());
+    assert!(token.delegate.is_none());
+    assert!(fixture.read_stake_record().await.is_none());
+}
+
+#[tokio::test]
+async fn stake_rejects_a_lock_that_leaves_the_token_unfrozen() {
+    // twice_permissioned only freezes the token once a second delegate locks it
+    let mut fixture = Fixture::with_perm_program(VAULT_FUNDING, twice_permissioned::id()).await;
+
+    let err = fixture.stake().await.unwrap_err();
+    assert_eq!(custom_error(&err), Some(InterfaceError::TokenNotFrozen.into()));
+    let token = token_account(&mut fixture.base.context, fixture.base.token).await;
+    assert!(!token.is_frozen());
+    assert!(token.delegate.is_none());
+    assert!(fixture.read_stake_record().await.is_none());
+}
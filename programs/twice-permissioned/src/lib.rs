@@ -13,11 +13,13 @@ pub mod twice_permissioned {
         let token = ctx.accounts.token.key();
         let (program_control, _) = Pubkey::find_program_address(&[STATIC_PREFIX.as_bytes()], &crate::id());
         let (token_record, _) = Pubkey::find_program_address(&[token.key().as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], &crate::id());
+        let (lock_config, _) = Pubkey::find_program_address(&[ctx.accounts.mint.key().as_ref(), LOCK_CONFIG_PREFIX.as_bytes()], &crate::id());
         let system_program = System::id();
         Ok(PreflightAccounts { accounts: vec![
             IAccountMeta { pubkey: program_control, signer: false, writable: false }, 
             IAccountMeta { pubkey: token_record, signer: false, writable: true }, 
-            IAccountMeta { pubkey: system_program, signer: false, writable: false }
+            IAccountMeta { pubkey: system_program, signer: false, writable: false },
+            IAccountMeta { pubkey: lock_config, signer: false, writable: false }
         ] }.try_to_vec()?)
     }
 
@@ -25,69 +27,360 @@ pub mod twice_permissioned {
         let token = ctx.accounts.token.key();
         let (program_control, _) = Pubkey::find_program_address(&[STATIC_PREFIX.as_bytes()], &crate::id());
         let (token_record, _) = Pubkey::find_program_address(&[token.key().as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], &crate::id());
+        let (lock_config, _) = Pubkey::find_program_address(&[ctx.accounts.mint.key().as_ref(), LOCK_CONFIG_PREFIX.as_bytes()], &crate::id());
         let system_program = System::id();
         Ok(PreflightAccounts { accounts: vec![
             IAccountMeta { pubkey: program_control, signer: false, writable: false }, 
             IAccountMeta { pubkey: token_record, signer: false, writable: true }, 
-            IAccountMeta { pubkey: system_program, signer: false, writable: false }
+            IAccountMeta { pubkey: system_program, signer: false, writable: false },
+            IAccountMeta { pubkey: lock_config, signer: false, writable: false }
         ] }.try_to_vec()?)
     }
 
-    pub fn lock(ctx: Context<Lock>) -> Result<()> {
-        ctx.accounts.token_record.count += 1;
-        if ctx.accounts.token_record.count > 1 {
-            ctx.accounts.token_record.locked = 1;
-
-            let static_seeds: &[u8] = &STATIC_PREFIX.as_bytes();
-            let bump_seed = ctx.bumps.get("program_control").unwrap();
-            let seeds = &[&static_seeds[..], &[*bump_seed]];
-            let binding = [&seeds[..]];
-            let ctx = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                token_interface::FreezeAccount {
-                    account: ctx.accounts.token.to_account_info(),
-                    mint: ctx.accounts.mint.to_account_info(),
-                    authority: ctx.accounts.program_control.to_account_info(),
-                },
-                &binding,
-            );
-            token_interface::freeze_account(ctx)?;
+    pub fn init_lock_config(ctx: Context<InitLockConfig>, admin: Pubkey, threshold: u64) -> Result<()> {
+        require!(
+            threshold > 0 && threshold <= MAX_LOCKERS as u64,
+            TwicePermissionedError::InvalidThreshold
+        );
+        let lock_config = &mut ctx.accounts.lock_config;
+        lock_config.admin = admin;
+        lock_config.threshold = threshold;
+        Ok(())
+    }
+
+    pub fn update_lock_config(ctx: Context<UpdateLockConfig>, threshold: u64) -> Result<()> {
+        require!(
+            threshold > 0 && threshold <= MAX_LOCKERS as u64,
+            TwicePermissionedError::InvalidThreshold
+        );
+        ctx.accounts.lock_config.threshold = threshold;
+        Ok(())
+    }
+
+    pub fn lock(ctx: Context<Lock>, nonce: Option<u64>) -> Result<()> {
+        let threshold = lock_threshold(&ctx.accounts.lock_config)?;
+        let delegate = ctx.accounts.delegate.key();
+        let token_record = &mut ctx.accounts.token_record;
+        token_record.version = TOKEN_RECORD_VERSION;
+        if token_record.has_nonce(delegate, NonceOperation::Lock, nonce) {
+            msg!("Nonce already used, skipping lock");
+            return Ok(());
+        }
+        token_record.record_nonce(delegate, NonceOperation::Lock, nonce);
+        require!(
+            !token_record.lockers.contains(&delegate),
+            TwicePermissionedError::AlreadyLocked
+        );
+        require!(
+            token_record.locked & LOCKED_EXCLUSIVE == 0,
+            TwicePermissionedError::ExclusivelyLocked
+        );
+        require!(
+            token_record.lockers.len() < MAX_LOCKERS,
+            TwicePermissionedError::TooManyLockers
+        );
+        token_record.lockers.push(delegate);
+        if token_record.lockers.len() as u64 >= threshold && token_record.locked & LOCKED_FROZEN == 0 {
+            token_record.locked |= LOCKED_FROZEN;
+            freeze(
+                &ctx.accounts.token_program,
+                &ctx.accounts.token,
+                &ctx.accounts.mint,
+                &ctx.accounts.program_control,
+                *ctx.bumps.get("program_control").unwrap(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Same accounts as `lock`
+    pub fn preflight_lock_exclusive(ctx: Context<ILock>) -> Result<Vec<u8>> {
+        preflight_lock(ctx)
+    }
+
+    /// Locks the token for `delegate` alone: fails if anyone else holds a lock, and no other
+    /// delegate can lock until `delegate` unlocks. The lock still counts once towards the
+    /// threshold, so it only freezes the token when the threshold is 1.
+    pub fn lock_exclusive(ctx: Context<Lock>, nonce: Option<u64>) -> Result<()> {
+        let threshold = lock_threshold(&ctx.accounts.lock_config)?;
+        let delegate = ctx.accounts.delegate.key();
+        let token_record = &mut ctx.accounts.token_record;
+        token_record.version = TOKEN_RECORD_VERSION;
+        if token_record.has_nonce(delegate, NonceOperation::Lock, nonce) {
+            msg!("Nonce already used, skipping lock");
+            return Ok(());
+        }
+        token_record.record_nonce(delegate, NonceOperation::Lock, nonce);
+        require!(
+            !token_record.lockers.contains(&delegate),
+            TwicePermissionedError::AlreadyLocked
+        );
+        require!(
+            token_record.locked & LOCKED_EXCLUSIVE == 0,
+            TwicePermissionedError::ExclusivelyLocked
+        );
+        require!(
+            token_record.lockers.is_empty(),
+            TwicePermissionedError::LockedByOthers
+        );
+        token_record.lockers.push(delegate);
+        token_record.locked |= LOCKED_EXCLUSIVE;
+        if threshold <= 1 && token_record.locked & LOCKED_FROZEN == 0 {
+            token_record.locked |= LOCKED_FROZEN;
+            freeze(
+                &ctx.accounts.token_program,
+                &ctx.accounts.token,
+                &ctx.accounts.mint,
+                &ctx.accounts.program_control,
+                *ctx.bumps.get("program_control").unwrap(),
+            )?;
         }
         Ok(())
     }
 
-    pub fn unlock(ctx: Context<Unlock>) -> Result<()> {
-        if ctx.accounts.token_record.count > 0 {
-            ctx.accounts.token_record.count -= 1;
+    pub fn unlock(ctx: Context<Unlock>, nonce: Option<u64>) -> Result<()> {
+        let threshold = lock_threshold(&ctx.accounts.lock_config)?;
+        let delegate = ctx.accounts.delegate.key();
+        let token_record = &mut ctx.accounts.token_record;
+        if token_record.has_nonce(delegate, NonceOperation::Unlock, nonce) {
+            msg!("Nonce already used, skipping unlock");
+            return Ok(());
+        }
+        token_record.record_nonce(delegate, NonceOperation::Unlock, nonce);
+        require!(!token_record.lockers.is_empty(), TwicePermissionedError::NotLocked);
+        let position = token_record
+            .lockers
+            .iter()
+            .position(|locker| *locker == delegate)
+            .ok_or(TwicePermissionedError::LockNotHeld)?;
+        token_record.lockers.remove(position);
+        if token_record.lockers.is_empty() {
+            token_record.locked &= !LOCKED_EXCLUSIVE;
         }
-        if ctx.accounts.token_record.count < 1 {
-            ctx.accounts.token_record.locked = 0;
-
-            let static_seeds: &[u8] = &STATIC_PREFIX.as_bytes();
-            let bump_seed = ctx.bumps.get("program_control").unwrap();
-            let seeds = &[&static_seeds[..], &[*bump_seed]];
-            let binding = [&seeds[..]];
-            let ctx = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                token_interface::ThawAccount {
-                    account: ctx.accounts.token.to_account_info(),
-                    mint: ctx.accounts.mint.to_account_info(),
-                    authority: ctx.accounts.program_control.to_account_info(),
-                },
-                &binding,
+        if (token_record.lockers.len() as u64) < threshold && token_record.locked & LOCKED_FROZEN != 0 {
+            token_record.locked &= !LOCKED_FROZEN;
+            thaw(
+                &ctx.accounts.token_program,
+                &ctx.accounts.token,
+                &ctx.accounts.mint,
+                &ctx.accounts.program_control,
+                *ctx.bumps.get("program_control").unwrap(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reallocs a token record written by an older program version into the current layout.
+    ///
+    /// v0 records are detected by their length alone: their first field, `locked`, sits where
+    /// `version` is now and a locked v0 record stores 1 there, the same byte as version 1.
+    pub fn migrate_record(ctx: Context<MigrateRecord>) -> Result<()> {
+        let token_record = &ctx.accounts.token_record;
+        let record = {
+            let data = token_record.try_borrow_data()?;
+            require!(
+                data.len() > 8 && data[..8] == TokenRecord::discriminator(),
+                TwicePermissionedError::UnknownRecordVersion
             );
-            token_interface::thaw_account(ctx)?;
+            if data.len() == LEGACY_TOKEN_RECORD_SPACE {
+                // v0 records only counted locks, not who took them, so credit them to the
+                // token owner, who was always allowed to unlock. `locked` is kept as is and
+                // the owner's unlock thaws the token.
+                let (locked, count) = (data[8], data[9]);
+                TokenRecord {
+                    version: TOKEN_RECORD_VERSION,
+                    locked,
+                    lockers: if count > 0 { vec![ctx.accounts.token.owner] } else { vec![] },
+                    recent_nonces: vec![],
+                }
+            } else if data[8] == TOKEN_RECORD_VERSION {
+                return Ok(());
+            } else {
+                return err!(TwicePermissionedError::UnknownRecordVersion);
+            }
+        };
+
+        let rent = Rent::get()?.minimum_balance(TokenRecord::SPACE);
+        let lamports = token_record.lamports();
+        if lamports < rent {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: token_record.to_account_info(),
+                    },
+                ),
+                rent - lamports,
+            )?;
         }
+        token_record.realloc(TokenRecord::SPACE, true)?;
+        record.try_serialize(&mut &mut token_record.try_borrow_mut_data()?[..])?;
         Ok(())
     }
 }
 
 pub const STATIC_PREFIX: &'static str = "static";
 pub const TOKEN_RECORD_PREFIX: &'static str = "token_record";
+pub const LOCK_CONFIG_PREFIX: &'static str = "lock_config";
+/// Threshold used by mints that never created a `LockConfig`
+pub const DEFAULT_LOCK_THRESHOLD: u64 = 2;
+
+/// Maximum number of distinct delegates that can hold a lock on one token account
+pub const MAX_LOCKERS: usize = 16;
+
+/// Number of idempotency nonces remembered per token account
+pub const MAX_RECENT_NONCES: usize = 8;
+
+/// Layout version written to every `TokenRecord`, bump it whenever the layout changes
+/// and teach `migrate_record` to upgrade from the previous one
+pub const TOKEN_RECORD_VERSION: u8 = 1;
+/// Size of the unversioned v0 record, which stored `locked` and a `u8` lock count.
+/// This size is the only way to tell v0 apart, its `locked` byte is indistinguishable
+/// from `version`
+pub const LEGACY_TOKEN_RECORD_SPACE: usize = 8 + 1 + 1;
+/// Flag of `TokenRecord::locked`, set while the program keeps the token account frozen
+pub const LOCKED_FROZEN: u8 = 1;
+/// Flag of `TokenRecord::locked`, set while the single locker holds it through `lock_exclusive`
+pub const LOCKED_EXCLUSIVE: u8 = 2;
+
+/// The token account is frozen while it has at least the mint's lock threshold of lockers,
+/// each delegate contributes at most one lock.
+///
+/// Lock and unlock calls carrying a nonce that the same delegate already used for the
+/// same operation within the last `MAX_RECENT_NONCES` calls are no-ops.
+///
+/// `locked` holds the `LOCKED_FROZEN` and `LOCKED_EXCLUSIVE` flags.
 #[account]
 pub struct TokenRecord {
+    pub version: u8,
     pub locked: u8,
-    pub count: u8
+    pub lockers: Vec<Pubkey>,
+    pub recent_nonces: Vec<RecentNonce>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct RecentNonce {
+    pub delegate: Pubkey,
+    pub operation: NonceOperation,
+    pub nonce: u64,
+}
+
+/// Nonces are scoped to the operation, so a lock and the unlock releasing it may share one
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NonceOperation {
+    Lock,
+    Unlock,
+}
+
+impl TokenRecord {
+    pub const SPACE: usize =
+        8 + 1 + 1 + 4 + 32 * MAX_LOCKERS + 4 + (32 + 1 + 8) * MAX_RECENT_NONCES;
+
+    pub fn has_nonce(&self, delegate: Pubkey, operation: NonceOperation, nonce: Option<u64>) -> bool {
+        match nonce {
+            Some(nonce) => self.recent_nonces.iter().any(|recent| {
+                recent.delegate == delegate && recent.operation == operation && recent.nonce == nonce
+            }),
+            None => false,
+        }
+    }
+
+    /// Remembers `nonce`, evicting the oldest one once the set is full
+    pub fn record_nonce(&mut self, delegate: Pubkey, operation: NonceOperation, nonce: Option<u64>) {
+        if let Some(nonce) = nonce {
+            if self.recent_nonces.len() >= MAX_RECENT_NONCES {
+                self.recent_nonces.remove(0);
+            }
+            self.recent_nonces.push(RecentNonce { delegate, operation, nonce });
+        }
+    }
+}
+
+/// Per-mint number of locks required before a token account is frozen
+#[account]
+pub struct LockConfig {
+    pub admin: Pubkey,
+    pub threshold: u64,
+}
+
+impl LockConfig {
+    pub const SPACE: usize = 8 + 32 + 8;
+}
+
+fn freeze<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    token: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    program_control: &AccountInfo<'info>,
+    bump: u8,
+) -> Result<()> {
+    let static_seeds: &[u8] = &STATIC_PREFIX.as_bytes();
+    let seeds = &[&static_seeds[..], &[bump]];
+    let binding = [&seeds[..]];
+    let ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        token_interface::FreezeAccount {
+            account: token.to_account_info(),
+            mint: mint.to_account_info(),
+            authority: program_control.clone(),
+        },
+        &binding,
+    );
+    token_interface::freeze_account(ctx)
+}
+
+fn thaw<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    token: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    program_control: &AccountInfo<'info>,
+    bump: u8,
+) -> Result<()> {
+    let static_seeds: &[u8] = &STATIC_PREFIX.as_bytes();
+    let seeds = &[&static_seeds[..], &[bump]];
+    let binding = [&seeds[..]];
+    let ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        token_interface::ThawAccount {
+            account: token.to_account_info(),
+            mint: mint.to_account_info(),
+            authority: program_control.clone(),
+        },
+        &binding,
+    );
+    token_interface::thaw_account(ctx)
+}
+
+fn lock_threshold(lock_config: &AccountInfo) -> Result<u64> {
+    if lock_config.data_is_empty() {
+        return Ok(DEFAULT_LOCK_THRESHOLD);
+    }
+    require_keys_eq!(*lock_config.owner, crate::id(), TwicePermissionedError::InvalidLockConfig);
+    let config = LockConfig::try_deserialize(&mut &lock_config.data.borrow()[..])?;
+    Ok(config.threshold)
+}
+
+#[error_code]
+pub enum TwicePermissionedError {
+    #[msg("Lock threshold must be between 1 and the maximum number of lockers")]
+    InvalidThreshold,
+    #[msg("Token account is not locked")]
+    NotLocked,
+    #[msg("Lock config is not owned by this program")]
+    InvalidLockConfig,
+    #[msg("Delegate already holds a lock on this token account")]
+    AlreadyLocked,
+    #[msg("Delegate does not hold a lock on this token account")]
+    LockNotHeld,
+    #[msg("Token account already has the maximum number of lockers")]
+    TooManyLockers,
+    #[msg("Token record has an unknown layout version")]
+    UnknownRecordVersion,
+    #[msg("Token account is locked exclusively by another delegate")]
+    ExclusivelyLocked,
+    #[msg("Token account is already locked by other delegates")]
+    LockedByOthers,
 }
 
 #[account]
@@ -99,6 +392,37 @@ pub struct IxAccounts {
 pub struct SetIxAccounts {
 }
 
+#[derive(Accounts)]
+pub struct InitLockConfig<'info> {
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = mint.mint_authority.is_some() && mint.mint_authority.unwrap() == mint_authority.key())]
+    mint_authority: Signer<'info>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    #[account(init, payer=payer, space=LockConfig::SPACE, seeds=[mint.key().as_ref(), LOCK_CONFIG_PREFIX.as_bytes()], bump)]
+    lock_config: Account<'info, LockConfig>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateLockConfig<'info> {
+    mint: InterfaceAccount<'info, Mint>,
+    admin: Signer<'info>,
+    #[account(mut, has_one=admin, seeds=[mint.key().as_ref(), LOCK_CONFIG_PREFIX.as_bytes()], bump)]
+    lock_config: Account<'info, LockConfig>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateRecord<'info> {
+    token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    /// CHECK: may hold any past layout, checked in `migrate_record`
+    #[account(mut, owner = crate::id(), seeds=[token.key().as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], bump)]
+    token_record: AccountInfo<'info>,
+    system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct ILock<'info> {
     token: InterfaceAccount<'info, TokenAccount>,
@@ -129,9 +453,12 @@ pub struct Lock<'info> {
         constraint = mint.freeze_authority.is_some() && mint.freeze_authority.unwrap() == program_control.key()
     )]
     program_control: AccountInfo<'info>,
-    #[account(init_if_needed, payer=payer, space=8 + std::mem::size_of::<TokenRecord>(), seeds=[token.key().as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], bump)]
+    #[account(init_if_needed, payer=payer, space=TokenRecord::SPACE, seeds=[token.key().as_ref(), TOKEN_RECORD_PREFIX.as_bytes()], bump)]
     token_record: Account<'info, TokenRecord>,
     system_program: Program<'info, System>,
+    /// CHECK: deserialized in `lock_threshold`, may not be initialized
+    #[account(seeds=[mint.key().as_ref(), LOCK_CONFIG_PREFIX.as_bytes()], bump)]
+    lock_config: AccountInfo<'info>,
 }
 
 #[derive(Accounts)]
@@ -139,7 +466,7 @@ pub struct IUnlock<'info> {
     token: InterfaceAccount<'info, TokenAccount>,
     mint: InterfaceAccount<'info, Mint>,
     /// CHECK: nil
-    owner: AccountInfo<'info>,
+    delegate: AccountInfo<'info>,
     token_program: Interface<'info, TokenInterface>,
     // #[account(seeds=["unlock".as_bytes()], bump)]
     // ix_accounts: Option<Account<'info, IxAccounts>>
@@ -150,7 +477,6 @@ pub struct Unlock<'info> {
     #[account(mut)]
     token: InterfaceAccount<'info, TokenAccount>,
     mint: InterfaceAccount<'info, Mint>,
-    #[account(constraint = token.owner == delegate.key() || token.delegate.is_some() && token.delegate.unwrap() == delegate.key())]
     delegate: Signer<'info>,
     token_program: Interface<'info, TokenInterface>,
     /// CHECK: nothing
@@ -163,4 +489,7 @@ pub struct Unlock<'info> {
     #[account(mut, seeds=[token.key().as_ref(), b"token_record"], bump)]
     token_record: Account<'info, TokenRecord>,
     system_program: Program<'info, System>,
+    /// CHECK: deserialized in `lock_threshold`, may not be initialized
+    #[account(seeds=[mint.key().as_ref(), LOCK_CONFIG_PREFIX.as_bytes()], bump)]
+    lock_config: AccountInfo<'info>,
 }
\ No newline at end of file
@@ -0,0 +1,161 @@
+use anchor_lang::{AccountDeserialize, Discriminator, InstructionData, ToAccountMetas};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    instruction::Instruction,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::Signer,
+    system_program,
+};
+use spl_token::state::AccountState;
+use test_fixtures::{clone_keypair, process, program_control, token_account, TokenFixture};
+use twice_permissioned::{
+    TokenRecord, LEGACY_TOKEN_RECORD_SPACE, LOCK_CONFIG_PREFIX, TOKEN_RECORD_PREFIX,
+    TOKEN_RECORD_VERSION,
+};
+
+/// A token of a twice mint whose record was written by the unversioned v0 program:
+/// the discriminator followed by `locked` and a `u8` lock count
+struct Fixture {
+    base: TokenFixture,
+}
+
+impl Fixture {
+    async fn new(locked: bool, count: u8) -> Self {
+        let programs = [(twice_permissioned::id(), "twice_permissioned")];
+        let mut fixture = Self {
+            base: TokenFixture::new(&programs, &twice_permissioned::id(), 1).await,
+        };
+        let token = fixture.base.token;
+
+        let mut data = TokenRecord::discriminator().to_vec();
+        data.extend([locked as u8, count]);
+        assert_eq!(data.len(), LEGACY_TOKEN_RECORD_SPACE);
+        fixture.set_account(fixture.token_record(), data, twice_permissioned::id());
+
+        if locked {
+            // v0 froze the token once it had two locks
+            let mut state = token_account(&mut fixture.base.context, token).await;
+            state.state = AccountState::Frozen;
+            let mut data = vec![0; spl_token::state::Account::LEN];
+            state.pack_into_slice(&mut data);
+            fixture.set_account(token, data, spl_token::id());
+        }
+        fixture
+    }
+
+    fn set_account(&mut self, key: Pubkey, data: Vec<u8>, owner: Pubkey) {
+        let account = Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner,
+            executable: false,
+            rent_epoch: 0,
+        };
+        self.base.context.set_account(&key, &AccountSharedData::from(account));
+    }
+
+    fn token_record(&self) -> Pubkey {
+        Pubkey::find_program_address(
+            &[self.base.token.as_ref(), TOKEN_RECORD_PREFIX.as_bytes()],
+            &twice_permissioned::id(),
+        )
+        .0
+    }
+
+    async fn migrate(&mut self) {
+        let ix = Instruction {
+            program_id: twice_permissioned::id(),
+            accounts: twice_permissioned::accounts::MigrateRecord {
+                token: self.base.token,
+                payer: self.base.context.payer.pubkey(),
+                token_record: self.token_record(),
+                system_program: system_program::id(),
+            }
+            .to_account_metas(None),
+            data: twice_permissioned::instruction::MigrateRecord {}.data(),
+        };
+        process(&mut self.base.context, &[ix], &[]).await.unwrap();
+    }
+
+    /// Size and contents of the token record
+    async fn record(&mut self) -> (usize, TokenRecord) {
+        let account = self
+            .base
+            .context
+            .banks_client
+            .get_account(self.token_record())
+            .await
+            .unwrap()
+            .unwrap();
+        let record = TokenRecord::try_deserialize(&mut &account.data[..]).unwrap();
+        (account.data.len(), record)
+    }
+}
+
+#[tokio::test]
+async fn migrates_a_locked_v0_record_to_the_token_owner() {
+    let mut fixture = Fixture::new(true, 2).await;
+    fixture.migrate().await;
+
+    let (len, record) = fixture.record().await;
+    assert_eq!(len, TokenRecord::SPACE);
+    assert_eq!(record.version, TOKEN_RECORD_VERSION);
+    assert_eq!(record.locked, 1);
+    assert_eq!(record.lockers, vec![fixture.base.owner.pubkey()]);
+    assert!(record.recent_nonces.is_empty());
+
+    // the owner all locks were credited to can release them
+    let lock_config = Pubkey::find_program_address(
+        &[fixture.base.mint.as_ref(), LOCK_CONFIG_PREFIX.as_bytes()],
+        &twice_permissioned::id(),
+    )
+    .0;
+    let ix = Instruction {
+        program_id: twice_permissioned::id(),
+        accounts: twice_permissioned::accounts::Unlock {
+            token: fixture.base.token,
+            mint: fixture.base.mint,
+            delegate: fixture.base.owner.pubkey(),
+            token_program: spl_token::id(),
+            program_control: program_control(&twice_permissioned::id()),
+            token_record: fixture.token_record(),
+            system_program: system_program::id(),
+            lock_config,
+        }
+        .to_account_metas(None),
+        data: twice_permissioned::instruction::Unlock { nonce: None }.data(),
+    };
+    let owner = clone_keypair(&fixture.base.owner);
+    process(&mut fixture.base.context, &[ix], &[&owner]).await.unwrap();
+    assert!(!token_account(&mut fixture.base.context, fixture.base.token).await.is_frozen());
+    let (_, record) = fixture.record().await;
+    assert_eq!(record.locked, 0);
+    assert!(record.lockers.is_empty());
+}
+
+#[tokio::test]
+async fn migrates_an_unlocked_v0_record() {
+    let mut fixture = Fixture::new(false, 0).await;
+    fixture.migrate().await;
+
+    let (len, record) = fixture.record().await;
+    assert_eq!(len, TokenRecord::SPACE);
+    assert_eq!(record.version, TOKEN_RECORD_VERSION);
+    assert_eq!(record.locked, 0);
+    assert!(record.lockers.is_empty());
+}
+
+#[tokio::test]
+async fn migrating_a_current_record_is_a_no_op() {
+    let mut fixture = Fixture::new(true, 2).await;
+    fixture.migrate().await;
+    let (_, migrated) = fixture.record().await;
+
+    fixture.migrate().await;
+    let (len, record) = fixture.record().await;
+    assert_eq!(len, TokenRecord::SPACE);
+    assert_eq!(record.locked, migrated.locked);
+    assert_eq!(record.lockers, migrated.lockers);
+}
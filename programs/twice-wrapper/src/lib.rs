@@ -1,269 +1,155 @@
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 
-use interface::PreflightAccounts;
+use interface::{IAccountMeta, PreflightAccounts};
 
 declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
 
+/// Middleware permission program: implements the lock interface itself and forwards
+/// every call to the permission program it wraps, which is passed as the first
+/// remaining account. Since the inner program may itself be a wrapper, wrappers stack.
 #[program]
 pub mod twice_wrapper {
     use super::*;
-    use anchor_lang::solana_program::{
-        hash,
-        program::{get_return_data, invoke},
-    };
 
-    // pub fn preflight_lock(ctx: Context<ILock>) -> Result<PreflightAccounts> {
-    //     cpi::
-    //     Ok(PreflightAccounts {
-    //         pubkeys: vec![
-    //             IAccountMeta {
-    //                 pubkey: program_control,
-    //                 signer: false,
-    //                 writable: false,
-    //             },
-    //             IAccountMeta {
-    //                 pubkey: token_record,
-    //                 signer: false,
-    //                 writable: true,
-    //             },
-    //             IAccountMeta {
-    //                 pubkey: system_program,
-    //                 signer: false,
-    //                 writable: false,
-    //             },
-    //         ],
-    //     })
-    // }
-
-    // pub fn preflight_unlock(ctx: Context<IUnlock>) -> Result<PreflightAccounts> {
-    //     let token = ctx.accounts.token.key();
-    //     let (program_control, _) =
-    //         Pubkey::find_program_address(&[STATIC_PREFIX.as_bytes()], &crate::id());
-    //     let (token_record, _) = Pubkey::find_program_address(
-    //         &[token.key().as_ref(), TOKEN_RECORD_PREFIX.as_bytes()],
-    //         &crate::id(),
-    //     );
-    //     let system_program = System::id();
-    //     Ok(PreflightAccounts {
-    //         pubkeys: vec![
-    //             IAccountMeta {
-    //                 pubkey: program_control,
-    //                 signer: false,
-    //                 writable: false,
-    //             },
-    //             IAccountMeta {
-    //                 pubkey: token_record,
-    //                 signer: false,
-    //                 writable: true,
-    //             },
-    //             IAccountMeta {
-    //                 pubkey: system_program,
-    //                 signer: false,
-    //                 writable: false,
-    //             },
-    //         ],
-    //     })
-    // }
-
-    pub fn lock<'info>(ctx: Context<'_, '_, '_, 'info, TILock<'info>>) -> Result<()> {
-        let token = &ctx.accounts.token;
-        let mint = &ctx.accounts.mint;
-        let delegate = &ctx.accounts.delegate;
-        let payer = &ctx.accounts.payer;
-        let token_program = &ctx.accounts.token_program;
-
-        // setup preflight
-        {
-            let ix_data: Vec<u8> = hash::hash("global:preflight_lock".as_bytes())
-                .to_bytes()
-                .to_vec();
-            let ix_account_metas = vec![
-                AccountMeta::new_readonly(ctx.accounts.token.key(), false),
-                AccountMeta::new_readonly(ctx.accounts.mint.key(), false),
-                AccountMeta::new_readonly(ctx.accounts.delegate.key(), false),
-                AccountMeta::new_readonly(ctx.accounts.payer.key(), false),
-                AccountMeta::new_readonly(ctx.accounts.token_program.key(), false),
-            ];
-            let ix = anchor_lang::solana_program::instruction::Instruction {
-                program_id: ctx.accounts.perm_program.key(),
-                accounts: ix_account_metas,
-                data: ix_data,
-            };
-
-            // execute preflight
-            invoke(
-                &ix,
-                &[
-                    token.to_account_info(),
-                    mint.to_account_info(),
-                    delegate.to_account_info(),
-                    payer.to_account_info(),
-                    token_program.to_account_info(),
-                ],
-            )?;
-        }
-        {
-            // parse cpi return data
-            let (program_key, program_data) = get_return_data().unwrap();
-            assert_eq!(program_key, ctx.accounts.perm_program.key());
-            let mut program_data = program_data.as_slice();
-            let additional_interface_accounts = PreflightAccounts::deserialize(&mut program_data)?;
-            msg!(
-                "Additional interface accounts: {:?}",
-                &additional_interface_accounts
-            );
-
-            // setup lock
-            let remaining_accounts = ctx.remaining_accounts.to_vec();
-
-            let ix_data: Vec<u8> = hash::hash("global:lock".as_bytes()).to_bytes().to_vec();
-            let mut ix_account_metas = vec![
-                AccountMeta::new(token.key(), false),
-                AccountMeta::new_readonly(mint.key(), false),
-                AccountMeta::new(delegate.key(), true),
-                AccountMeta::new(payer.key(), true),
-                AccountMeta::new_readonly(token_program.key(), false),
-            ];
-            ix_account_metas.append(
-                additional_interface_accounts
-                    .accounts
-                    .iter()
-                    .map(|acc| {
-                        if acc.writable {
-                            AccountMeta::new(acc.pubkey, acc.signer)
-                        } else {
-                            AccountMeta::new_readonly(acc.pubkey, acc.signer)
-                        }
-                    })
-                    .collect::<Vec<AccountMeta>>()
-                    .as_mut(),
-            );
-            let ix = anchor_lang::solana_program::instruction::Instruction {
-                program_id: ctx.accounts.perm_program.key(),
-                accounts: ix_account_metas,
-                data: ix_data,
-            };
-
-            let mut ix_ais: Vec<AccountInfo> = vec![
-                token.to_account_info(),
-                mint.to_account_info(),
-                delegate.to_account_info(),
-                payer.to_account_info(),
-                token_program.to_account_info(),
-            ];
-            ix_ais.append(
-                &mut additional_interface_accounts
-                    .match_accounts(&remaining_accounts)?
-                    .to_vec(),
-            );
-
-            // execute lock
-            invoke(&ix, &ix_ais)?;
-        }
-        Ok(())
+    pub fn preflight_lock<'info>(ctx: Context<'_, '_, '_, 'info, ILock<'info>>) -> Result<Vec<u8>> {
+        let (inner_program, inner_accounts) = split_inner_program(ctx.remaining_accounts)?;
+        let inner_ctx = CpiContext::new(
+            inner_program.clone(),
+            interface::ILock {
+                token: ctx.accounts.token.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                delegate: ctx.accounts.delegate.to_account_info(),
+                payer: ctx.accounts.payer.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+            },
+        )
+        .with_remaining_accounts(inner_accounts.to_vec());
+
+        let inner_preflight = interface::preflight("lock", &inner_ctx)?;
+        Ok(with_inner_program(inner_program.key(), inner_preflight).try_to_vec()?)
     }
 
-    pub fn unlock<'info>(ctx: Context<'_, '_, '_, 'info, TIUnlock<'info>>) -> Result<()> {
-        let token = &ctx.accounts.token;
-        let mint = &ctx.accounts.mint;
-        let delegate = &ctx.accounts.delegate;
-        let token_program = &ctx.accounts.token_program;
-
-        // setup preflight
-        {
-            let ix_data: Vec<u8> = hash::hash("global:preflight_unlock".as_bytes())
-                .to_bytes()
-                .to_vec();
-            let ix_account_metas = vec![
-                AccountMeta::new_readonly(ctx.accounts.token.key(), false),
-                AccountMeta::new_readonly(ctx.accounts.mint.key(), false),
-                AccountMeta::new_readonly(ctx.accounts.delegate.key(), false),
-                AccountMeta::new_readonly(ctx.accounts.token_program.key(), false),
-            ];
-            let ix = anchor_lang::solana_program::instruction::Instruction {
-                program_id: ctx.accounts.perm_program.key(),
-                accounts: ix_account_metas,
-                data: ix_data,
-            };
+    pub fn preflight_unlock<'info>(
+        ctx: Context<'_, '_, '_, 'info, IUnlock<'info>>,
+    ) -> Result<Vec<u8>> {
+        let (inner_program, inner_accounts) = split_inner_program(ctx.remaining_accounts)?;
+        let inner_ctx = CpiContext::new(
+            inner_program.clone(),
+            interface::IUnlock {
+                token: ctx.accounts.token.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                delegate: ctx.accounts.delegate.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+            },
+        )
+        .with_remaining_accounts(inner_accounts.to_vec());
+
+        let inner_preflight = interface::preflight("unlock", &inner_ctx)?;
+        Ok(with_inner_program(inner_program.key(), inner_preflight).try_to_vec()?)
+    }
 
-            // execute preflight
-            invoke(
-                &ix,
-                &[
-                    token.to_account_info(),
-                    mint.to_account_info(),
-                    delegate.to_account_info(),
-                    token_program.to_account_info(),
-                ],
-            )?;
-        }
-        {
-            // parse cpi return data
-            let (program_key, program_data) = get_return_data().unwrap();
-            assert_eq!(program_key, ctx.accounts.perm_program.key());
-            let mut program_data = program_data.as_slice();
-            let additional_interface_accounts = PreflightAccounts::deserialize(&mut program_data)?;
-            msg!(
-                "Additional interface accounts: {:?}",
-                &additional_interface_accounts
-            );
+    pub fn lock<'info>(
+        ctx: Context<'_, '_, '_, 'info, Lock<'info>>,
+        nonce: Option<u64>,
+    ) -> Result<()> {
+        let (inner_program, inner_accounts) = split_inner_program(ctx.remaining_accounts)?;
+        let inner_ctx = CpiContext::new(
+            inner_program.clone(),
+            interface::TILock {
+                token: ctx.accounts.token.clone(),
+                mint: ctx.accounts.mint.clone(),
+                delegate: ctx.accounts.delegate.clone(),
+                payer: ctx.accounts.payer.clone(),
+                token_program: ctx.accounts.token_program.clone(),
+                perm_program: inner_program.clone(),
+            },
+        )
+        .with_remaining_accounts(inner_accounts.to_vec());
+
+        interface::call_with_nonce("lock".to_string(), inner_ctx, nonce, false)
+    }
 
-            // setup lock
-            let remaining_accounts = ctx.remaining_accounts.to_vec();
+    pub fn unlock<'info>(
+        ctx: Context<'_, '_, '_, 'info, Unlock<'info>>,
+        nonce: Option<u64>,
+    ) -> Result<()> {
+        let (inner_program, inner_accounts) = split_inner_program(ctx.remaining_accounts)?;
+        let inner_ctx = CpiContext::new(
+            inner_program.clone(),
+            interface::TIUnlock {
+                token: ctx.accounts.token.clone(),
+                mint: ctx.accounts.mint.clone(),
+                delegate: ctx.accounts.delegate.clone(),
+                token_program: ctx.accounts.token_program.clone(),
+                perm_program: inner_program.clone(),
+            },
+        )
+        .with_remaining_accounts(inner_accounts.to_vec());
+
+        interface::call_with_nonce("unlock".to_string(), inner_ctx, nonce, false)
+    }
+}
 
-            let ix_data: Vec<u8> = hash::hash("global:unlock".as_bytes()).to_bytes().to_vec();
-            let mut ix_account_metas = vec![
-                AccountMeta::new(token.key(), false),
-                AccountMeta::new_readonly(mint.key(), false),
-                AccountMeta::new(delegate.key(), true),
-                AccountMeta::new_readonly(token_program.key(), false),
-            ];
-            ix_account_metas.append(
-                additional_interface_accounts
-                    .accounts
-                    .iter()
-                    .map(|acc| {
-                        if acc.writable {
-                            AccountMeta::new(acc.pubkey, acc.signer)
-                        } else {
-                            AccountMeta::new_readonly(acc.pubkey, acc.signer)
-                        }
-                    })
-                    .collect::<Vec<AccountMeta>>()
-                    .as_mut(),
-            );
-            let ix = anchor_lang::solana_program::instruction::Instruction {
-                program_id: ctx.accounts.perm_program.key(),
-                accounts: ix_account_metas,
-                data: ix_data,
-            };
+/// Splits the remaining accounts into the wrapped permission program and the accounts
+/// that get forwarded to it.
+fn split_inner_program<'a, 'info>(
+    remaining_accounts: &'a [AccountInfo<'info>],
+) -> Result<(&'a AccountInfo<'info>, &'a [AccountInfo<'info>])> {
+    let (inner_program, inner_accounts) = remaining_accounts
+        .split_first()
+        .ok_or(TwiceWrapperError::MissingInnerProgram)?;
+    require!(inner_program.executable, TwiceWrapperError::MissingInnerProgram);
+    require_keys_neq!(inner_program.key(), crate::id(), TwiceWrapperError::MissingInnerProgram);
+    Ok((inner_program, inner_accounts))
+}
 
-            let mut ix_ais: Vec<AccountInfo> = vec![
-                token.to_account_info(),
-                mint.to_account_info(),
-                delegate.to_account_info(),
-                token_program.to_account_info(),
-            ];
-            ix_ais.append(
-                &mut additional_interface_accounts
-                    .match_accounts(&remaining_accounts)?
-                    .to_vec(),
-            );
+/// Puts the inner program in front of the accounts its preflight returned, so that
+/// `lock`/`unlock` receive it as their first remaining account.
+fn with_inner_program(inner_program: Pubkey, inner_preflight: PreflightAccounts) -> PreflightAccounts {
+    let mut accounts = vec![IAccountMeta {
+        pubkey: inner_program,
+        signer: false,
+        writable: false,
+    }];
+    accounts.extend(inner_preflight.accounts);
+    PreflightAccounts { accounts }
+}
 
-            // execute lock 2x
-            invoke(&ix, &ix_ais)?;
+#[error_code]
+pub enum TwiceWrapperError {
+    #[msg("First remaining account must be the wrapped permission program")]
+    MissingInnerProgram,
+}
 
-            // execute lock 2x
-            invoke(&ix, &ix_ais)?;
-        }
+#[derive(Accounts)]
+pub struct ILock<'info> {
+    /// CHECK: nil
+    token: AccountInfo<'info>,
+    /// CHECK: nil
+    mint: AccountInfo<'info>,
+    /// CHECK: nil
+    delegate: AccountInfo<'info>,
+    /// CHECK: nil
+    payer: AccountInfo<'info>,
+    /// CHECK: nil
+    token_program: AccountInfo<'info>,
+}
 
-        Ok(())
-    }
+#[derive(Accounts)]
+pub struct IUnlock<'info> {
+    /// CHECK: nil
+    token: AccountInfo<'info>,
+    /// CHECK: nil
+    mint: AccountInfo<'info>,
+    /// CHECK: nil
+    delegate: AccountInfo<'info>,
+    /// CHECK: nil
+    token_program: AccountInfo<'info>,
 }
 
 #[derive(Accounts)]
-pub struct TILock<'info> {
+pub struct Lock<'info> {
     #[account(mut)]
     token: InterfaceAccount<'info, TokenAccount>,
     mint: InterfaceAccount<'info, Mint>,
@@ -271,19 +157,13 @@ pub struct TILock<'info> {
     #[account(mut)]
     payer: Signer<'info>,
     token_program: Interface<'info, TokenInterface>,
-    /// CHECK: permission program
-    perm_program: AccountInfo<'info>,
-    // ix_accounts: Option<Account<'info, IxAccounts>>,
 }
 
 #[derive(Accounts)]
-pub struct TIUnlock<'info> {
+pub struct Unlock<'info> {
     #[account(mut)]
     token: InterfaceAccount<'info, TokenAccount>,
     mint: InterfaceAccount<'info, Mint>,
     delegate: Signer<'info>,
     token_program: Interface<'info, TokenInterface>,
-    /// CHECK: permission program
-    perm_program: AccountInfo<'info>,
-    // ix_accounts: Option<Account<'info, IxAccounts>>,
 }
@@ -0,0 +1,191 @@
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use permissioned_token_wrapper::{TokenRecord, LOCKER_LIST_PREFIX, TOKEN_RECORD_PREFIX};
+use solana_program_test::BanksClientError;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_program,
+};
+use test_fixtures::{
+    clone_keypair, lock_metas, preflight, process, program_control, token_account, unlock_metas,
+    TokenFixture,
+};
+
+/// A token of a permissioned_token_wrapper mint, locked and unlocked through twice_wrapper
+struct Fixture {
+    base: TokenFixture,
+}
+
+impl Fixture {
+    async fn new() -> Self {
+        let programs = [
+            (twice_wrapper::id(), "twice_wrapper"),
+            (
+                permissioned_token_wrapper::id(),
+                "permissioned_token_wrapper",
+            ),
+        ];
+        Self {
+            base: TokenFixture::new(&programs, &permissioned_token_wrapper::id(), 1).await,
+        }
+    }
+
+    fn token_record(&self) -> Pubkey {
+        Pubkey::find_program_address(
+            &[self.base.token.as_ref(), TOKEN_RECORD_PREFIX.as_bytes()],
+            &permissioned_token_wrapper::id(),
+        )
+        .0
+    }
+
+    /// Interface accounts followed by the wrapped program, which twice_wrapper expects first
+    fn with_inner_program(mut base: Vec<AccountMeta>) -> Vec<AccountMeta> {
+        base.push(AccountMeta::new_readonly(
+            permissioned_token_wrapper::id(),
+            false,
+        ));
+        base
+    }
+
+    async fn lock(&mut self, nonce: Option<u64>) -> Result<(), BanksClientError> {
+        let (token, mint, owner, payer) = (
+            self.base.token,
+            self.base.mint,
+            self.base.owner.pubkey(),
+            self.base.context.payer.pubkey(),
+        );
+        let base = Self::with_inner_program(lock_metas(&token, &mint, &owner, &payer));
+        let mut accounts = twice_wrapper::accounts::Lock {
+            token,
+            mint,
+            delegate: owner,
+            payer,
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None);
+        accounts
+            .extend(preflight(&mut self.base.context, &twice_wrapper::id(), "lock", base).await);
+        let ix = Instruction {
+            program_id: twice_wrapper::id(),
+            accounts,
+            data: twice_wrapper::instruction::Lock { nonce }.data(),
+        };
+        let owner = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&owner]).await
+    }
+
+    async fn unlock(&mut self, nonce: Option<u64>) -> Result<(), BanksClientError> {
+        let (token, mint, owner) = (self.base.token, self.base.mint, self.base.owner.pubkey());
+        let base = Self::with_inner_program(unlock_metas(&token, &mint, &owner));
+        let mut accounts = twice_wrapper::accounts::Unlock {
+            token,
+            mint,
+            delegate: owner,
+            token_program: spl_token::id(),
+        }
+        .to_account_metas(None);
+        accounts
+            .extend(preflight(&mut self.base.context, &twice_wrapper::id(), "unlock", base).await);
+        let ix = Instruction {
+            program_id: twice_wrapper::id(),
+            accounts,
+            data: twice_wrapper::instruction::Unlock { nonce }.data(),
+        };
+        let owner = clone_keypair(&self.base.owner);
+        process(&mut self.base.context, &[ix], &[&owner]).await
+    }
+
+    /// Locks the token directly with permissioned_token_wrapper, as an SPL delegate the
+    /// owner approved
+    async fn lock_directly(&mut self) -> Keypair {
+        let delegate = Keypair::new();
+        let approve = spl_token::instruction::approve(
+            &spl_token::id(),
+            &self.base.token,
+            &delegate.pubkey(),
+            &self.base.owner.pubkey(),
+            &[],
+            1,
+        )
+        .unwrap();
+        let lock = Instruction {
+            program_id: permissioned_token_wrapper::id(),
+            accounts: permissioned_token_wrapper::accounts::Lock {
+                token: self.base.token,
+                mint: self.base.mint,
+                delegate: delegate.pubkey(),
+                payer: self.base.context.payer.pubkey(),
+                token_program: spl_token::id(),
+                program_control: program_control(&permissioned_token_wrapper::id()),
+                token_record: self.token_record(),
+                system_program: system_program::id(),
+                locker_list: Pubkey::find_program_address(
+                    &[self.base.mint.as_ref(), LOCKER_LIST_PREFIX.as_bytes()],
+                    &permissioned_token_wrapper::id(),
+                )
+                .0,
+            }
+            .to_account_metas(None),
+            data: permissioned_token_wrapper::instruction::Lock {}.data(),
+        };
+        let owner = clone_keypair(&self.base.owner);
+        process(
+            &mut self.base.context,
+            &[approve, lock],
+            &[&owner, &delegate],
+        )
+        .await
+        .unwrap();
+        delegate
+    }
+
+    async fn lockers(&mut self) -> Vec<Pubkey> {
+        let account = self
+            .context
+            .banks_client
+            .get_account(self.token_record())
+            .await
+            .unwrap()
+            .unwrap();
+        TokenRecord::try_deserialize(&mut &account.data[..])
+            .unwrap()
+            .lockers
+    }
+}
+
+#[tokio::test]
+async fn locks_and_unlocks_through_the_wrapper() {
+    let mut fixture = Fixture::new().await;
+    fixture.lock(None).await.unwrap();
+    assert!(token_account(&mut fixture.base.context, fixture.base.token)
+        .await
+        .is_frozen());
+    assert_eq!(fixture.lockers().await, vec![fixture.base.owner.pubkey()]);
+
+    // the inner program sees a single unlock, a second one would fail with `NotLocked`
+    fixture.unlock(None).await.unwrap();
+    assert!(
+        !token_account(&mut fixture.base.context, fixture.base.token)
+            .await
+            .is_frozen()
+    );
+    assert!(fixture.lockers().await.is_empty());
+}
+
+#[tokio::test]
+async fn unlock_releases_only_the_callers_lock() {
+    let mut fixture = Fixture::new().await;
+    let other = fixture.lock_directly().await;
+    fixture.lock(Some(1)).await.unwrap();
+    assert_eq!(
+        fixture.lockers().await,
+        vec![other.pubkey(), fixture.base.owner.pubkey()]
+    );
+
+    fixture.unlock(Some(1)).await.unwrap();
+    assert!(token_account(&mut fixture.base.context, fixture.base.token)
+        .await
+        .is_frozen());
+    assert_eq!(fixture.lockers().await, vec![other.pubkey()]);
+}
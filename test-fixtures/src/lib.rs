@@ -0,0 +1,302 @@
+//! `solana-program-test` fixtures shared by the workspace's tests: loading the programs
+//! `anchor build` deployed, creating mints and token accounts, and simulating preflight.
+
+use std::path::PathBuf;
+
+use anchor_lang::{solana_program::hash, AnchorDeserialize};
+use interface::PreflightAccounts;
+use solana_program_test::{BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    bpf_loader,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signer},
+    system_instruction, system_program,
+    transaction::{Transaction, TransactionError},
+};
+
+/// Lamports every wallet added with `add_wallet` starts with
+pub const WALLET_LAMPORTS: u64 = 1_000_000_000;
+
+/// Path of `target/deploy/{name}.so`, panics when the program hasn't been built
+pub fn deployed_program_path(name: &str) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../target/deploy")
+        .join(format!("{}.so", name));
+    assert!(
+        path.exists(),
+        "{} not found, run `anchor build` first",
+        path.display()
+    );
+    path
+}
+
+pub fn deployed_program(name: &str) -> Vec<u8> {
+    std::fs::read(deployed_program_path(name)).unwrap()
+}
+
+pub fn program_account(elf: Vec<u8>) -> Account {
+    Account {
+        lamports: Rent::default().minimum_balance(elf.len()),
+        data: elf,
+        owner: bpf_loader::id(),
+        executable: true,
+        rent_epoch: 0,
+    }
+}
+
+/// Deploys `target/deploy/{name}.so` at `program_id`
+pub fn add_program(program_test: &mut ProgramTest, program_id: Pubkey, name: &str) {
+    program_test.add_account(program_id, program_account(deployed_program(name)));
+}
+
+/// A new keypair holding `WALLET_LAMPORTS`
+pub fn add_wallet(program_test: &mut ProgramTest) -> Keypair {
+    let wallet = Keypair::new();
+    program_test.add_account(
+        wallet.pubkey(),
+        Account::new(WALLET_LAMPORTS, 0, &system_program::id()),
+    );
+    wallet
+}
+
+pub fn clone_keypair(keypair: &Keypair) -> Keypair {
+    Keypair::from_bytes(&keypair.to_bytes()).unwrap()
+}
+
+pub fn sighash(ix_name: &str) -> Vec<u8> {
+    hash::hash(format!("global:{}", ix_name).as_bytes()).to_bytes()[..8].to_vec()
+}
+
+/// The `"static"` PDA a permission program freezes and thaws with
+pub fn program_control(perm_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"static"], perm_program).0
+}
+
+/// Custom program error a transaction failed with, e.g. an Anchor error code
+pub fn custom_error(err: &BanksClientError) -> Option<u32> {
+    match err {
+        BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        ))
+        | BanksClientError::SimulationError {
+            err: TransactionError::InstructionError(_, InstructionError::Custom(code)),
+            ..
+        } => Some(*code),
+        _ => None,
+    }
+}
+
+/// Signs with the context payer and `signers` and processes `ixs` under a new blockhash
+pub async fn process(
+    context: &mut ProgramTestContext,
+    ixs: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    let blockhash = context
+        .get_new_latest_blockhash()
+        .await
+        .map_err(BanksClientError::from)?;
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(
+        ixs,
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        blockhash,
+    );
+    context.banks_client.process_transaction(tx).await
+}
+
+/// A mint whose mint authority is the context payer
+pub async fn create_mint(
+    context: &mut ProgramTestContext,
+    decimals: u8,
+    freeze_authority: Option<&Pubkey>,
+) -> Pubkey {
+    let mint = Keypair::new();
+    let payer = context.payer.pubkey();
+    let ixs = [
+        system_instruction::create_account(
+            &payer,
+            &mint.pubkey(),
+            Rent::default().minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint2(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &payer,
+            freeze_authority,
+            decimals,
+        )
+        .unwrap(),
+    ];
+    process(context, &ixs, &[&mint]).await.unwrap();
+    mint.pubkey()
+}
+
+/// A token account of `mint` owned by `owner`, `amount` tokens are minted to it
+pub async fn create_token_account(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> Pubkey {
+    let account = Keypair::new();
+    let payer = context.payer.pubkey();
+    let mut ixs = vec![
+        system_instruction::create_account(
+            &payer,
+            &account.pubkey(),
+            Rent::default().minimum_balance(spl_token::state::Account::LEN),
+            spl_token::state::Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_account3(
+            &spl_token::id(),
+            &account.pubkey(),
+            mint,
+            owner,
+        )
+        .unwrap(),
+    ];
+    if amount > 0 {
+        ixs.push(
+            spl_token::instruction::mint_to(
+                &spl_token::id(),
+                mint,
+                &account.pubkey(),
+                &payer,
+                &[],
+                amount,
+            )
+            .unwrap(),
+        );
+    }
+    process(context, &ixs, &[&account]).await.unwrap();
+    account.pubkey()
+}
+
+pub async fn token_account(
+    context: &mut ProgramTestContext,
+    key: Pubkey,
+) -> spl_token::state::Account {
+    let account = context
+        .banks_client
+        .get_account(key)
+        .await
+        .unwrap()
+        .unwrap();
+    spl_token::state::Account::unpack(&account.data).unwrap()
+}
+
+pub async fn lamports(context: &mut ProgramTestContext, key: Pubkey) -> u64 {
+    context.banks_client.get_balance(key).await.unwrap()
+}
+
+/// A wallet holding tokens of a new mint, the starting point of most suites
+pub struct TokenFixture {
+    pub context: ProgramTestContext,
+    pub mint: Pubkey,
+    pub owner: Keypair,
+    pub token: Pubkey,
+}
+
+impl TokenFixture {
+    /// Adds the owner's wallet to `program_test` and starts it, then creates a mint without
+    /// decimals frozen by `freeze_authority` and the owner's token account holding `amount`
+    pub async fn start(
+        mut program_test: ProgramTest,
+        freeze_authority: &Pubkey,
+        amount: u64,
+    ) -> Self {
+        let owner = add_wallet(&mut program_test);
+        let mut context = program_test.start_with_context().await;
+        let mint = create_mint(&mut context, 0, Some(freeze_authority)).await;
+        let token = create_token_account(&mut context, &mint, &owner.pubkey(), amount).await;
+        Self {
+            context,
+            mint,
+            owner,
+            token,
+        }
+    }
+
+    /// `start` with `programs`, given as (program id, `target/deploy` name), deployed and the
+    /// mint frozen by `perm_program`'s program control
+    pub async fn new(programs: &[(Pubkey, &str)], perm_program: &Pubkey, amount: u64) -> Self {
+        let mut program_test = ProgramTest::default();
+        for (program_id, name) in programs {
+            add_program(&mut program_test, *program_id, name);
+        }
+        Self::start(program_test, &program_control(perm_program), amount).await
+    }
+}
+
+/// Interface accounts of `lock`, none of them signing as preflight receives them
+pub fn lock_metas(
+    token: &Pubkey,
+    mint: &Pubkey,
+    delegate: &Pubkey,
+    payer: &Pubkey,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(*token, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*delegate, false),
+        AccountMeta::new(*payer, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]
+}
+
+/// Interface accounts of `unlock`, none of them signing as preflight receives them
+pub fn unlock_metas(token: &Pubkey, mint: &Pubkey, delegate: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(*token, false),
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new_readonly(*delegate, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]
+}
+
+/// Simulates `perm_program`'s `preflight_{ix_name}` and returns the accounts it resolved
+pub async fn preflight(
+    context: &mut ProgramTestContext,
+    perm_program: &Pubkey,
+    ix_name: &str,
+    base: Vec<AccountMeta>,
+) -> Vec<AccountMeta> {
+    let ix = Instruction {
+        program_id: *perm_program,
+        accounts: base,
+        data: sighash(&format!("preflight_{}", ix_name)),
+    };
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&context.payer.pubkey()),
+        &[&context.payer],
+        context.last_blockhash,
+    );
+    let simulation = context.banks_client.simulate_transaction(tx).await.unwrap();
+    let return_data = simulation
+        .simulation_details
+        .and_then(|details| details.return_data)
+        .expect("preflight set no return data");
+    let bytes = Vec::<u8>::try_from_slice(&return_data.data).unwrap();
+    PreflightAccounts::try_from_slice(&bytes)
+        .unwrap()
+        .accounts
+        .iter()
+        .map(|account| AccountMeta {
+            pubkey: account.pubkey,
+            is_signer: account.signer,
+            is_writable: account.writable,
+        })
+        .collect()
+}
@@ -18,13 +18,20 @@ import {
   ASSOCIATED_TOKEN_PROGRAM_ID,
   getAssociatedTokenAddressSync,
   createTransferInstruction,
+  createApproveInstruction,
+  createFreezeAccountInstruction,
+  createSetAuthorityInstruction,
+  AuthorityType,
+  getAccount,
   TokenError,
 } from "@solana/spl-token";
 import { Caller } from "../target/types/caller";
 import { PermissionedTokenWrapper } from "../target/types/permissioned_token_wrapper";
 import { TwicePermissioned } from "../target/types/twice_permissioned";
+import { PermissionChain } from "../target/types/permission_chain";
 import { IdlInstruction, idlAddress } from "@coral-xyz/anchor/dist/cjs/idl";
 import { AccountsGeneric } from "@coral-xyz/anchor/dist/cjs/program/accounts-resolver";
+import { assert } from "chai";
 
 export type LockContext = {
   token: anchor.Address;
@@ -71,12 +78,121 @@ async function resolveRemainingAccounts(
     };
   }
 
-  let builder = targetProgram.methods.lock().accounts(ctx as any);
+  // interface functions may take extra args (e.g. an idempotency nonce), which don't affect accounts
+  let idlIx = targetProgram.idl.instructions.find(
+    (ix) => ix.name === instructionName
+  );
+  let args = idlIx ? idlIx.args.map(() => null) : [];
+  let builder = targetProgram.methods[instructionName](...args).accounts(
+    ctx as any
+  );
   const ix = await builder.instruction();
   let additionalAccounts = ix.keys.slice(Object.keys(ctx).length);
   return { accounts: additionalAccounts, resolved: 0 };
 }
 
+async function callLock(
+  caller: Program<Caller>,
+  permProgram: Program<any>,
+  token: PublicKey,
+  mint: PublicKey,
+  delegate: Keypair | null,
+  nonce: anchor.BN | null = null
+): Promise<string> {
+  const payer = permProgram.provider.publicKey!;
+  const builder = caller.methods.lock(nonce, false, false).accounts({
+    token,
+    mint,
+    delegate: delegate ? delegate.publicKey : payer,
+    payer,
+    tokenProgram: TOKEN_PROGRAM_ID,
+    permProgram: permProgram.programId,
+  });
+  let keys = await builder.pubkeys();
+  let { accounts: remainingAccounts } = await resolveRemainingAccounts(
+    permProgram.provider,
+    "lock",
+    keys
+  );
+  return await builder
+    .remainingAccounts(remainingAccounts)
+    .signers(delegate ? [delegate] : [])
+    .rpc({ skipPreflight: true });
+}
+
+async function callUnlock(
+  caller: Program<Caller>,
+  permProgram: Program<any>,
+  token: PublicKey,
+  mint: PublicKey,
+  delegate: Keypair | null,
+  nonce: anchor.BN | null = null
+): Promise<string> {
+  const payer = permProgram.provider.publicKey!;
+  const builder = caller.methods.unlock(nonce, false).accounts({
+    token,
+    mint,
+    delegate: delegate ? delegate.publicKey : payer,
+    operator: payer,
+    tokenProgram: TOKEN_PROGRAM_ID,
+    permProgram: permProgram.programId,
+  });
+  let keys = await builder.pubkeys();
+  let { accounts: remainingAccounts } = await resolveRemainingAccounts(
+    permProgram.provider,
+    "unlock",
+    keys
+  );
+  return await builder
+    .remainingAccounts(remainingAccounts)
+    .signers(delegate ? [delegate] : [])
+    .rpc({ skipPreflight: true });
+}
+
+async function expectFailure(promise: Promise<any>, description: string) {
+  let failed = false;
+  try {
+    await promise;
+  } catch (e) {
+    failed = true;
+    console.log(`\tSuccessfully failed to ${description}:`, e);
+  }
+  if (!failed) {
+    throw Error(`Should not be able to ${description}`);
+  }
+}
+
+// Like `expectFailure`, but the failure must be `program`'s error `errorName`,
+// even when it was raised in a CPI
+async function expectError(
+  promise: Promise<any>,
+  program: Program<any>,
+  errorName: string,
+  description: string
+) {
+  const code = program.idl.errors!.find((e) => e.name === errorName)!.code;
+  try {
+    await promise;
+  } catch (e) {
+    console.log(`\tSuccessfully failed to ${description}:`, e);
+    assert.instanceOf(e, anchor.AnchorError);
+    assert.equal((e as anchor.AnchorError).error.errorCode.number, code);
+    return;
+  }
+  throw Error(`Should not be able to ${description}`);
+}
+
+// Lays out the accounts of several interface calls back to back, see `interface::partition_accounts`
+function partitionRemainingAccounts(partitions: AccountMeta[][]): {
+  lens: number[];
+  accounts: AccountMeta[];
+} {
+  return {
+    lens: partitions.map((partition) => partition.length),
+    accounts: partitions.flat(),
+  };
+}
+
 describe("caller-program", () => {
   // Configure the client to use the local cluster.
   anchor.setProvider(anchor.AnchorProvider.env());
@@ -167,7 +283,7 @@ describe("caller-program", () => {
         permProgram: program.programId,
       };
 
-      const builder = caller.methods.lock().accounts(lockCtx);
+      const builder = caller.methods.lock(null, false, true).accounts(lockCtx);
       let keys = await builder.pubkeys();
       let { accounts: remainingAccounts } = await resolveRemainingAccounts(
         program.provider,
@@ -205,10 +321,11 @@ describe("caller-program", () => {
       }
     });
     it("Can unlock user token account", async () => {
-      const builder = caller.methods.unlock().accounts({
+      const builder = caller.methods.unlock(null, false).accounts({
         token: tokenAccount,
         mint,
         delegate: program.provider.publicKey!,
+        operator: program.provider.publicKey!,
         tokenProgram: TOKEN_PROGRAM_ID,
         permProgram: program.programId,
       });
@@ -244,9 +361,9 @@ describe("caller-program", () => {
       console.log("\tTransferred token to normie: ", txid);
     });
   });
-  describe("Twice permissioned token", () => {
+  describe("permissioned token with multiple lockers", () => {
     const program = anchor.workspace
-      .TwicePermissioned as Program<TwicePermissioned>;
+      .PermissionedTokenWrapper as Program<PermissionedTokenWrapper>;
 
     let programControl: PublicKey = PublicKey.findProgramAddressSync(
       [Buffer.from("static")],
@@ -257,17 +374,31 @@ describe("caller-program", () => {
     const decimals = 9;
     let mint: PublicKey;
     let tokenAccount: PublicKey;
-    let tokenRecord: PublicKey;
+
+    // Second locker, e.g. a lending program's delegate
+    let lenderKp = Keypair.generate();
+    let lender = lenderKp.publicKey;
 
     let randomKp = Keypair.generate();
     let randomPerson = randomKp.publicKey;
     let randoToken: PublicKey;
 
+    async function transferToRando() {
+      let transaction = new Transaction().add(
+        createTransferInstruction(tokenAccount, randoToken, payer, 1, [])
+      );
+      return await program.provider.sendAndConfirm(transaction, [], {
+        skipPreflight: true,
+        commitment: "confirmed",
+      });
+    }
+
     before(async () => {
       let mintKp = Keypair.generate();
       mint = mintKp.publicKey;
 
       tokenAccount = getAssociatedTokenAddressSync(mint, payer);
+      randoToken = getAssociatedTokenAddressSync(mint, randomPerson);
 
       let lamports =
         await program.provider.connection.getMinimumBalanceForRentExemption(
@@ -297,6 +428,14 @@ describe("caller-program", () => {
           TOKEN_PROGRAM_ID,
           ASSOCIATED_TOKEN_PROGRAM_ID
         ),
+        createAssociatedTokenAccountInstruction(
+          payer,
+          randoToken,
+          randomPerson,
+          mint,
+          TOKEN_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        ),
         createMintToInstruction(
           mint,
           tokenAccount,
@@ -304,7 +443,8 @@ describe("caller-program", () => {
           1,
           [],
           TOKEN_PROGRAM_ID
-        )
+        ),
+        createApproveInstruction(tokenAccount, lender, payer, 1)
       );
 
       let txid = await program.provider.sendAndConfirm(transaction, [mintKp], {
@@ -313,108 +453,1583 @@ describe("caller-program", () => {
       });
       console.log("\tCreated new mint with txid: ", txid);
     });
-    it("Can lock user token account", async () => {
-      tokenRecord = PublicKey.findProgramAddressSync(
-        [tokenAccount.toBuffer(), Buffer.from("token_record")],
-        program.programId
-      )[0];
+    it("Owner and lender can both lock", async () => {
+      console.log("\tOwner locked", await callLock(caller, program, tokenAccount, mint, null));
+      console.log("\tLender locked", await callLock(caller, program, tokenAccount, mint, lenderKp));
+    });
+    it("Token stays locked after owner unlocks", async () => {
+      console.log("\tOwner unlocked", await callUnlock(caller, program, tokenAccount, mint, null));
+      let account = await getAccount(
+        program.provider.connection,
+        tokenAccount,
+        "confirmed"
+      );
+      assert.isTrue(account.isFrozen);
+      await expectFailure(transferToRando(), "transfer locked token");
+    });
+    it("Token thaws once every locker unlocks", async () => {
+      console.log("\tLender unlocked", await callUnlock(caller, program, tokenAccount, mint, lenderKp));
+      let txid = await transferToRando();
+      console.log("\tTransferred token to normie: ", txid);
+    });
+  });
+  describe("permissioned token with locker list", () => {
+    const program = anchor.workspace
+      .PermissionedTokenWrapper as Program<PermissionedTokenWrapper>;
 
-      let lockCtx: LockContext = {
-        token: tokenAccount,
-        mint,
-        delegate: program.provider.publicKey!,
-        payer: program.provider.publicKey!,
-        tokenProgram: TOKEN_PROGRAM_ID,
-        permProgram: program.programId,
-      };
+    let programControl: PublicKey = PublicKey.findProgramAddressSync(
+      [Buffer.from("static")],
+      program.programId
+    )[0];
 
-      const builder = caller.methods.lock().accounts(lockCtx);
-      let keys = await builder.pubkeys();
-      let { accounts: remainingAccounts } = await resolveRemainingAccounts(
-        program.provider,
-        "lock",
-        keys
-      );
-      const transaction = await builder
-        .remainingAccounts(remainingAccounts)
-        .transaction();
+    let payer: PublicKey = program.provider.publicKey!;
+    const decimals = 9;
+    let mint: PublicKey;
+    let tokenAccount: PublicKey;
+    let lockerList: PublicKey;
 
-      let tx = await program.provider.sendAndConfirm(transaction, [], {
-        skipPreflight: true,
-      });
-      tx = await program.provider.sendAndConfirm(transaction, [], {
-        skipPreflight: true,
-      });
-      console.log("\tLocked", tx);
-    });
-    it("Cannot transfer locked token", async () => {
-      randoToken = getAssociatedTokenAddressSync(mint, randomPerson);
+    // Marketplace the issuer does not want locking its tokens
+    let marketKp = Keypair.generate();
+    let market = marketKp.publicKey;
 
-      let transaction = new Transaction().add(
+    before(async () => {
+      let mintKp = Keypair.generate();
+      mint = mintKp.publicKey;
+
+      tokenAccount = getAssociatedTokenAddressSync(mint, payer);
+      lockerList = PublicKey.findProgramAddressSync(
+        [mint.toBuffer(), Buffer.from("locker_list")],
+        program.programId
+      )[0];
+
+      let lamports =
+        await program.provider.connection.getMinimumBalanceForRentExemption(
+          MINT_SIZE,
+          "confirmed"
+        );
+      const transaction = new Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: payer,
+          newAccountPubkey: mint,
+          space: MINT_SIZE,
+          lamports,
+          programId: TOKEN_PROGRAM_ID,
+        }),
+        createInitializeMint2Instruction(
+          mint,
+          decimals,
+          payer,
+          programControl,
+          TOKEN_PROGRAM_ID
+        ),
         createAssociatedTokenAccountInstruction(
           payer,
-          randoToken,
-          randomPerson,
+          tokenAccount,
+          payer,
           mint,
           TOKEN_PROGRAM_ID,
           ASSOCIATED_TOKEN_PROGRAM_ID
         ),
-        createTransferInstruction(tokenAccount, randoToken, payer, 1, [])
+        createMintToInstruction(
+          mint,
+          tokenAccount,
+          payer,
+          1,
+          [],
+          TOKEN_PROGRAM_ID
+        ),
+        createApproveInstruction(tokenAccount, market, payer, 1)
       );
 
-      try {
-        let txid = await program.provider.sendAndConfirm(transaction, [], {
-          skipPreflight: true,
-          preflightCommitment: "confirmed",
-        });
-        throw Error("Should not be able to transfer locked token");
-      } catch (e) {
-        console.log("\tSuccessfully failed to transfer locked token:", e);
-      }
-    });
-    it("Can unlock user token account", async () => {
-      const builder = caller.methods.unlock().accounts({
-        token: tokenAccount,
-        mint,
-        delegate: program.provider.publicKey!,
-        tokenProgram: TOKEN_PROGRAM_ID,
-        permProgram: program.programId,
-      });
-      let keys = await builder.pubkeys();
-      let { accounts: remainingAccounts } = await resolveRemainingAccounts(
-        program.provider,
-        "unlock",
-        keys
-      );
-      const transaction = await builder
-        .remainingAccounts(remainingAccounts)
-        .transaction();
-      let tx = await program.provider.sendAndConfirm(transaction, [], {
-        skipPreflight: true,
-      });
-      tx = await program.provider.sendAndConfirm(transaction, [], {
+      let txid = await program.provider.sendAndConfirm(transaction, [mintKp], {
         skipPreflight: true,
+        preflightCommitment: "confirmed",
       });
-      console.log("\tUnlocked", tx);
+      console.log("\tCreated new mint with txid: ", txid);
+
+      txid = await program.methods
+        .initLockerList(payer, [], [market])
+        .accounts({
+          mint,
+          mintAuthority: payer,
+          payer,
+          lockerList,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      console.log("\tCreated locker list: ", txid);
+    });
+    it("Denylisted delegate cannot lock", async () => {
+      await expectError(
+        callLock(caller, program, tokenAccount, mint, marketKp),
+        program,
+        "LockerNotPermitted",
+        "lock as a denylisted delegate"
+      );
     });
+    it("Owner can still lock and unlock", async () => {
+      console.log(
+        "\tLocked",
+        await callLock(caller, program, tokenAccount, mint, null)
+      );
+      console.log(
+        "\tUnlocked",
+        await callUnlock(caller, program, tokenAccount, mint, null)
+      );
+    });
+    it("Admin can allowlist the delegate", async () => {
+      let txid = await program.methods
+        .updateLockerList([market], [])
+        .accounts({ mint, admin: payer, lockerList })
+        .rpc();
+      console.log("\tUpdated locker list: ", txid);
 
-    it("Can transfer unlocked token", async () => {
-      let transaction = new Transaction().add(
+      console.log(
+        "\tLocked as marketplace",
+        await callLock(caller, program, tokenAccount, mint, marketKp)
+      );
+    });
+    it("Delegate missing from the allowlist cannot lock", async () => {
+      // The allowlist now only holds the marketplace, which leaves out the owner
+      await expectError(
+        callLock(caller, program, tokenAccount, mint, null),
+        program,
+        "LockerNotPermitted",
+        "lock as a delegate missing from the allowlist"
+      );
+      let record = await program.account.tokenRecord.fetch(
+        PublicKey.findProgramAddressSync(
+          [tokenAccount.toBuffer(), Buffer.from("token_record")],
+          program.programId
+        )[0]
+      );
+      assert.deepEqual(
+        record.lockers.map((locker) => locker.toBase58()),
+        [market.toBase58()]
+      );
+    });
+  });
+  describe("permissioned token lock state transitions", () => {
+    const program = anchor.workspace
+      .PermissionedTokenWrapper as Program<PermissionedTokenWrapper>;
+
+    let programControl: PublicKey = PublicKey.findProgramAddressSync(
+      [Buffer.from("static")],
+      program.programId
+    )[0];
+
+    let payer: PublicKey = program.provider.publicKey!;
+    const decimals = 9;
+    let mint: PublicKey;
+    let tokenAccount: PublicKey;
+    let tokenRecord: PublicKey;
+
+    // Token account that is frozen before the program controls the mint
+    let frozenOwnerKp = Keypair.generate();
+    let frozenToken: PublicKey;
+
+    // Second locker, approved by the owner before any lock
+    let lenderKp = Keypair.generate();
+
+    before(async () => {
+      let mintKp = Keypair.generate();
+      mint = mintKp.publicKey;
+
+      tokenAccount = getAssociatedTokenAddressSync(mint, payer);
+      frozenToken = getAssociatedTokenAddressSync(
+        mint,
+        frozenOwnerKp.publicKey
+      );
+      tokenRecord = PublicKey.findProgramAddressSync(
+        [tokenAccount.toBuffer(), Buffer.from("token_record")],
+        program.programId
+      )[0];
+
+      let lamports =
+        await program.provider.connection.getMinimumBalanceForRentExemption(
+          MINT_SIZE,
+          "confirmed"
+        );
+      const transaction = new Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: payer,
+          newAccountPubkey: mint,
+          space: MINT_SIZE,
+          lamports,
+          programId: TOKEN_PROGRAM_ID,
+        }),
+        createInitializeMint2Instruction(
+          mint,
+          decimals,
+          payer,
+          payer,
+          TOKEN_PROGRAM_ID
+        ),
         createAssociatedTokenAccountInstruction(
           payer,
-          randoToken,
-          randomPerson,
+          tokenAccount,
+          payer,
           mint,
           TOKEN_PROGRAM_ID,
           ASSOCIATED_TOKEN_PROGRAM_ID
         ),
-        createTransferInstruction(tokenAccount, randoToken, payer, 1, [])
+        createAssociatedTokenAccountInstruction(
+          payer,
+          frozenToken,
+          frozenOwnerKp.publicKey,
+          mint,
+          TOKEN_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        ),
+        createMintToInstruction(
+          mint,
+          tokenAccount,
+          payer,
+          1,
+          [],
+          TOKEN_PROGRAM_ID
+        ),
+        createApproveInstruction(tokenAccount, lenderKp.publicKey, payer, 1),
+        createFreezeAccountInstruction(frozenToken, mint, payer),
+        createSetAuthorityInstruction(
+          mint,
+          payer,
+          AuthorityType.FreezeAccount,
+          programControl
+        )
       );
 
-      let txid = await program.provider.sendAndConfirm(transaction, [], {
+      let txid = await program.provider.sendAndConfirm(transaction, [mintKp], {
         skipPreflight: true,
         preflightCommitment: "confirmed",
       });
+      console.log("\tCreated new mint with txid: ", txid);
+    });
+    it("Unlocked --unlock--> fails", async () => {
+      await expectFailure(
+        callUnlock(caller, program, tokenAccount, mint, null),
+        "unlock a token that was never locked"
+      );
+    });
+    it("Unlocked --lock--> Locked(1)", async () => {
+      await callLock(caller, program, tokenAccount, mint, null);
+      let record = await program.account.tokenRecord.fetch(tokenRecord);
+      assert.equal(record.version, 1);
+      assert.equal(record.locked, 1);
+      assert.equal(record.lockers.length, 1);
+    });
+    it("Migrating a current record is a no-op", async () => {
+      let tx = await program.methods
+        .migrateRecord()
+        .accounts({
+          token: tokenAccount,
+          payer,
+          tokenRecord,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc({ skipPreflight: true });
+      console.log("\tMigrated", tx);
+      let record = await program.account.tokenRecord.fetch(tokenRecord);
+      assert.equal(record.version, 1);
+      assert.equal(record.lockers.length, 1);
+    });
+    it("Locked(1) --lock by same delegate--> fails", async () => {
+      await expectError(
+        callLock(caller, program, tokenAccount, mint, null),
+        program,
+        "AlreadyLocked",
+        "lock a token twice with the same delegate"
+      );
+    });
+    it("Locked(1) --unlock--> Unlocked", async () => {
+      await callUnlock(caller, program, tokenAccount, mint, null);
+      let record = await program.account.tokenRecord.fetch(tokenRecord);
+      assert.equal(record.locked, 0);
+      assert.equal(record.lockers.length, 0);
+    });
+    it("Unlocked --unlock--> fails on an existing record", async () => {
+      await expectError(
+        callUnlock(caller, program, tokenAccount, mint, null),
+        program,
+        "NotLocked",
+        "unlock a token twice"
+      );
+    });
+    it("Unlocked --lock--> Locked(1) on an existing record", async () => {
+      await callLock(caller, program, tokenAccount, mint, null);
+      let record = await program.account.tokenRecord.fetch(tokenRecord);
+      assert.equal(record.locked, 1);
+      assert.equal(record.lockers.length, 1);
+    });
+    it("Locked(1) --lock by another delegate--> Locked(2)", async () => {
+      await callLock(caller, program, tokenAccount, mint, lenderKp);
+      let record = await program.account.tokenRecord.fetch(tokenRecord);
+      assert.equal(record.locked, 1);
+      assert.deepEqual(
+        record.lockers.map((locker) => locker.toBase58()),
+        [payer.toBase58(), lenderKp.publicKey.toBase58()]
+      );
+    });
+    it("Locked(2) --unlock by a non-locker--> fails", async () => {
+      await expectError(
+        callUnlock(caller, program, tokenAccount, mint, Keypair.generate()),
+        program,
+        "LockNotHeld",
+        "unlock a token with a delegate that holds no lock"
+      );
+      let record = await program.account.tokenRecord.fetch(tokenRecord);
+      assert.equal(record.lockers.length, 2);
+    });
+    it("Locked(2) --unlock--> Locked(1)", async () => {
+      await callUnlock(caller, program, tokenAccount, mint, lenderKp);
+      let record = await program.account.tokenRecord.fetch(tokenRecord);
+      assert.equal(record.locked, 1);
+      assert.deepEqual(
+        record.lockers.map((locker) => locker.toBase58()),
+        [payer.toBase58()]
+      );
+      let account = await getAccount(
+        program.provider.connection,
+        tokenAccount,
+        "confirmed"
+      );
+      assert.isTrue(account.isFrozen);
+    });
+    it("Locked(1) --unlock by the last locker--> Unlocked", async () => {
+      await callUnlock(caller, program, tokenAccount, mint, null);
+      let record = await program.account.tokenRecord.fetch(tokenRecord);
+      assert.equal(record.locked, 0);
+      assert.equal(record.lockers.length, 0);
+      let account = await getAccount(
+        program.provider.connection,
+        tokenAccount,
+        "confirmed"
+      );
+      assert.isFalse(account.isFrozen);
+    });
+    it("Unlocked --lock--> fails when frozen by other means", async () => {
+      let account = await getAccount(
+        program.provider.connection,
+        frozenToken,
+        "confirmed"
+      );
+      assert.isTrue(account.isFrozen);
+      await expectError(
+        callLock(caller, program, frozenToken, mint, frozenOwnerKp),
+        program,
+        "FrozenExternally",
+        "lock a token that was frozen outside the program"
+      );
+    });
+  });
+  describe("permissioned token with a full locker list", () => {
+    const program = anchor.workspace
+      .PermissionedTokenWrapper as Program<PermissionedTokenWrapper>;
+
+    // Loaded from `accounts/full_lockers-*.json` by the test validator: a frozen token account
+    // of a mint the program controls, whose record already lists `MAX_LOCKERS` (8) delegates.
+    // A frozen token account can't approve a new delegate, so the record can't be filled
+    // through the program. The owner isn't among the lockers.
+    const ownerKp = Keypair.fromSeed(new Uint8Array(32).fill(1));
+    const mint = new PublicKey("9hSR6S7WPtxmTojgo6GG3k4yDPecgJY292j7xrsUGWBu");
+    const tokenAccount = new PublicKey(
+      "GyGKxMyg1p9SsHfm15MkNUu1u9TN2JtTspcdmrtGUdse"
+    );
+
+    it("Locked(8) --lock--> fails", async () => {
+      let tokenRecord = PublicKey.findProgramAddressSync(
+        [tokenAccount.toBuffer(), Buffer.from("token_record")],
+        program.programId
+      )[0];
+      let record = await program.account.tokenRecord.fetch(tokenRecord);
+      assert.equal(record.lockers.length, 8);
+
+      await expectError(
+        callLock(caller, program, tokenAccount, mint, ownerKp),
+        program,
+        "TooManyLockers",
+        "lock a token that already has the maximum number of lockers"
+      );
+    });
+  });
+  describe("permissioned token batch lock", () => {
+    const program = anchor.workspace
+      .PermissionedTokenWrapper as Program<PermissionedTokenWrapper>;
+
+    let programControl: PublicKey = PublicKey.findProgramAddressSync(
+      [Buffer.from("static")],
+      program.programId
+    )[0];
+
+    let payer: PublicKey = program.provider.publicKey!;
+    const decimals = 0;
+    const portfolioSize = 3;
+    let mints: PublicKey[] = [];
+    let tokenAccounts: PublicKey[] = [];
+
+    // (token, mint) pairs followed by the accounts preflight would resolve
+    function batchRemainingAccounts(instructionName: string): AccountMeta[] {
+      let pairs: AccountMeta[] = [];
+      let records: AccountMeta[] = [];
+      for (let i = 0; i < portfolioSize; i++) {
+        pairs.push(
+          { pubkey: tokenAccounts[i], isSigner: false, isWritable: true },
+          { pubkey: mints[i], isSigner: false, isWritable: false }
+        );
+        records.push({
+          pubkey: PublicKey.findProgramAddressSync(
+            [tokenAccounts[i].toBuffer(), Buffer.from("token_record")],
+            program.programId
+          )[0],
+          isSigner: false,
+          isWritable: true,
+        });
+        if (instructionName === "lock_many") {
+          records.push({
+            pubkey: PublicKey.findProgramAddressSync(
+              [mints[i].toBuffer(), Buffer.from("locker_list")],
+              program.programId
+            )[0],
+            isSigner: false,
+            isWritable: false,
+          });
+        }
+      }
+      let shared: AccountMeta[] = [
+        { pubkey: programControl, isSigner: false, isWritable: false },
+      ];
+      if (instructionName === "lock_many") {
+        shared.push({
+          pubkey: SystemProgram.programId,
+          isSigner: false,
+          isWritable: false,
+        });
+      }
+      return [...pairs, ...shared, ...records];
+    }
+
+    before(async () => {
+      let lamports =
+        await program.provider.connection.getMinimumBalanceForRentExemption(
+          MINT_SIZE,
+          "confirmed"
+        );
+      for (let i = 0; i < portfolioSize; i++) {
+        let mintKp = Keypair.generate();
+        let mint = mintKp.publicKey;
+        let tokenAccount = getAssociatedTokenAddressSync(mint, payer);
+        mints.push(mint);
+        tokenAccounts.push(tokenAccount);
+
+        const transaction = new Transaction().add(
+          SystemProgram.createAccount({
+            fromPubkey: payer,
+            newAccountPubkey: mint,
+            space: MINT_SIZE,
+            lamports,
+            programId: TOKEN_PROGRAM_ID,
+          }),
+          createInitializeMint2Instruction(
+            mint,
+            decimals,
+            payer,
+            programControl,
+            TOKEN_PROGRAM_ID
+          ),
+          createAssociatedTokenAccountInstruction(
+            payer,
+            tokenAccount,
+            payer,
+            mint,
+            TOKEN_PROGRAM_ID,
+            ASSOCIATED_TOKEN_PROGRAM_ID
+          ),
+          createMintToInstruction(
+            mint,
+            tokenAccount,
+            payer,
+            1,
+            [],
+            TOKEN_PROGRAM_ID
+          )
+        );
+
+        let txid = await program.provider.sendAndConfirm(
+          transaction,
+          [mintKp],
+          {
+            skipPreflight: true,
+            preflightCommitment: "confirmed",
+          }
+        );
+        console.log("\tCreated new mint with txid: ", txid);
+      }
+
+      // anyone can fund a record PDA before it's created, lock_many must still create it
+      let prefunded = PublicKey.findProgramAddressSync(
+        [tokenAccounts[0].toBuffer(), Buffer.from("token_record")],
+        program.programId
+      )[0];
+      console.log(
+        "\tPrefunded a token record",
+        await program.provider.sendAndConfirm(
+          new Transaction().add(
+            SystemProgram.transfer({
+              fromPubkey: payer,
+              toPubkey: prefunded,
+              lamports: 1_000_000,
+            })
+          )
+        )
+      );
+    });
+    it("Can lock a portfolio in one instruction", async () => {
+      let tx = await caller.methods
+        .lockMany(portfolioSize)
+        .accounts({
+          delegate: payer,
+          payer,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          permProgram: program.programId,
+        })
+        .remainingAccounts(batchRemainingAccounts("lock_many"))
+        .rpc({ skipPreflight: true });
+      console.log("\tLocked portfolio", tx);
+
+      for (let tokenAccount of tokenAccounts) {
+        let record = await program.account.tokenRecord.fetch(
+          PublicKey.findProgramAddressSync(
+            [tokenAccount.toBuffer(), Buffer.from("token_record")],
+            program.programId
+          )[0]
+        );
+        assert.equal(record.locked, 1);
+      }
+    });
+    it("Can unlock a portfolio in one instruction", async () => {
+      let tx = await caller.methods
+        .unlockMany(portfolioSize, false)
+        .accounts({
+          delegate: payer,
+          operator: payer,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          permProgram: program.programId,
+        })
+        .remainingAccounts(batchRemainingAccounts("unlock_many"))
+        .rpc({ skipPreflight: true });
+      console.log("\tUnlocked portfolio", tx);
+
+      for (let tokenAccount of tokenAccounts) {
+        let record = await program.account.tokenRecord.fetch(
+          PublicKey.findProgramAddressSync(
+            [tokenAccount.toBuffer(), Buffer.from("token_record")],
+            program.programId
+          )[0]
+        );
+        assert.equal(record.locked, 0);
+      }
+    });
+  });
+  describe("permissioned token transfer through caller", () => {
+    const program = anchor.workspace
+      .PermissionedTokenWrapper as Program<PermissionedTokenWrapper>;
+
+    let programControl: PublicKey = PublicKey.findProgramAddressSync(
+      [Buffer.from("static")],
+      program.programId
+    )[0];
+
+    let payer: PublicKey = program.provider.publicKey!;
+    const decimals = 9;
+    let mint: PublicKey;
+    let tokenAccount: PublicKey;
+
+    // Receiver lets the payer lock its account, e.g. while a sale settles
+    let randomKp = Keypair.generate();
+    let randomPerson = randomKp.publicKey;
+    let randoToken: PublicKey;
+
+    // Receiver that has to opt in before the payer can send it locked tokens
+    let strangerKp = Keypair.generate();
+    let strangerToken: PublicKey;
+
+    before(async () => {
+      let mintKp = Keypair.generate();
+      mint = mintKp.publicKey;
+
+      tokenAccount = getAssociatedTokenAddressSync(mint, payer);
+      randoToken = getAssociatedTokenAddressSync(mint, randomPerson);
+      strangerToken = getAssociatedTokenAddressSync(mint, strangerKp.publicKey);
+
+      let lamports =
+        await program.provider.connection.getMinimumBalanceForRentExemption(
+          MINT_SIZE,
+          "confirmed"
+        );
+      const transaction = new Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: payer,
+          newAccountPubkey: mint,
+          space: MINT_SIZE,
+          lamports,
+          programId: TOKEN_PROGRAM_ID,
+        }),
+        createInitializeMint2Instruction(
+          mint,
+          decimals,
+          payer,
+          programControl,
+          TOKEN_PROGRAM_ID
+        ),
+        createAssociatedTokenAccountInstruction(
+          payer,
+          tokenAccount,
+          payer,
+          mint,
+          TOKEN_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        ),
+        createAssociatedTokenAccountInstruction(
+          payer,
+          randoToken,
+          randomPerson,
+          mint,
+          TOKEN_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        ),
+        createAssociatedTokenAccountInstruction(
+          payer,
+          strangerToken,
+          strangerKp.publicKey,
+          mint,
+          TOKEN_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        ),
+        createMintToInstruction(
+          mint,
+          tokenAccount,
+          payer,
+          3,
+          [],
+          TOKEN_PROGRAM_ID
+        ),
+        createApproveInstruction(randoToken, payer, randomPerson, 1)
+      );
+
+      let txid = await program.provider.sendAndConfirm(
+        transaction,
+        [mintKp, randomKp],
+        {
+          skipPreflight: true,
+          preflightCommitment: "confirmed",
+        }
+      );
+      console.log("\tCreated new mint with txid: ", txid);
+
+      console.log(
+        "\tLocked",
+        await callLock(caller, program, tokenAccount, mint, null)
+      );
+    });
+    // unlock accounts of source, lock accounts of dest, lock accounts of source when relocking.
+    // `lockDest` is the instruction `tfer` picks for dest, `lock_on_receive` unless the payer
+    // owns it or is its delegate
+    async function tferAccounts(
+      dest: PublicKey,
+      relock: boolean,
+      lockDest = "lock",
+      source = tokenAccount
+    ) {
+      let unlockSource = await resolveRemainingAccounts(
+        program.provider,
+        "unlock",
+        {
+          token: source,
+          mint,
+          delegate: payer,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          permProgram: program.programId,
+        }
+      );
+      let lockCtx = {
+        mint,
+        delegate: payer,
+        payer,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        permProgram: program.programId,
+      };
+      let lockDestAccounts = await resolveRemainingAccounts(
+        program.provider,
+        lockDest,
+        { ...lockCtx, token: dest }
+      );
+      let lockSource = await resolveRemainingAccounts(
+        program.provider,
+        "lock",
+        { ...lockCtx, token: source }
+      );
+      return partitionRemainingAccounts([
+        unlockSource.accounts,
+        lockDestAccounts.accounts,
+        relock ? lockSource.accounts : [],
+      ]);
+    }
+
+    function tfer(
+      dest: PublicKey,
+      amount: number,
+      lens: number[],
+      accounts: AccountMeta[],
+      source = tokenAccount
+    ) {
+      return caller.methods
+        .tfer(new anchor.BN(amount), lens, false, true)
+        .accounts({
+          source,
+          dest,
+          mint,
+          delegate: payer,
+          payer,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          permProgram: program.programId,
+        })
+        .remainingAccounts(accounts)
+        .rpc({ skipPreflight: true });
+    }
+
+    it("Rejects partitions that don't cover the remaining accounts", async () => {
+      let { lens, accounts } = await tferAccounts(randoToken, true);
+      await expectFailure(
+        tfer(randoToken, 1, [lens[0], lens[1], lens[2] + 1], accounts),
+        "transfer with mismatched account partitions"
+      );
+    });
+    it("Partial transfer relocks the source", async () => {
+      let { lens, accounts } = await tferAccounts(randoToken, true);
+      console.log(
+        "\tTransferred with lock: ",
+        await tfer(randoToken, 1, lens, accounts)
+      );
+
+      let source = await getAccount(program.provider.connection, tokenAccount);
+      let dest = await getAccount(program.provider.connection, randoToken);
+      assert.isTrue(source.isFrozen);
+      assert.isTrue(dest.isFrozen);
+      assert.equal(source.amount.toString(), "2");
+      assert.equal(dest.amount.toString(), "1");
+    });
+    it("Rejects a recipient that did not opt in", async () => {
+      let { lens, accounts } = await tferAccounts(
+        strangerToken,
+        true,
+        "lock_on_receive"
+      );
+      await expectError(
+        tfer(strangerToken, 1, lens, accounts),
+        program,
+        "ReceiverNotOptedIn",
+        "transfer to a recipient that did not opt in"
+      );
+
+      let dest = await getAccount(program.provider.connection, strangerToken);
+      assert.isFalse(dest.isFrozen);
+      assert.equal(dest.amount.toString(), "0");
+    });
+    it("Locks a recipient that opted in", async () => {
+      console.log(
+        "\tStranger opted in",
+        await program.methods
+          .optInToReceive(payer)
+          .accounts({
+            token: strangerToken,
+            owner: strangerKp.publicKey,
+            systemProgram: SystemProgram.programId,
+          })
+          .signers([strangerKp])
+          .rpc()
+      );
+
+      let { lens, accounts } = await tferAccounts(
+        strangerToken,
+        true,
+        "lock_on_receive"
+      );
+      console.log(
+        "\tTransferred to stranger with lock: ",
+        await tfer(strangerToken, 1, lens, accounts)
+      );
+
+      let source = await getAccount(program.provider.connection, tokenAccount);
+      let dest = await getAccount(program.provider.connection, strangerToken);
+      assert.isTrue(source.isFrozen);
+      assert.isTrue(dest.isFrozen);
+      assert.equal(source.amount.toString(), "1");
+      assert.equal(dest.amount.toString(), "1");
+    });
+    it("Full transfer leaves the empty source unlocked", async () => {
+      console.log(
+        "\tUnlocked dest",
+        await callUnlock(caller, program, randoToken, mint, null)
+      );
+
+      let { lens, accounts } = await tferAccounts(randoToken, false);
+      console.log(
+        "\tTransferred with lock: ",
+        await tfer(randoToken, 1, lens, accounts)
+      );
+
+      let source = await getAccount(program.provider.connection, tokenAccount);
+      let dest = await getAccount(program.provider.connection, randoToken);
+      assert.isFalse(source.isFrozen);
+      assert.isTrue(dest.isFrozen);
+      assert.equal(source.amount.toString(), "0");
+      assert.equal(dest.amount.toString(), "2");
+    });
+    it("Leaves the source unlocked once the delegate's allowance is used up", async () => {
+      // the payer is the SPL delegate of `ownerToken` for exactly the amount it transfers
+      let ownerKp = Keypair.generate();
+      let ownerToken = getAssociatedTokenAddressSync(mint, ownerKp.publicKey);
+      await program.provider.sendAndConfirm(
+        new Transaction().add(
+          createAssociatedTokenAccountInstruction(
+            payer,
+            ownerToken,
+            ownerKp.publicKey,
+            mint,
+            TOKEN_PROGRAM_ID,
+            ASSOCIATED_TOKEN_PROGRAM_ID
+          ),
+          createMintToInstruction(mint, ownerToken, payer, 2, [], TOKEN_PROGRAM_ID),
+          createApproveInstruction(ownerToken, payer, ownerKp.publicKey, 1)
+        ),
+        [ownerKp]
+      );
+      console.log(
+        "\tLocked as SPL delegate",
+        await callLock(caller, program, ownerToken, mint, null)
+      );
+
+      let { lens, accounts } = await tferAccounts(
+        tokenAccount,
+        true,
+        "lock",
+        ownerToken
+      );
+      console.log(
+        "\tTransferred the whole allowance: ",
+        await tfer(tokenAccount, 1, lens, accounts, ownerToken)
+      );
+
+      let source = await getAccount(program.provider.connection, ownerToken);
+      let dest = await getAccount(program.provider.connection, tokenAccount);
+      assert.isNull(source.delegate);
+      assert.isFalse(source.isFrozen);
+      assert.isTrue(dest.isFrozen);
+      assert.equal(source.amount.toString(), "1");
+      assert.equal(dest.amount.toString(), "1");
+    });
+  });
+  describe("permissioned token escrow through caller authority", () => {
+    const program = anchor.workspace
+      .PermissionedTokenWrapper as Program<PermissionedTokenWrapper>;
+
+    let programControl: PublicKey = PublicKey.findProgramAddressSync(
+      [Buffer.from("static")],
+      program.programId
+    )[0];
+
+    let payer: PublicKey = program.provider.publicKey!;
+    const decimals = 9;
+    let mint: PublicKey;
+    let tokenAccount: PublicKey;
+
+    // caller PDA the owner approves, operated by the payer
+    let authority: PublicKey = PublicKey.findProgramAddressSync(
+      [Buffer.from("authority"), payer.toBuffer()],
+      caller.programId
+    )[0];
+
+    // Buyer that approved the caller authority on its account
+    let buyerKp = Keypair.generate();
+    let buyerToken: PublicKey;
+
+    before(async () => {
+      let mintKp = Keypair.generate();
+      mint = mintKp.publicKey;
+
+      tokenAccount = getAssociatedTokenAddressSync(mint, payer);
+      buyerToken = getAssociatedTokenAddressSync(mint, buyerKp.publicKey);
+
+      let lamports =
+        await program.provider.connection.getMinimumBalanceForRentExemption(
+          MINT_SIZE,
+          "confirmed"
+        );
+      const transaction = new Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: payer,
+          newAccountPubkey: mint,
+          space: MINT_SIZE,
+          lamports,
+          programId: TOKEN_PROGRAM_ID,
+        }),
+        createInitializeMint2Instruction(
+          mint,
+          decimals,
+          payer,
+          programControl,
+          TOKEN_PROGRAM_ID
+        ),
+        createAssociatedTokenAccountInstruction(
+          payer,
+          tokenAccount,
+          payer,
+          mint,
+          TOKEN_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        ),
+        createAssociatedTokenAccountInstruction(
+          payer,
+          buyerToken,
+          buyerKp.publicKey,
+          mint,
+          TOKEN_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        ),
+        createMintToInstruction(
+          mint,
+          tokenAccount,
+          payer,
+          3,
+          [],
+          TOKEN_PROGRAM_ID
+        ),
+        createApproveInstruction(tokenAccount, authority, payer, 3),
+        // the buyer opts in to receiving locked tokens from the escrow
+        createApproveInstruction(buyerToken, authority, buyerKp.publicKey, 0)
+      );
+
+      let txid = await program.provider.sendAndConfirm(transaction, [mintKp, buyerKp], {
+        skipPreflight: true,
+        preflightCommitment: "confirmed",
+      });
+      console.log("\tCreated new mint with txid: ", txid);
+    });
+    it("Caller authority can lock the approved account", async () => {
+      const builder = caller.methods.lock(null, true, true).accounts({
+        token: tokenAccount,
+        mint,
+        delegate: authority,
+        payer,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        permProgram: program.programId,
+      });
+      let { accounts: remainingAccounts } = await resolveRemainingAccounts(
+        program.provider,
+        "lock",
+        await builder.pubkeys()
+      );
+      let txid = await builder
+        .remainingAccounts(remainingAccounts)
+        .rpc({ skipPreflight: true });
+      console.log("\tLocked as caller authority", txid);
+
+      await expectFailure(
+        callUnlock(caller, program, tokenAccount, mint, null),
+        "unlock an escrowed token as the owner"
+      );
+    });
+    it("Caller authority can transfer out of escrow", async () => {
+      let lockCtx = {
+        mint,
+        delegate: authority,
+        payer,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        permProgram: program.programId,
+      };
+      let unlockSource = await resolveRemainingAccounts(
+        program.provider,
+        "unlock",
+        { ...lockCtx, token: tokenAccount }
+      );
+      let lockDest = await resolveRemainingAccounts(program.provider, "lock", {
+        ...lockCtx,
+        token: buyerToken,
+      });
+      let lockSource = await resolveRemainingAccounts(
+        program.provider,
+        "lock",
+        { ...lockCtx, token: tokenAccount }
+      );
+      let { lens, accounts } = partitionRemainingAccounts([
+        unlockSource.accounts,
+        lockDest.accounts,
+        lockSource.accounts,
+      ]);
+
+      let txid = await caller.methods
+        .tfer(new anchor.BN(1), lens, true, true)
+        .accounts({
+          source: tokenAccount,
+          dest: buyerToken,
+          mint,
+          delegate: authority,
+          payer,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          permProgram: program.programId,
+        })
+        .remainingAccounts(accounts)
+        .rpc({ skipPreflight: true });
+      console.log("\tTransferred as caller authority", txid);
+
+      let source = await getAccount(program.provider.connection, tokenAccount);
+      let dest = await getAccount(program.provider.connection, buyerToken);
+      assert.isTrue(source.isFrozen);
+      assert.isTrue(dest.isFrozen);
+      assert.equal(source.amount.toString(), "2");
+      assert.equal(dest.amount.toString(), "1");
+    });
+    it("Caller authority can release the escrow", async () => {
+      const builder = caller.methods.unlock(null, true).accounts({
+        token: tokenAccount,
+        mint,
+        delegate: authority,
+        operator: payer,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        permProgram: program.programId,
+      });
+      let { accounts: remainingAccounts } = await resolveRemainingAccounts(
+        program.provider,
+        "unlock",
+        await builder.pubkeys()
+      );
+      let txid = await builder
+        .remainingAccounts(remainingAccounts)
+        .rpc({ skipPreflight: true });
+      console.log("\tUnlocked as caller authority", txid);
+
+      let source = await getAccount(program.provider.connection, tokenAccount);
+      assert.isFalse(source.isFrozen);
+    });
+  });
+  describe("Twice permissioned token", () => {
+    const program = anchor.workspace
+      .TwicePermissioned as Program<TwicePermissioned>;
+
+    let programControl: PublicKey = PublicKey.findProgramAddressSync(
+      [Buffer.from("static")],
+      program.programId
+    )[0];
+
+    let payer: PublicKey = program.provider.publicKey!;
+    const decimals = 9;
+    let mint: PublicKey;
+    let tokenAccount: PublicKey;
+    let tokenRecord: PublicKey;
+
+    // Second locker, each delegate only counts once towards the threshold
+    let lenderKp = Keypair.generate();
+
+    let randomKp = Keypair.generate();
+    let randomPerson = randomKp.publicKey;
+    let randoToken: PublicKey;
+
+    before(async () => {
+      let mintKp = Keypair.generate();
+      mint = mintKp.publicKey;
+
+      tokenAccount = getAssociatedTokenAddressSync(mint, payer);
+
+      let lamports =
+        await program.provider.connection.getMinimumBalanceForRentExemption(
+          MINT_SIZE,
+          "confirmed"
+        );
+      const transaction = new Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: payer,
+          newAccountPubkey: mint,
+          space: MINT_SIZE,
+          lamports,
+          programId: TOKEN_PROGRAM_ID,
+        }),
+        createInitializeMint2Instruction(
+          mint,
+          decimals,
+          payer,
+          programControl,
+          TOKEN_PROGRAM_ID
+        ),
+        createAssociatedTokenAccountInstruction(
+          payer,
+          tokenAccount,
+          payer,
+          mint,
+          TOKEN_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        ),
+        createMintToInstruction(
+          mint,
+          tokenAccount,
+          payer,
+          1,
+          [],
+          TOKEN_PROGRAM_ID
+        ),
+        createApproveInstruction(tokenAccount, lenderKp.publicKey, payer, 1)
+      );
+
+      let txid = await program.provider.sendAndConfirm(transaction, [mintKp], {
+        skipPreflight: true,
+        preflightCommitment: "confirmed",
+      });
+      console.log("\tCreated new mint with txid: ", txid);
+    });
+    it("Lock expecting a freeze fails below the threshold", async () => {
+      const builder = caller.methods.lock(null, false, true).accounts({
+        token: tokenAccount,
+        mint,
+        delegate: payer,
+        payer,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        permProgram: program.programId,
+      });
+      let keys = await builder.pubkeys();
+      let { accounts: remainingAccounts } = await resolveRemainingAccounts(
+        program.provider,
+        "lock",
+        keys
+      );
+      await expectFailure(
+        builder.remainingAccounts(remainingAccounts).rpc({ skipPreflight: true }),
+        "lock without freezing when a freeze is expected"
+      );
+      let account = await getAccount(program.provider.connection, tokenAccount);
+      assert.isFalse(account.isFrozen);
+    });
+    it("Can lock user token account", async () => {
+      tokenRecord = PublicKey.findProgramAddressSync(
+        [tokenAccount.toBuffer(), Buffer.from("token_record")],
+        program.programId
+      )[0];
+
+      let lockCtx: LockContext = {
+        token: tokenAccount,
+        mint,
+        delegate: program.provider.publicKey!,
+        payer: program.provider.publicKey!,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        permProgram: program.programId,
+      };
+
+      const builder = caller.methods.lock(null, false, false).accounts(lockCtx);
+      let keys = await builder.pubkeys();
+      let { accounts: remainingAccounts } = await resolveRemainingAccounts(
+        program.provider,
+        "lock",
+        keys
+      );
+      const transaction = await builder
+        .remainingAccounts(remainingAccounts)
+        .transaction();
+
+      let tx = await program.provider.sendAndConfirm(transaction, [], {
+        skipPreflight: true,
+      });
+      await expectFailure(
+        callLock(caller, program, tokenAccount, mint, null),
+        "lock twice with the same delegate"
+      );
+      tx = await callLock(caller, program, tokenAccount, mint, lenderKp);
+      console.log("\tLocked", tx);
+    });
+    it("Cannot transfer locked token", async () => {
+      randoToken = getAssociatedTokenAddressSync(mint, randomPerson);
+
+      let transaction = new Transaction().add(
+        createAssociatedTokenAccountInstruction(
+          payer,
+          randoToken,
+          randomPerson,
+          mint,
+          TOKEN_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        ),
+        createTransferInstruction(tokenAccount, randoToken, payer, 1, [])
+      );
+
+      try {
+        let txid = await program.provider.sendAndConfirm(transaction, [], {
+          skipPreflight: true,
+          preflightCommitment: "confirmed",
+        });
+        throw Error("Should not be able to transfer locked token");
+      } catch (e) {
+        console.log("\tSuccessfully failed to transfer locked token:", e);
+      }
+    });
+    it("Can unlock user token account", async () => {
+      const builder = caller.methods.unlock(null, false).accounts({
+        token: tokenAccount,
+        mint,
+        delegate: program.provider.publicKey!,
+        operator: program.provider.publicKey!,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        permProgram: program.programId,
+      });
+      let keys = await builder.pubkeys();
+      let { accounts: remainingAccounts } = await resolveRemainingAccounts(
+        program.provider,
+        "unlock",
+        keys
+      );
+      const transaction = await builder
+        .remainingAccounts(remainingAccounts)
+        .transaction();
+      let tx = await program.provider.sendAndConfirm(transaction, [], {
+        skipPreflight: true,
+      });
+      await expectFailure(
+        callUnlock(caller, program, tokenAccount, mint, null),
+        "unlock another delegate's lock"
+      );
+      tx = await callUnlock(caller, program, tokenAccount, mint, lenderKp);
+      console.log("\tUnlocked", tx);
+    });
+
+    it("Can transfer unlocked token", async () => {
+      let transaction = new Transaction().add(
+        createAssociatedTokenAccountInstruction(
+          payer,
+          randoToken,
+          randomPerson,
+          mint,
+          TOKEN_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        ),
+        createTransferInstruction(tokenAccount, randoToken, payer, 1, [])
+      );
+
+      let txid = await program.provider.sendAndConfirm(transaction, [], {
+        skipPreflight: true,
+        preflightCommitment: "confirmed",
+      });
+      console.log("\tTransferred token to normie: ", txid);
+    });
+  });
+  describe("Twice permissioned token with configured threshold", () => {
+    const program = anchor.workspace
+      .TwicePermissioned as Program<TwicePermissioned>;
+
+    let programControl: PublicKey = PublicKey.findProgramAddressSync(
+      [Buffer.from("static")],
+      program.programId
+    )[0];
+
+    let payer: PublicKey = program.provider.publicKey!;
+    const decimals = 9;
+    let mint: PublicKey;
+    let tokenAccount: PublicKey;
+    let tokenRecord: PublicKey;
+    let lockConfig: PublicKey;
+
+    before(async () => {
+      let mintKp = Keypair.generate();
+      mint = mintKp.publicKey;
+
+      tokenAccount = getAssociatedTokenAddressSync(mint, payer);
+      tokenRecord = PublicKey.findProgramAddressSync(
+        [tokenAccount.toBuffer(), Buffer.from("token_record")],
+        program.programId
+      )[0];
+      lockConfig = PublicKey.findProgramAddressSync(
+        [mint.toBuffer(), Buffer.from("lock_config")],
+        program.programId
+      )[0];
+
+      let lamports =
+        await program.provider.connection.getMinimumBalanceForRentExemption(
+          MINT_SIZE,
+          "confirmed"
+        );
+      const transaction = new Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: payer,
+          newAccountPubkey: mint,
+          space: MINT_SIZE,
+          lamports,
+          programId: TOKEN_PROGRAM_ID,
+        }),
+        createInitializeMint2Instruction(
+          mint,
+          decimals,
+          payer,
+          programControl,
+          TOKEN_PROGRAM_ID
+        ),
+        createAssociatedTokenAccountInstruction(
+          payer,
+          tokenAccount,
+          payer,
+          mint,
+          TOKEN_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        ),
+        createMintToInstruction(
+          mint,
+          tokenAccount,
+          payer,
+          1,
+          [],
+          TOKEN_PROGRAM_ID
+        )
+      );
+
+      let txid = await program.provider.sendAndConfirm(transaction, [mintKp], {
+        skipPreflight: true,
+        preflightCommitment: "confirmed",
+      });
+      console.log("\tCreated new mint with txid: ", txid);
+
+      txid = await program.methods
+        .initLockConfig(payer, new anchor.BN(1))
+        .accounts({
+          mint,
+          mintAuthority: payer,
+          payer,
+          lockConfig,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      console.log("\tSet lock threshold to 1: ", txid);
+    });
+    it("Single lock freezes with a threshold of 1", async () => {
+      await callLock(caller, program, tokenAccount, mint, null);
+      let record = await program.account.tokenRecord.fetch(tokenRecord);
+      assert.equal(record.locked, 1);
+      assert.equal(record.lockers.length, 1);
+    });
+    it("Single unlock thaws with a threshold of 1", async () => {
+      await callUnlock(caller, program, tokenAccount, mint, null);
+      let record = await program.account.tokenRecord.fetch(tokenRecord);
+      assert.equal(record.locked, 0);
+      assert.equal(record.lockers.length, 0);
+    });
+    it("Repeated lock with the same nonce is a no-op", async () => {
+      const nonce = new anchor.BN(7);
+      let ixs: TransactionInstruction[] = [];
+      for (let i = 0; i < 2; i++) {
+        const builder = caller.methods.lock(nonce, false, false).accounts({
+          token: tokenAccount,
+          mint,
+          delegate: payer,
+          payer,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          permProgram: program.programId,
+        });
+        let keys = await builder.pubkeys();
+        let { accounts: remainingAccounts } = await resolveRemainingAccounts(
+          program.provider,
+          "lock",
+          keys
+        );
+        ixs.push(
+          await builder.remainingAccounts(remainingAccounts).instruction()
+        );
+      }
+      let tx = await program.provider.sendAndConfirm(
+        new Transaction().add(...ixs),
+        [],
+        { skipPreflight: true }
+      );
+      console.log("\tLocked twice with one nonce", tx);
+
+      let record = await program.account.tokenRecord.fetch(tokenRecord);
+      assert.equal(record.lockers.length, 1);
+    });
+    it("Unlock with the nonce of a lock still unlocks", async () => {
+      await callUnlock(
+        caller,
+        program,
+        tokenAccount,
+        mint,
+        null,
+        new anchor.BN(7)
+      );
+
+      let record = await program.account.tokenRecord.fetch(tokenRecord);
+      assert.equal(record.locked, 0);
+      assert.equal(record.lockers.length, 0);
+    });
+    it("Cannot unlock below zero", async () => {
+      await expectFailure(
+        callUnlock(caller, program, tokenAccount, mint, null),
+        "unlock a token with no locks"
+      );
+    });
+  });
+  describe("Permission chain", () => {
+    const chain = anchor.workspace.PermissionChain as Program<PermissionChain>;
+    // single downstream program that holds the freeze authority
+    const program = anchor.workspace
+      .PermissionedTokenWrapper as Program<PermissionedTokenWrapper>;
+
+    let programControl: PublicKey = PublicKey.findProgramAddressSync(
+      [Buffer.from("static")],
+      program.programId
+    )[0];
+
+    let payer: PublicKey = program.provider.publicKey!;
+    const decimals = 9;
+    let mint: PublicKey;
+    let tokenAccount: PublicKey;
+    let chainConfig: PublicKey;
+
+    let randomKp = Keypair.generate();
+    let randomPerson = randomKp.publicKey;
+    let randoToken: PublicKey;
+
+    // chain config first, then every downstream program followed by its own accounts
+    async function chainRemainingAccounts(
+      instructionName: string,
+      keys: AccountsGeneric
+    ): Promise<AccountMeta[]> {
+      let { accounts: downstreamAccounts } = await resolveRemainingAccounts(
+        program.provider,
+        instructionName,
+        { ...keys, permProgram: program.programId }
+      );
+      return [
+        { pubkey: chainConfig, isSigner: false, isWritable: false },
+        { pubkey: program.programId, isSigner: false, isWritable: false },
+        ...downstreamAccounts,
+      ];
+    }
+
+    async function transferToRando() {
+      let transaction = new Transaction().add(
+        createTransferInstruction(tokenAccount, randoToken, payer, 1, [])
+      );
+      return await program.provider.sendAndConfirm(transaction, [], {
+        skipPreflight: true,
+        commitment: "confirmed",
+      });
+    }
+
+    before(async () => {
+      let mintKp = Keypair.generate();
+      mint = mintKp.publicKey;
+
+      tokenAccount = getAssociatedTokenAddressSync(mint, payer);
+      randoToken = getAssociatedTokenAddressSync(mint, randomPerson);
+      chainConfig = PublicKey.findProgramAddressSync(
+        [mint.toBuffer(), Buffer.from("chain_config")],
+        chain.programId
+      )[0];
+
+      let lamports =
+        await program.provider.connection.getMinimumBalanceForRentExemption(
+          MINT_SIZE,
+          "confirmed"
+        );
+      const transaction = new Transaction().add(
+        SystemProgram.createAccount({
+          fromPubkey: payer,
+          newAccountPubkey: mint,
+          space: MINT_SIZE,
+          lamports,
+          programId: TOKEN_PROGRAM_ID,
+        }),
+        createInitializeMint2Instruction(
+          mint,
+          decimals,
+          payer,
+          programControl,
+          TOKEN_PROGRAM_ID
+        ),
+        createAssociatedTokenAccountInstruction(
+          payer,
+          tokenAccount,
+          payer,
+          mint,
+          TOKEN_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        ),
+        createAssociatedTokenAccountInstruction(
+          payer,
+          randoToken,
+          randomPerson,
+          mint,
+          TOKEN_PROGRAM_ID,
+          ASSOCIATED_TOKEN_PROGRAM_ID
+        ),
+        createMintToInstruction(
+          mint,
+          tokenAccount,
+          payer,
+          1,
+          [],
+          TOKEN_PROGRAM_ID
+        )
+      );
+
+      let txid = await program.provider.sendAndConfirm(transaction, [mintKp], {
+        skipPreflight: true,
+        preflightCommitment: "confirmed",
+      });
+      console.log("\tCreated new mint with txid: ", txid);
+
+      txid = await chain.methods
+        .initChainConfig(payer, [program.programId])
+        .accounts({
+          mint,
+          mintAuthority: payer,
+          payer,
+          chainConfig,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      console.log("\tCreated chain config: ", txid);
+    });
+    it("Can lock through the chain", async () => {
+      const builder = caller.methods.lock(null, false, true).accounts({
+        token: tokenAccount,
+        mint,
+        delegate: payer,
+        payer,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        permProgram: chain.programId,
+      });
+      let remainingAccounts = await chainRemainingAccounts(
+        "lock",
+        await builder.pubkeys()
+      );
+      let txid = await builder
+        .remainingAccounts(remainingAccounts)
+        .rpc({ skipPreflight: true });
+      console.log("\tLocked through chain", txid);
+
+      await expectFailure(transferToRando(), "transfer a chain locked token");
+    });
+    it("Can unlock through the chain", async () => {
+      const builder = caller.methods.unlock(null, false).accounts({
+        token: tokenAccount,
+        mint,
+        delegate: payer,
+        operator: payer,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        permProgram: chain.programId,
+      });
+      let remainingAccounts = await chainRemainingAccounts(
+        "unlock",
+        await builder.pubkeys()
+      );
+      let txid = await builder
+        .remainingAccounts(remainingAccounts)
+        .rpc({ skipPreflight: true });
+      console.log("\tUnlocked through chain", txid);
+
+      txid = await transferToRando();
       console.log("\tTransferred token to normie: ", txid);
     });
   });